@@ -0,0 +1,281 @@
+// Parser for the ATerm format in which Nix stores the derivations (`.drv` files).
+// A derivation looks like this:
+//   Derive([("out","/nix/store/...-hello","","")],[("/nix/store/...-bash.drv",["out"])],
+//          ["/nix/store/...-builder.sh"],"x86_64-linux","/nix/store/...-bash/bin/bash",
+//          ["-e","builder.sh"],[("name","hello"),("out","/nix/store/...-hello")])
+use std::collections::{BTreeSet, HashMap};
+use std::path::Path;
+
+const DERIVATION_PREFIX: &str = "Derive(";
+const DERIVATION_EXTENSION: &str = ".drv";
+
+// Reads the derivation at the given path, and then all its input derivations recursively.
+// The paths of the derivations are relative to the store root, which is `/` for the
+// local Nix store, but can point to a copy of a store or a mounted image.
+pub fn read_derivations(derivation_path: &str, store_root: &str) -> Result<crate::nix::Derivations, String> {
+    let mut derivations = crate::nix::Derivations::default();
+    let mut derivation_paths: BTreeSet<String> = BTreeSet::default();
+    derivation_paths.insert(derivation_path.to_string());
+
+    while let Some(derivation_path) = derivation_paths.pop_first() {
+        if derivations.contains_key(&derivation_path) {
+            continue;
+        }
+        if !derivation_path.ends_with(DERIVATION_EXTENSION) {
+            return Err(format!("{} is not a derivation", &derivation_path));
+        }
+
+        let file_path = Path::new(store_root).join(derivation_path.trim_start_matches('/'));
+        let content = match std::fs::read_to_string(&file_path) {
+            Ok(c) => c,
+            Err(e) => return Err(format!("Could not read {}: {}", file_path.display(), e)),
+        };
+        let derivation = match parse_derivation(&content) {
+            Ok(d) => d,
+            Err(e) => return Err(format!("Could not parse {}: {}", &derivation_path, e)),
+        };
+
+        for input_derivation_path in derivation.input_derivations.keys() {
+            if !derivations.contains_key(input_derivation_path) {
+                derivation_paths.insert(input_derivation_path.to_string());
+            }
+        }
+        derivations.insert(derivation_path, derivation);
+    }
+
+    Ok(derivations)
+}
+
+pub fn parse_derivation(content: &str) -> Result<crate::nix::Derivation, String> {
+    let mut parser = Parser {
+        content: content.trim_end().as_bytes(),
+        position: 0,
+    };
+    parser.expect_str(DERIVATION_PREFIX)?;
+
+    let mut outputs: HashMap<String, crate::nix::Output> = HashMap::default();
+    parser.parse_list(|parser| {
+        parser.expect(b'(')?;
+        let output_name = parser.parse_string()?;
+        parser.expect(b',')?;
+        let path = parser.parse_string()?;
+        // The hash algorithm and the hash are only set for fixed-output derivations,
+        // and are also available in the environment of the derivation.
+        parser.expect(b',')?;
+        parser.parse_string()?;
+        parser.expect(b',')?;
+        parser.parse_string()?;
+        parser.expect(b')')?;
+        outputs.insert(output_name, crate::nix::Output { path });
+        Ok(())
+    })?;
+    parser.expect(b',')?;
+
+    let mut input_derivations: HashMap<String, crate::nix::InputDerivation> = HashMap::default();
+    parser.parse_list(|parser| {
+        parser.expect(b'(')?;
+        let input_derivation_path = parser.parse_string()?;
+        parser.expect(b',')?;
+        let input_outputs = parser.parse_string_list()?;
+        parser.expect(b')')?;
+        input_derivations.insert(
+            input_derivation_path,
+            crate::nix::InputDerivation::List(input_outputs),
+        );
+        Ok(())
+    })?;
+    parser.expect(b',')?;
+
+    let inputs_sources = parser.parse_string_list()?;
+    parser.expect(b',')?;
+    let system = parser.parse_string()?;
+    parser.expect(b',')?;
+    let builder = parser.parse_string()?;
+    parser.expect(b',')?;
+    let args = parser.parse_string_list()?;
+    parser.expect(b',')?;
+
+    let mut env: HashMap<String, String> = HashMap::default();
+    parser.parse_list(|parser| {
+        parser.expect(b'(')?;
+        let name = parser.parse_string()?;
+        parser.expect(b',')?;
+        let value = parser.parse_string()?;
+        parser.expect(b')')?;
+        env.insert(name, value);
+        Ok(())
+    })?;
+    parser.expect(b')')?;
+
+    if parser.position != parser.content.len() {
+        return Err(format!("Unexpected content at position {}", parser.position));
+    }
+
+    Ok(crate::nix::Derivation {
+        outputs,
+        inputs_sources,
+        input_derivations,
+        system,
+        builder,
+        args,
+        env,
+        extra: HashMap::default(),
+    })
+}
+
+struct Parser<'a> {
+    content: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn expect(&mut self, expected: u8) -> Result<(), String> {
+        match self.content.get(self.position) {
+            Some(c) if *c == expected => {
+                self.position += 1;
+                Ok(())
+            }
+            Some(c) => Err(format!(
+                "Expected '{}' but found '{}' at position {}",
+                expected as char, *c as char, self.position
+            )),
+            None => Err(format!(
+                "Expected '{}' but reached the end of the input",
+                expected as char
+            )),
+        }
+    }
+
+    fn expect_str(&mut self, expected: &str) -> Result<(), String> {
+        if !self.content[self.position..].starts_with(expected.as_bytes()) {
+            return Err(format!("Expected {} at position {}", expected, self.position));
+        }
+        self.position += expected.len();
+        Ok(())
+    }
+
+    fn parse_string(&mut self) -> Result<String, String> {
+        self.expect(b'"')?;
+        let mut value: Vec<u8> = vec![];
+        loop {
+            let c = match self.content.get(self.position) {
+                Some(c) => *c,
+                None => return Err("Unterminated string".to_string()),
+            };
+            self.position += 1;
+            match c {
+                b'"' => break,
+                b'\\' => {
+                    let escaped = match self.content.get(self.position) {
+                        Some(c) => *c,
+                        None => return Err("Unterminated string".to_string()),
+                    };
+                    self.position += 1;
+                    value.push(match escaped {
+                        b'n' => b'\n',
+                        b'r' => b'\r',
+                        b't' => b'\t',
+                        other => other,
+                    });
+                }
+                other => value.push(other),
+            }
+        }
+        String::from_utf8(value).map_err(|e| e.to_string())
+    }
+
+    fn parse_list<F>(&mut self, mut parse_item: F) -> Result<(), String>
+    where
+        F: FnMut(&mut Parser<'a>) -> Result<(), String>,
+    {
+        self.expect(b'[')?;
+        if self.content.get(self.position) == Some(&b']') {
+            self.position += 1;
+            return Ok(());
+        }
+        loop {
+            parse_item(self)?;
+            match self.content.get(self.position) {
+                Some(b',') => self.position += 1,
+                Some(b']') => {
+                    self.position += 1;
+                    return Ok(());
+                }
+                _ => return Err(format!("Unterminated list at position {}", self.position)),
+            }
+        }
+    }
+
+    fn parse_string_list(&mut self) -> Result<Vec<String>, String> {
+        let mut values: Vec<String> = vec![];
+        self.parse_list(|parser| {
+            values.push(parser.parse_string()?);
+            Ok(())
+        })?;
+        Ok(values)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GETTEXT_DERIVATION: &str = r###"Derive([("out","/nix/store/bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb-gettext-0.21.1","","")],[],["/nix/store/ffffffffffffffffffffffffffffffff-default-builder.sh"],"x86_64-linux","/nix/store/0rwyq0j954a7143p0wzd4rhycny8i967-bash-5.2-p15/bin/bash",["-e","/nix/store/ffffffffffffffffffffffffffffffff-default-builder.sh"],[("name","gettext-0.21.1"),("pname","gettext"),("postPatch","substituteInPlace \"a\\b\"\nexit 0"),("version","0.21.1")])"###;
+
+    const HELLO_DERIVATION: &str = r###"Derive([("out","/nix/store/eeeeeeeeeeeeeeeeeeeeeeeeeeeeeeee-hello-2.12.1","","")],[("/nix/store/bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb-gettext-0.21.1.drv",["out"])],[],"x86_64-linux","/nix/store/0rwyq0j954a7143p0wzd4rhycny8i967-bash-5.2-p15/bin/bash",[],[("name","hello-2.12.1"),("nativeBuildInputs","/nix/store/bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb-gettext-0.21.1")])"###;
+
+    #[test]
+    pub fn test_parse_derivation() {
+        let derivation = parse_derivation(GETTEXT_DERIVATION).unwrap();
+        assert_eq!(derivation.system, "x86_64-linux");
+        assert_eq!(derivation.get_builder(), crate::nix::DerivationBuilder::Bash);
+        assert_eq!(derivation.args.len(), 2);
+        assert_eq!(derivation.inputs_sources.len(), 1);
+        assert_eq!(derivation.input_derivations.len(), 0);
+        assert_eq!(
+            derivation.outputs.get("out").unwrap().path,
+            "/nix/store/bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb-gettext-0.21.1"
+        );
+        assert_eq!(derivation.get_name(), Some("gettext".to_string()));
+        assert_eq!(
+            derivation.env.get("postPatch").unwrap(),
+            "substituteInPlace \"a\\b\"\nexit 0"
+        );
+    }
+
+    #[test]
+    pub fn test_parse_invalid_derivation() {
+        assert!(parse_derivation("").is_err());
+        assert!(parse_derivation("Derive([],[],[],\"x86_64-linux\"").is_err());
+        assert!(parse_derivation(&format!("{}garbage", GETTEXT_DERIVATION)).is_err());
+    }
+
+    #[test]
+    pub fn test_read_derivations() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let store_root = temp_dir.path().join("read-derivations-store");
+        let store_dir = store_root.join("nix/store");
+        std::fs::create_dir_all(&store_dir).unwrap();
+        std::fs::write(
+            store_dir.join("bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb-gettext-0.21.1.drv"),
+            GETTEXT_DERIVATION,
+        )
+        .unwrap();
+        std::fs::write(
+            store_dir.join("aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa-hello-2.12.1.drv"),
+            HELLO_DERIVATION,
+        )
+        .unwrap();
+
+        let derivations = read_derivations(
+            "/nix/store/aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa-hello-2.12.1.drv",
+            store_root.to_str().unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(derivations.len(), 2);
+        assert!(derivations.contains_key("/nix/store/bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb-gettext-0.21.1.drv"));
+        let package_graph = crate::nix::get_package_graph_next(&derivations, &crate::nix::Packages::default());
+        assert_eq!(package_graph.nodes.len(), 2);
+    }
+}
@@ -28,33 +28,24 @@ fn main() -> Result<std::process::ExitCode, Box<dyn std::error::Error>> {
     // let package_graph = nix2sbom::nix::get_package_graph(&derivations, &packages);
     let package_graph = nix2sbom::nix::get_package_graph_next(&derivations, &packages);
 
-    let dump_options = nix2sbom::nix::DumpOptions::default();
+    let mut dump_options = nix2sbom::nix::DumpOptions::default();
+    // The SBOM is stored with the fixtures, so it needs a fixed timestamp to be reproducible.
+    dump_options.timestamp = nix2sbom::sbom::parse_timestamp("0");
 
     let package_graph_stats = package_graph.get_stats(&dump_options);
 
-    let mut required_packages = nix2sbom::nix::Packages::default();
-    for (_derivation_path, derivation) in derivations.iter() {
-        let derivation_name = match derivation.get_name() {
-            Some(n) => n,
-            None => continue,
-        };
-        if packages.contains_key(&derivation_name) {
-            required_packages.insert(
-                derivation_name.to_string(),
-                packages.get(&derivation_name).unwrap().clone(),
-            );
-        }
-    }
-    let packages = required_packages;
+    // Only the packages matched with a derivation are saved, so that the fixtures reproduce
+    // the same package graph.
+    let packages = nix2sbom::nix::get_matched_packages(&derivations, &packages);
 
-    let _sbom_dump = match nix2sbom::sbom::Format::CycloneDX.dump(
+    let sbom_dump = match nix2sbom::sbom::Format::CycloneDX.dump(
         &nix2sbom::sbom::SerializationFormat::JSON,
         &package_graph,
         &dump_options,
     ) {
         Ok(d) => d,
         Err(e) => {
-            eprintln!("{}", e.to_string());
+            eprintln!("{}", e);
             return Ok(std::process::ExitCode::FAILURE);
         }
     };
@@ -68,8 +59,7 @@ fn main() -> Result<std::process::ExitCode, Box<dyn std::error::Error>> {
     let package_graph_file_path = format!("{}/package-graph.json", target_dir);
     let package_graph_stats_file_path = format!("{}/package-graph-stats.json", target_dir);
     let derivations_file_path = format!("{}/derivations.json", target_dir);
-    // FIXME the sbom file is not deterministic yet, so we can't us it for the integration tests.
-    // let sbom_file_path = format!("{}/sbom.json", target_dir);
+    let sbom_file_path = format!("{}/sbom.json", target_dir);
 
     let mut packages_file = File::create(packages_file_path)?;
     packages_file.write_all(serde_json::to_string_pretty(&packages).unwrap().as_bytes())?;
@@ -87,8 +77,8 @@ fn main() -> Result<std::process::ExitCode, Box<dyn std::error::Error>> {
             .as_bytes(),
     )?;
 
-    // let mut sbom_file = File::create(sbom_file_path)?;
-    // sbom_file.write_all(sbom_dump.as_bytes());
+    let mut sbom_file = File::create(sbom_file_path)?;
+    sbom_file.write_all(sbom_dump.as_bytes())?;
 
     Ok(std::process::ExitCode::SUCCESS)
 }
@@ -0,0 +1,303 @@
+// Classification of the packages into the component types of CycloneDX. Scanners match
+// vulnerabilities differently for applications and libraries, so we try to be more
+// precise than using application for everything.
+use lazy_static::lazy_static;
+use regex::Regex;
+
+// Packages which applications are built upon, rather than simply linked against.
+const FRAMEWORK_NAMES: &[&str] = &[
+    "qtbase",
+    "qtdeclarative",
+    "gtk+",
+    "gtk+3",
+    "gtk3",
+    "gtk4",
+    "electron",
+    "flutter",
+    "dotnet-runtime",
+    "aspnetcore-runtime",
+];
+
+// Package URL types of the language registries, which distribute libraries.
+const LIBRARY_PURL_TYPES: &[&str] = &[
+    "cargo", "cpan", "gem", "golang", "hackage", "hex", "maven", "npm", "nuget", "pub", "pypi",
+];
+
+// Environment variables set by the language builders of nixpkgs which produce executables.
+const APPLICATION_BUILDER_VARIABLES: &[&str] = &["cargoDeps", "vendorHash", "npmDepsHash", "yarnOfflineCache"];
+
+// Environment variables set by the language builders of nixpkgs which produce modules.
+const LIBRARY_BUILDER_VARIABLES: &[&str] = &["gemName"];
+
+lazy_static! {
+    // Packages providing firmware blobs, loaded by the kernel or flashed on devices, like
+    // linux-firmware or microcode-intel. The patterns are anchored, since tools like
+    // firmware-updater or fwupd are applications.
+    static ref FIRMWARE_NAME_REGEX: Regex =
+        Regex::new(r"^(firmware|.+-firmware|microcode-.+|.+-microcode|.+-ucode)(-[0-9].*)?$").unwrap();
+
+    // Packages which only provide data used by other programs, and nothing executable. The
+    // patterns are anchored, since fontconfig, libXfont or fontforge are not fonts.
+    static ref DATA_NAME_REGEX: Regex = Regex::new(
+        concat!(
+            r"^(fonts?-.+|.+-fonts|.+-fonts-.+|.+-font|.+-icon-theme|.+-cursor-theme|.+-wallpapers?",
+            r"|tzdata|hwdata|cacert|mime-types)(-[0-9].*)?$",
+        )
+    )
+    .unwrap();
+
+    // Python and Perl modules are prefixed with the interpreter, like python3.11-requests-2.31.0
+    static ref INTERPRETER_MODULE_REGEX: Regex = Regex::new(r"^(python|perl|lua|php)[0-9.]*-").unwrap();
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(PartialEq)]
+pub enum ComponentType {
+    Application,
+    Framework,
+    Library,
+    Firmware,
+    File,
+    Data,
+}
+
+impl ComponentType {
+    // Name of the type in CycloneDX.
+    pub fn to_string(&self) -> String {
+        match self {
+            ComponentType::Application => "application".to_string(),
+            ComponentType::Framework => "framework".to_string(),
+            ComponentType::Library => "library".to_string(),
+            ComponentType::Firmware => "firmware".to_string(),
+            ComponentType::File => "file".to_string(),
+            ComponentType::Data => "data".to_string(),
+        }
+    }
+
+    // Value of the primaryPackagePurpose field in SPDX.
+    pub fn to_spdx_purpose(&self) -> String {
+        match self {
+            ComponentType::Application => "APPLICATION".to_string(),
+            ComponentType::Framework => "FRAMEWORK".to_string(),
+            ComponentType::Library => "LIBRARY".to_string(),
+            ComponentType::Firmware => "FIRMWARE".to_string(),
+            ComponentType::File => "FILE".to_string(),
+            ComponentType::Data => "OTHER".to_string(),
+        }
+    }
+}
+
+pub fn get_component_type(package_node: &crate::nix::PackageNode) -> ComponentType {
+    let derivation = &package_node.main_derivation;
+    // Scripts generated with writeText, writeScript and friends.
+    if derivation.is_inline_script() {
+        return ComponentType::File;
+    }
+
+    let name = package_node.get_name().unwrap_or("".to_string()).to_lowercase();
+    if FIRMWARE_NAME_REGEX.is_match(&name) {
+        return ComponentType::Firmware;
+    }
+    if DATA_NAME_REGEX.is_match(&name) {
+        return ComponentType::Data;
+    }
+    if FRAMEWORK_NAMES.contains(&name.as_str()) {
+        return ComponentType::Framework;
+    }
+
+    if let Some(package) = &package_node.package {
+        if package.meta.main_program.is_some() {
+            return ComponentType::Application;
+        }
+    }
+
+    if APPLICATION_BUILDER_VARIABLES
+        .iter()
+        .any(|v| derivation.env.contains_key(*v))
+    {
+        return ComponentType::Application;
+    }
+    if LIBRARY_BUILDER_VARIABLES
+        .iter()
+        .any(|v| derivation.env.contains_key(*v))
+    {
+        return ComponentType::Library;
+    }
+    if let Some(derivation_name) = derivation.env.get("name") {
+        if INTERPRETER_MODULE_REGEX.is_match(derivation_name) {
+            return ComponentType::Library;
+        }
+    }
+
+    let purl = package_node.get_purl();
+    if LIBRARY_PURL_TYPES.contains(&purl.package_type.as_str()) {
+        return ComponentType::Library;
+    }
+
+    // Anything else fetched from the network is a source archive or a patch.
+    if derivation.get_builder() == crate::nix::DerivationBuilder::FetchURL {
+        return ComponentType::File;
+    }
+
+    // Packages split their shared objects in a lib output, and their executables in a bin output.
+    if derivation.outputs.contains_key("lib") {
+        return ComponentType::Library;
+    }
+    if derivation.outputs.contains_key("bin") {
+        return ComponentType::Application;
+    }
+    if derivation.outputs.contains_key("dev") {
+        return ComponentType::Library;
+    }
+    if name.starts_with("lib") {
+        return ComponentType::Library;
+    }
+
+    ComponentType::Application
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::{BTreeMap, BTreeSet};
+
+    fn get_package_node(env: &str, outputs: &[&str]) -> crate::nix::PackageNode {
+        let outputs: Vec<String> = outputs
+            .iter()
+            .map(|o| {
+                format!(
+                    r#""{}": {{ "path": "/nix/store/eeeeeeeeeeeeeeeeeeeeeeeeeeeeeeee-{}" }}"#,
+                    o, o
+                )
+            })
+            .collect();
+        let derivation = format!(
+            r#"{{ "args": [], "builder": "/bin/bash", "env": {}, "inputDrvs": {{}}, "inputSrcs": [],
+                 "outputs": {{ {} }}, "system": "x86_64-linux" }}"#,
+            env,
+            outputs.join(",")
+        );
+        crate::nix::PackageNode {
+            main_derivation: serde_json::from_str(&derivation).unwrap(),
+            package: None,
+            package_match: None,
+            sources: vec![],
+            patches: BTreeSet::default(),
+            build_inputs: BTreeSet::default(),
+            children: BTreeSet::default(),
+            dependency_types: BTreeMap::default(),
+        }
+    }
+
+    #[test]
+    pub fn test_get_component_type() {
+        let cases = [
+            (
+                r#"{ "name": "hello-2.12.1" }"#,
+                vec!["out"],
+                ComponentType::Application,
+            ),
+            (
+                r#"{ "name": "curl-8.4.0" }"#,
+                vec!["bin", "dev", "out"],
+                ComponentType::Application,
+            ),
+            (
+                r#"{ "name": "zlib-1.3" }"#,
+                vec!["dev", "out"],
+                ComponentType::Library,
+            ),
+            (
+                r#"{ "name": "libpng-1.6.40" }"#,
+                vec!["out"],
+                ComponentType::Library,
+            ),
+            (
+                r#"{ "name": "linux-firmware-20231111" }"#,
+                vec!["out"],
+                ComponentType::Firmware,
+            ),
+            (
+                r#"{ "name": "dejavu-fonts-2.37" }"#,
+                vec!["out"],
+                ComponentType::Data,
+            ),
+            (
+                r#"{ "name": "microcode-intel-20231114", "pname": "microcode-intel" }"#,
+                vec!["out"],
+                ComponentType::Firmware,
+            ),
+            (
+                r#"{ "name": "firmware-updater-0.2.0", "pname": "firmware-updater" }"#,
+                vec!["out"],
+                ComponentType::Application,
+            ),
+            (
+                r#"{ "name": "noto-fonts-color-emoji-2.042", "pname": "noto-fonts-color-emoji" }"#,
+                vec!["out"],
+                ComponentType::Data,
+            ),
+            (
+                r#"{ "name": "font-awesome-6.5.1" }"#,
+                vec!["out"],
+                ComponentType::Data,
+            ),
+            (
+                r#"{ "name": "fontconfig-2.14.2", "pname": "fontconfig", "version": "2.14.2" }"#,
+                vec!["bin", "dev", "lib", "out"],
+                ComponentType::Library,
+            ),
+            (
+                r#"{ "name": "libXfont2-2.0.6", "pname": "libXfont2" }"#,
+                vec!["dev", "out"],
+                ComponentType::Library,
+            ),
+            (
+                r#"{ "name": "fontforge-20230101", "pname": "fontforge" }"#,
+                vec!["out"],
+                ComponentType::Application,
+            ),
+            (r#"{ "name": "tzdata-2024a" }"#, vec!["out"], ComponentType::Data),
+            (
+                r#"{ "name": "qtbase-6.6.0", "pname": "qtbase" }"#,
+                vec!["dev", "out"],
+                ComponentType::Framework,
+            ),
+            (
+                r#"{ "name": "run.sh", "text": "exit 0" }"#,
+                vec!["out"],
+                ComponentType::File,
+            ),
+            (
+                r#"{ "name": "python3.11-requests-2.31.0", "pname": "requests", "version": "2.31.0" }"#,
+                vec!["out"],
+                ComponentType::Library,
+            ),
+            (
+                r#"{ "name": "ripgrep-14.0.3", "cargoDeps": "/nix/store/x-vendor" }"#,
+                vec!["out"],
+                ComponentType::Application,
+            ),
+        ];
+        for (env, outputs, expected_type) in cases {
+            let package_node = get_package_node(env, &outputs);
+            assert_eq!(get_component_type(&package_node), expected_type, "{}", env);
+        }
+    }
+
+    #[test]
+    pub fn test_get_component_type_for_test_graph() {
+        let package_graph = crate::nix::get_test_package_graph();
+        let patch = package_graph
+            .nodes
+            .get("/nix/store/cccccccccccccccccccccccccccccccc-fix.patch.drv")
+            .unwrap();
+        assert_eq!(get_component_type(patch), ComponentType::File);
+        let hello = package_graph
+            .nodes
+            .get("/nix/store/aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa-hello-2.12.1.drv")
+            .unwrap();
+        assert_eq!(get_component_type(hello), ComponentType::Application);
+    }
+}
@@ -0,0 +1,204 @@
+// Generation of CPE 2.3 identifiers, which are used by the NVD to identify the products
+// affected by vulnerabilities. See https://nvlpubs.nist.gov/nistpubs/Legacy/IR/nistir7695.pdf
+use std::collections::HashMap;
+
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+
+const CPE_PREFIX: &str = "cpe:2.3";
+// Part used for applications, as opposed to the operating systems (o) and hardware (h).
+const APPLICATION_PART: &str = "a";
+const ANY_VALUE: &str = "*";
+
+// Characters which have to be quoted in the formatted string binding of a CPE.
+const SPECIAL_CHARACTERS: &str = "\\!\"#$%&'()+,/:;<=>@[]^`{|}~?*";
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(PartialEq)]
+#[derive(Deserialize)]
+#[derive(Serialize)]
+pub struct CpeMapping {
+    pub vendor: String,
+    pub product: String,
+}
+
+// Mapping from the name of the Nix packages to the vendor and product of their CPEs.
+pub type CpeMappings = HashMap<String, CpeMapping>;
+
+// The vendor and product names used by the NVD for common packages, keyed by the
+// name of the package in nixpkgs.
+const BUILTIN_CPE_MAPPINGS: &[(&str, &str, &str)] = &[
+    ("bash", "gnu", "bash"),
+    ("binutils", "gnu", "binutils"),
+    ("bind", "isc", "bind"),
+    ("busybox", "busybox", "busybox"),
+    ("bzip2", "bzip", "bzip2"),
+    ("coreutils", "gnu", "coreutils"),
+    ("curl", "haxx", "curl"),
+    ("dbus", "freedesktop", "dbus"),
+    ("expat", "libexpat_project", "libexpat"),
+    ("ffmpeg", "ffmpeg", "ffmpeg"),
+    ("freetype", "freetype", "freetype"),
+    ("gcc", "gnu", "gcc"),
+    ("git", "git-scm", "git"),
+    ("glib", "gnome", "glib"),
+    ("glibc", "gnu", "glibc"),
+    ("gnupg", "gnupg", "gnupg"),
+    ("gnutls", "gnu", "gnutls"),
+    ("go", "golang", "go"),
+    ("gzip", "gnu", "gzip"),
+    ("krb5", "mit", "kerberos_5"),
+    ("libarchive", "libarchive", "libarchive"),
+    ("libjpeg-turbo", "libjpeg-turbo", "libjpeg-turbo"),
+    ("libpng", "libpng", "libpng"),
+    ("libssh2", "libssh2", "libssh2"),
+    ("libtiff", "libtiff", "libtiff"),
+    ("libxml2", "xmlsoft", "libxml2"),
+    ("libxslt", "xmlsoft", "libxslt"),
+    ("linux", "linux", "linux_kernel"),
+    ("nghttp2", "nghttp2", "nghttp2"),
+    ("nginx", "f5", "nginx"),
+    ("nodejs", "nodejs", "node.js"),
+    ("openldap", "openldap", "openldap"),
+    ("openssh", "openbsd", "openssh"),
+    ("openssl", "openssl", "openssl"),
+    ("pcre2", "pcre", "pcre2"),
+    ("perl", "perl", "perl"),
+    ("postgresql", "postgresql", "postgresql"),
+    ("python3", "python", "python"),
+    ("qemu", "qemu", "qemu"),
+    ("ruby", "ruby-lang", "ruby"),
+    ("rustc", "rust-lang", "rust"),
+    ("sqlite", "sqlite", "sqlite"),
+    ("sudo", "sudo_project", "sudo"),
+    ("systemd", "systemd_project", "systemd"),
+    ("tar", "gnu", "tar"),
+    ("vim", "vim", "vim"),
+    ("wget", "gnu", "wget"),
+    ("xz", "tukaani", "xz"),
+    ("zlib", "zlib", "zlib"),
+];
+
+lazy_static! {
+    static ref BUILTIN_MAPPINGS: CpeMappings = {
+        let mut mappings = CpeMappings::default();
+        for (name, vendor, product) in BUILTIN_CPE_MAPPINGS {
+            mappings.insert(
+                name.to_string(),
+                CpeMapping {
+                    vendor: vendor.to_string(),
+                    product: product.to_string(),
+                },
+            );
+        }
+        mappings
+    };
+}
+
+// Reads the user-supplied mappings, which take precedence over the built-in ones.
+// The file is a JSON object keyed by package name, for example
+// { "openssl": { "vendor": "openssl", "product": "openssl" } }
+pub fn read_mappings(mappings_path: &str) -> Result<CpeMappings, String> {
+    log::info!("Using the CPE mappings from {}", mappings_path);
+    let content = std::fs::read(mappings_path).map_err(|e| e.to_string())?;
+    serde_json::from_slice(&content)
+        .map_err(|e| format!("Could not parse the CPE mappings from {}: {}", mappings_path, e))
+}
+
+// Generates the CPE of a package. When the package is not in the mappings, its name
+// is used as both the vendor and the product, which is what the NVD does for most
+// open source projects.
+pub fn get_cpe(name: &str, version: &str, user_mappings: &CpeMappings) -> String {
+    let name = name.to_lowercase();
+    let mapping = match user_mappings.get(&name) {
+        Some(m) => Some(m),
+        None => BUILTIN_MAPPINGS.get(&name),
+    };
+    let (vendor, product) = match mapping {
+        Some(m) => (m.vendor.to_string(), m.product.to_string()),
+        None => (name.to_string(), name.to_string()),
+    };
+    let components = [
+        CPE_PREFIX.to_string(),
+        APPLICATION_PART.to_string(),
+        escape(&vendor),
+        escape(&product),
+        escape(version),
+        // update, edition, language, sw_edition, target_sw, target_hw and other.
+        ANY_VALUE.to_string(),
+        ANY_VALUE.to_string(),
+        ANY_VALUE.to_string(),
+        ANY_VALUE.to_string(),
+        ANY_VALUE.to_string(),
+        ANY_VALUE.to_string(),
+        ANY_VALUE.to_string(),
+    ];
+    components.join(":")
+}
+
+fn escape(value: &str) -> String {
+    let mut response = "".to_string();
+    for c in value.chars() {
+        if c == ' ' {
+            response.push('_');
+            continue;
+        }
+        if SPECIAL_CHARACTERS.contains(c) {
+            response.push('\\');
+        }
+        response.push(c);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_get_cpe() {
+        let mappings = CpeMappings::default();
+        assert_eq!(
+            get_cpe("openssl", "3.0.12", &mappings),
+            "cpe:2.3:a:openssl:openssl:3.0.12:*:*:*:*:*:*:*"
+        );
+        assert_eq!(
+            get_cpe("curl", "8.4.0", &mappings),
+            "cpe:2.3:a:haxx:curl:8.4.0:*:*:*:*:*:*:*"
+        );
+        assert_eq!(
+            get_cpe("hello", "2.12.1", &mappings),
+            "cpe:2.3:a:hello:hello:2.12.1:*:*:*:*:*:*:*"
+        );
+        assert_eq!(
+            get_cpe("c++utils", "1.0+git:2", &mappings),
+            "cpe:2.3:a:c\\+\\+utils:c\\+\\+utils:1.0\\+git\\:2:*:*:*:*:*:*:*"
+        );
+    }
+
+    #[test]
+    pub fn test_get_mappings_with_overrides() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mappings_path = temp_dir.path().join("cpe-mappings.json");
+        std::fs::write(
+            &mappings_path,
+            r#"{ "curl": { "vendor": "curl", "product": "curl" }, "hello": { "vendor": "gnu", "product": "hello" } }"#,
+        )
+        .unwrap();
+        let mappings = read_mappings(mappings_path.to_str().unwrap()).unwrap();
+
+        assert_eq!(
+            get_cpe("curl", "8.4.0", &mappings),
+            "cpe:2.3:a:curl:curl:8.4.0:*:*:*:*:*:*:*"
+        );
+        assert_eq!(
+            get_cpe("hello", "2.12.1", &mappings),
+            "cpe:2.3:a:gnu:hello:2.12.1:*:*:*:*:*:*:*"
+        );
+        assert_eq!(
+            get_cpe("openssl", "3.0.12", &mappings),
+            "cpe:2.3:a:openssl:openssl:3.0.12:*:*:*:*:*:*:*"
+        );
+    }
+}
@@ -1,184 +1,795 @@
 use std::collections::BTreeSet;
-use std::time::SystemTime;
 
-use chrono::{DateTime, Utc};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 
-use serde_cyclonedx::cyclonedx::v_1_4::{
-    Commit, CommitBuilder, Component, ComponentBuilder, ComponentPedigreeBuilder, CycloneDxBuilder, Dependency,
-    DependencyBuilder, ExternalReference, ExternalReferenceBuilder, LicenseBuilder, LicenseChoice, Metadata,
-    ToolBuilder,
-};
+pub mod xml;
 
-const CURRENT_SPEC_VERSION: &str = "1.4";
+pub const SPEC_VERSION_1_4: &str = "1.4";
+pub const SPEC_VERSION_1_5: &str = "1.5";
+pub const SPEC_VERSION_1_6: &str = "1.6";
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(PartialEq)]
+#[derive(Default)]
+pub enum SpecVersion {
+    #[default]
+    V1_4,
+    V1_5,
+    V1_6,
+}
+
+impl SpecVersion {
+    pub fn from_string(spec_version: &str) -> Option<SpecVersion> {
+        match spec_version {
+            SPEC_VERSION_1_4 => Some(SpecVersion::V1_4),
+            SPEC_VERSION_1_5 => Some(SpecVersion::V1_5),
+            SPEC_VERSION_1_6 => Some(SpecVersion::V1_6),
+            _ => None,
+        }
+    }
+
+    pub fn to_string(&self) -> String {
+        match self {
+            SpecVersion::V1_4 => SPEC_VERSION_1_4.to_string(),
+            SpecVersion::V1_5 => SPEC_VERSION_1_5.to_string(),
+            SpecVersion::V1_6 => SPEC_VERSION_1_6.to_string(),
+        }
+    }
+}
 
 pub fn dump(
     package_graph: &crate::nix::PackageGraph,
     format: &crate::sbom::SerializationFormat,
     options: &crate::nix::DumpOptions,
 ) -> Result<String, String> {
-    let mut metadata = Metadata::default();
-    let now = SystemTime::now();
-    let now: DateTime<Utc> = now.into();
-    metadata.timestamp = Some(now.to_rfc3339());
-
-    metadata.tools = Some(vec![ToolBuilder::default()
-        .vendor("louib".to_string())
-        .name(crate::consts::PROJECT_NAME.to_string())
-        .version(env!("CARGO_PKG_VERSION"))
-        .build()
-        .unwrap()]);
-
-    let mut components: Vec<Component> = vec![];
-    for (derivation_path, package) in package_graph.nodes.iter() {
-        if let Some(component) = dump_package_node(derivation_path, package, package_graph) {
+    use serde_cyclonedx::cyclonedx::{v_1_4, v_1_5, v_1_6};
+    match options.cyclone_dx_spec_version {
+        SpecVersion::V1_4 => dump_as::<v_1_4::CycloneDx>(package_graph, format, options, SpecVersion::V1_4),
+        SpecVersion::V1_5 => dump_as::<v_1_5::CycloneDx>(package_graph, format, options, SpecVersion::V1_5),
+        SpecVersion::V1_6 => dump_as::<v_1_6::CycloneDx>(package_graph, format, options, SpecVersion::V1_6),
+    }
+}
+
+// The document is deserialized into the model of its version of the spec, which validates it.
+fn dump_as<T: DeserializeOwned + Serialize>(
+    package_graph: &crate::nix::PackageGraph,
+    format: &crate::sbom::SerializationFormat,
+    options: &crate::nix::DumpOptions,
+    spec_version: SpecVersion,
+) -> Result<String, String> {
+    let document = get_document(package_graph, options, &spec_version);
+    let cyclonedx: T = serde_json::from_value(document)
+        .map_err(|e| format!("Invalid CycloneDX {} document: {}", spec_version.to_string(), e))?;
+    serialize(&cyclonedx, format, &spec_version.to_string())
+}
+
+// The document is built as JSON, which has mostly the same structure in all the versions of
+// the spec.
+pub(crate) fn get_document(
+    package_graph: &crate::nix::PackageGraph,
+    options: &crate::nix::DumpOptions,
+    spec_version: &SpecVersion,
+) -> serde_json::Value {
+    let mut document = serde_json::json!({
+        "bomFormat": crate::sbom::CYCLONE_DX_NAME,
+        "specVersion": spec_version.to_string(),
+        "version": 1,
+        "metadata": get_metadata(options, spec_version),
+    });
+
+    let mut components: Vec<serde_json::Value> = vec![];
+    for (derivation_path, package_node) in package_graph.nodes.iter() {
+        if let Some(component) = dump_package_node(
+            derivation_path,
+            package_node,
+            package_graph,
+            options,
+            spec_version,
+        ) {
             components.push(component);
         }
     }
+    let component_refs: BTreeSet<String> = components
+        .iter()
+        .filter_map(|c| c["bom-ref"].as_str().map(|r| r.to_string()))
+        .collect();
+    components.extend(get_flake_input_components(options));
+    document["components"] = serde_json::Value::Array(components);
 
-    let mut dependencies: Vec<Dependency> = vec![];
-    for (derivation_path, package) in package_graph.nodes.iter() {
-        if package.children.len() == 0 {
-            continue;
-        }
-        let mut dependency_builder = DependencyBuilder::default();
-        dependency_builder.ref_(derivation_path);
-        let mut depends_on: Vec<String> = vec![];
-        for child in package.children.iter() {
-            depends_on.push(child.to_string());
-        }
-        if !options.runtime_only {
-            for build_input in package.build_inputs.iter() {
-                depends_on.push(build_input.to_string());
-            }
-        }
-        dependency_builder.depends_on(depends_on);
-        dependencies.push(dependency_builder.build().unwrap());
+    let dependencies: Vec<serde_json::Value> = get_dependencies(package_graph, options)
+        .into_iter()
+        .map(|(bom_ref, depends_on)| serde_json::json!({ "ref": bom_ref, "dependsOn": depends_on }))
+        .collect();
+    document["dependencies"] = serde_json::Value::Array(dependencies);
+
+    let vulnerabilities = get_vulnerabilities(package_graph, options);
+    if vulnerabilities.len() != 0 {
+        document["vulnerabilities"] = serde_json::Value::Array(vulnerabilities);
     }
 
-    let cyclonedx = CycloneDxBuilder::default()
-        .bom_format(crate::sbom::CYCLONE_DX_NAME)
-        .spec_version(CURRENT_SPEC_VERSION)
-        .version(1)
-        .metadata(metadata)
-        .components(components)
-        .dependencies(dependencies)
-        .build()
-        .unwrap();
+    // The formulation was only introduced in 1.5.
+    if *spec_version != SpecVersion::V1_4 {
+        let workflows = get_workflows(package_graph, &component_refs);
+        if workflows.len() != 0 {
+            document["formulation"] = serde_json::json!([{
+                "bom-ref": FORMULA_REF,
+                "workflows": workflows,
+            }]);
+        }
+    }
+    document
+}
 
+// Canonicalizes a document built with the model of a version of the spec, and serializes it.
+pub(crate) fn serialize<T: Serialize + DeserializeOwned>(
+    document: &T,
+    format: &crate::sbom::SerializationFormat,
+    spec_version: &str,
+) -> Result<String, String> {
+    let cyclonedx = canonicalize(document)?;
     match format {
         crate::sbom::SerializationFormat::JSON => {
             serde_json::to_string_pretty(&cyclonedx).map_err(|e| e.to_string())
         }
         crate::sbom::SerializationFormat::YAML => serde_yaml::to_string(&cyclonedx).map_err(|e| e.to_string()),
-        crate::sbom::SerializationFormat::XML => Err("XML is not supported for CycloneDX".to_string()),
+        crate::sbom::SerializationFormat::XML => {
+            let document = serde_json::to_value(&cyclonedx).map_err(|e| e.to_string())?;
+            crate::cyclone_dx::xml::to_xml(&document, spec_version)
+        }
+        crate::sbom::SerializationFormat::TagValue => Err("Tag-value is only supported for SPDX".to_string()),
+    }
+}
+
+fn get_metadata(options: &crate::nix::DumpOptions, spec_version: &SpecVersion) -> serde_json::Value {
+    let mut metadata = serde_json::json!({ "timestamp": get_timestamp(options) });
+    match spec_version {
+        SpecVersion::V1_4 => {
+            metadata["tools"] = serde_json::json!([{
+                "vendor": "louib",
+                "name": crate::consts::PROJECT_NAME,
+                "version": env!("CARGO_PKG_VERSION"),
+            }]);
+        }
+        _ => {
+            // Starting with 1.5, the tools are described using components.
+            metadata["tools"] = serde_json::json!({
+                "components": [{
+                    "type": "application",
+                    "publisher": "louib",
+                    "name": crate::consts::PROJECT_NAME,
+                    "version": env!("CARGO_PKG_VERSION"),
+                }],
+            });
+            // The SBOM is generated from the derivations, before or after they are built.
+            metadata["lifecycles"] = serde_json::json!([{ "phase": "build" }]);
+        }
+    }
+    if let Some(component) = get_metadata_component(options) {
+        metadata["component"] = component;
+    }
+    let metadata_properties = get_metadata_properties(options);
+    if metadata_properties.len() != 0 {
+        metadata["properties"] = get_properties_value(metadata_properties);
     }
+    metadata
 }
 
-pub fn dump_package_node(
+fn dump_package_node(
     package_derivation_path: &str,
     package_node: &crate::nix::PackageNode,
     package_graph: &crate::nix::PackageGraph,
-) -> Option<Component> {
+    options: &crate::nix::DumpOptions,
+    spec_version: &SpecVersion,
+) -> Option<serde_json::Value> {
     // FIXME this should be configurable.
     if package_node.is_inline_script() {
         return None;
     }
 
-    let component = dump_derivation(package_graph, package_derivation_path, package_node);
-    let mut sub_components: Vec<Component> = vec![];
-    let main_source_path = package_node.main_derivation.get_source_path();
-    for child in &package_node.sources {
-        // FIXME not sure about that one
-        let child_derivation_path = child.get_source_path();
-        if main_source_path == child_derivation_path {
+    // The fetched sources are derivations of their own in the package graph, so they are
+    // dumped as separate components, with the hashes of their fixed output.
+    dump_derivation(
+        package_graph,
+        package_derivation_path,
+        package_node,
+        options,
+        spec_version,
+    )
+}
+
+fn dump_derivation(
+    package_graph: &crate::nix::PackageGraph,
+    derivation_path: &str,
+    package_node: &crate::nix::PackageNode,
+    options: &crate::nix::DumpOptions,
+    spec_version: &SpecVersion,
+) -> Option<serde_json::Value> {
+    log::debug!("Dumping derivation for {}", &derivation_path);
+    let name = package_node.get_name()?;
+    // TODO populate the mime_type in case of a file type.
+    let component_type = match (
+        crate::component_type::get_component_type(package_node),
+        spec_version,
+    ) {
+        // The data type was only introduced in 1.5.
+        (crate::component_type::ComponentType::Data, SpecVersion::V1_4) => {
+            crate::component_type::ComponentType::File
+        }
+        (t, _) => t,
+    };
+    // I'm assuming here that if a package has been installed by Nix, it was required.
+    let mut component = serde_json::json!({
+        "type": component_type.to_string(),
+        "bom-ref": derivation_path,
+        "name": name,
+        "scope": "required",
+        "purl": package_node.get_purl().to_string(),
+    });
+    if let Some(cpe) = package_node.get_cpe(options) {
+        component["cpe"] = serde_json::json!(cpe);
+    }
+    if let Some(version) = package_node.get_version() {
+        component["version"] = serde_json::json!(version);
+    }
+    if let Some(description) = package_node
+        .package
+        .as_ref()
+        .and_then(|p| p.meta.description.as_ref())
+    {
+        component["description"] = serde_json::json!(description);
+    }
+    if let Some(author) = get_author(package_node) {
+        component["author"] = serde_json::json!(author);
+    }
+
+    let hashes = get_hashes(&package_node.main_derivation);
+    if hashes.len() != 0 {
+        component["hashes"] = serde_json::Value::Array(hashes);
+    }
+
+    let external_references: Vec<serde_json::Value> = get_external_references(package_node)
+        .into_iter()
+        .map(|(reference_type, url)| serde_json::json!({ "type": reference_type, "url": url }))
+        .collect();
+    if external_references.len() != 0 {
+        component["externalReferences"] = serde_json::Value::Array(external_references);
+    }
+
+    let commits: Vec<serde_json::Value> = get_commit_urls(package_graph, &package_node.patches)
+        .into_iter()
+        .map(|url| serde_json::json!({ "url": url }))
+        .collect();
+    if commits.len() != 0 {
+        component["pedigree"] = serde_json::json!({ "commits": commits });
+    }
+
+    if let Some(licenses) = get_license_choices(package_node, options, spec_version) {
+        component["licenses"] = licenses;
+    }
+
+    let properties = get_properties(package_node);
+    if properties.len() != 0 {
+        component["properties"] = get_properties_value(properties);
+    }
+
+    Some(component)
+}
+
+fn get_hashes(derivation: &crate::nix::Derivation) -> Vec<serde_json::Value> {
+    let mut hashes: Vec<serde_json::Value> = vec![];
+    // The hash of a recursive fixed-output derivation is the hash of the NAR serialization of
+    // the output, and not of a file, so it is reported as a property instead.
+    if let Some(hash) = derivation.get_output_hash() {
+        if hash.mode != crate::hash::HashMode::Flat {
+            return hashes;
+        }
+        let alg = match hash.algorithm {
+            crate::hash::HashAlgorithm::MD5 => "MD5",
+            crate::hash::HashAlgorithm::SHA1 => "SHA-1",
+            crate::hash::HashAlgorithm::SHA256 => "SHA-256",
+            crate::hash::HashAlgorithm::SHA512 => "SHA-512",
+        };
+        hashes.push(serde_json::json!({ "alg": alg, "content": hash.value }));
+    }
+    hashes
+}
+
+// A license choice is either a list of licenses, or a single license expression. The
+// properties of the licenses were only introduced in 1.5.
+fn get_license_choices(
+    package_node: &crate::nix::PackageNode,
+    options: &crate::nix::DumpOptions,
+    spec_version: &SpecVersion,
+) -> Option<serde_json::Value> {
+    match get_licenses(package_node, options)? {
+        ComponentLicense::License(license) => {
+            let mut license_document = serde_json::json!({});
+            let (id, name) = get_license_id_and_name(&license);
+            if let Some(id) = id {
+                license_document["id"] = serde_json::json!(id);
+            }
+            if let Some(name) = name {
+                license_document["name"] = serde_json::json!(name);
+            }
+            let properties = get_license_properties(&license);
+            if properties.len() != 0 && *spec_version != SpecVersion::V1_4 {
+                license_document["properties"] = get_properties_value(properties);
+            }
+            Some(serde_json::json!([{ "license": license_document }]))
+        }
+        ComponentLicense::Expression(expression) => Some(serde_json::json!([{ "expression": expression }])),
+    }
+}
+
+fn get_properties_value(properties: Vec<(String, String)>) -> serde_json::Value {
+    properties
+        .into_iter()
+        .map(|(name, value)| serde_json::json!({ "name": name, "value": value }))
+        .collect()
+}
+
+// The bom-ref of the formula describing the build of the closure.
+pub const FORMULA_REF: &str = "nix-build";
+// Prefix of the bom-refs of the workflows, which are keyed by the path of their derivation.
+pub const WORKFLOW_REF_PREFIX: &str = "nix-build:";
+pub const SYSTEM_PROPERTY_NAME: &str = "nix:system";
+pub const OUTPUT_PROPERTY_NAME_PREFIX: &str = "nix:output:";
+pub const NAR_HASH_PROPERTY_NAME: &str = "nix:narHash";
+
+// Every derivation of a component is built by a workflow, which runs the builder of the
+// derivation with its arguments, takes the components of the input derivations and
+// produces the component.
+fn get_workflows(
+    package_graph: &crate::nix::PackageGraph,
+    component_refs: &BTreeSet<String>,
+) -> Vec<serde_json::Value> {
+    let mut workflows: Vec<serde_json::Value> = vec![];
+    for (derivation_path, package_node) in package_graph.nodes.iter() {
+        if !component_refs.contains(derivation_path) {
             continue;
         }
-        if let Some(component) = dump_sub_derivation(&child) {
-            sub_components.push(component);
+        let derivation = &package_node.main_derivation;
+        let mut command = vec![derivation.builder.to_string()];
+        command.extend(derivation.args.iter().cloned());
+        let mut workflow = serde_json::json!({
+            "bom-ref": format!("{}{}", WORKFLOW_REF_PREFIX, derivation_path),
+            "uid": derivation_path,
+            "taskTypes": ["build"],
+            "steps": [{
+                "name": "builder",
+                "commands": [{ "executed": command.join(" ") }],
+            }],
+            "outputs": [{ "type": "artifact", "resource": { "ref": derivation_path } }],
+        });
+        if let Some(name) = derivation.env.get("name") {
+            workflow["name"] = serde_json::json!(name);
+        }
+        let inputs: Vec<serde_json::Value> = derivation
+            .input_derivations
+            .keys()
+            .filter(|input_derivation_path| component_refs.contains(*input_derivation_path))
+            .map(|input_derivation_path| serde_json::json!({ "resource": { "ref": input_derivation_path } }))
+            .collect();
+        if inputs.len() != 0 {
+            workflow["inputs"] = serde_json::Value::Array(inputs);
         }
+        let mut properties = vec![(SYSTEM_PROPERTY_NAME.to_string(), derivation.system.to_string())];
+        for (output_name, output) in derivation.outputs.iter() {
+            properties.push((
+                format!("{}{}", OUTPUT_PROPERTY_NAME_PREFIX, output_name),
+                output.path.to_string(),
+            ));
+        }
+        workflow["properties"] = get_properties_value(properties);
+        workflows.push(workflow);
     }
-    component
+    workflows
+}
+
+pub(crate) fn get_timestamp(options: &crate::nix::DumpOptions) -> String {
+    crate::sbom::get_timestamp(options).to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+}
+
+// Sorts all the arrays of the document and derives its serial number from its content,
+// so that the same package graph always produces the same SBOM.
+pub(crate) fn canonicalize<T: Serialize + DeserializeOwned>(document: &T) -> Result<T, String> {
+    let mut document = serde_json::to_value(document).map_err(|e| e.to_string())?;
+    sort_arrays(&mut document);
+
+    let document_object = match document.as_object_mut() {
+        Some(o) => o,
+        None => return Err("The CycloneDX document is not an object".to_string()),
+    };
+    document_object.remove("serialNumber");
+    // The keys of serde_json maps are sorted, so this serialization is stable.
+    let content = serde_json::to_vec(&document_object).map_err(|e| e.to_string())?;
+    document_object.insert(
+        "serialNumber".to_string(),
+        serde_json::Value::String(get_serial_number(&content)),
+    );
+
+    serde_json::from_value(document).map_err(|e| e.to_string())
+}
+
+pub(crate) fn get_serial_number(content: &[u8]) -> String {
+    format!(
+        "urn:uuid:{}",
+        uuid::Uuid::new_v5(&uuid::Uuid::NAMESPACE_URL, content)
+    )
 }
 
-pub fn dump_sub_derivation(derivation: &crate::nix::Derivation) -> Option<Component> {
-    let derivation_name = match derivation.get_name() {
-        Some(n) => n,
-        None => {
-            // TODO we should probably log something here, but I'm not sure what other value
-            // from the derivation would make sense.
-            return None;
+// Only the arrays which are sets in the spec are sorted. The order of the other arrays, like
+// the commits of the pedigree or the steps of a workflow, carries meaning and is kept.
+const SORTED_ARRAY_FIELDS: &[(&str, Option<&str>)] = &[
+    ("components", Some("bom-ref")),
+    ("dependencies", Some("ref")),
+    ("dependsOn", None),
+    ("hashes", None),
+    ("properties", None),
+];
+
+fn sort_arrays(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Array(items) => {
+            for item in items.iter_mut() {
+                sort_arrays(item);
+            }
         }
+        serde_json::Value::Object(fields) => {
+            for (field_name, field_value) in fields.iter_mut() {
+                sort_arrays(field_value);
+                let sort_key = match SORTED_ARRAY_FIELDS.iter().find(|(f, _)| f == field_name) {
+                    Some((_, sort_key)) => sort_key,
+                    None => continue,
+                };
+                if let serde_json::Value::Array(items) = field_value {
+                    items.sort_by_cached_key(|item| get_sort_key(item, *sort_key));
+                    if field_name == "dependsOn" {
+                        items.dedup();
+                    }
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+fn get_sort_key(item: &serde_json::Value, field_name: Option<&str>) -> (String, String) {
+    let key = match field_name.and_then(|f| item.get(f)) {
+        Some(serde_json::Value::String(s)) => s.to_string(),
+        _ => "".to_string(),
+    };
+    let content = match item {
+        serde_json::Value::String(s) => s.to_string(),
+        _ => item.to_string(),
     };
-    log::debug!("Dumping sub-derivation for {}", &derivation_name);
+    (key, content)
+}
 
-    let mut component_builder = ComponentBuilder::default();
-    if let Some(source_path) = derivation.get_source_path() {
-        component_builder.bom_ref(source_path.to_string());
+// The dependencies of the components, as the bom-ref of each component and the bom-refs
+// of its dependencies. The root components depend on the inputs of the flake, and the
+// NixOS system depends on the root components.
+pub(crate) fn get_dependencies(
+    package_graph: &crate::nix::PackageGraph,
+    options: &crate::nix::DumpOptions,
+) -> Vec<(String, Vec<String>)> {
+    let flake_root_inputs: Vec<String> = match &options.flake_lock {
+        Some(flake_lock) => flake_lock
+            .get_root_inputs()
+            .iter()
+            .map(|n| crate::flake_lock::get_flake_input_ref(n))
+            .collect(),
+        None => vec![],
+    };
+    let mut dependencies: Vec<(String, Vec<String>)> = vec![];
+    for (derivation_path, package) in package_graph.nodes.iter() {
+        let mut depends_on = get_depends_on(package, options);
+        if package_graph.root_nodes.contains(derivation_path) {
+            depends_on.extend(flake_root_inputs.iter().cloned());
+        }
+        if depends_on.len() == 0 {
+            continue;
+        }
+        dependencies.push((derivation_path.to_string(), depends_on));
     }
-    component_builder.name(derivation_name.to_string());
-    component_builder.scope("required".to_string());
-    if let Ok(component) = component_builder.build() {
-        return Some(component);
+    if options.system_metadata.is_nixos_system() && package_graph.root_nodes.len() != 0 {
+        dependencies.push((
+            SYSTEM_COMPONENT_REF.to_string(),
+            package_graph.root_nodes.iter().cloned().collect(),
+        ));
     }
-    None
+    for flake_input in get_flake_inputs(options) {
+        if flake_input.inputs.len() == 0 {
+            continue;
+        }
+        dependencies.push((
+            flake_input.get_ref(),
+            flake_input
+                .inputs
+                .iter()
+                .map(|n| crate::flake_lock::get_flake_input_ref(n))
+                .collect(),
+        ));
+    }
+    dependencies
 }
 
-pub fn dump_derivation(
-    package_graph: &crate::nix::PackageGraph,
-    derivation_path: &str,
-    package_node: &crate::nix::PackageNode,
-) -> Option<Component> {
-    log::debug!("Dumping derivation for {}", &derivation_path);
-    let mut component_builder = ComponentBuilder::default();
+fn get_depends_on(package_node: &crate::nix::PackageNode, options: &crate::nix::DumpOptions) -> Vec<String> {
+    package_node
+        .get_dependencies(options.runtime_only)
+        .into_iter()
+        .collect()
+}
 
-    component_builder.bom_ref(derivation_path.to_string());
-    if let Some(name) = package_node.get_name() {
-        component_builder.name(name.to_string());
-    } else {
-        return None;
-    }
-    // component_builder.cpe("TODO".to_string())
-    // TODO application is the generic type, but we should also use file and library
-    // also, populate the mime_type in case of a file type.
-    component_builder.type_("application".to_string());
-    // I'm assuming here that if a package has been installed by Nix, it was required.
-    component_builder.scope("required".to_string());
-    component_builder.purl(package_node.get_purl().to_string());
-    if let Some(v) = package_node.get_version() {
-        component_builder.version(v.to_string());
+pub const FLAKE_INPUT_TYPE_PROPERTY_NAME: &str = "nix:flake:type";
+pub const FLAKE_INPUT_REV_PROPERTY_NAME: &str = "nix:flake:rev";
+pub const FLAKE_INPUT_NAR_HASH_PROPERTY_NAME: &str = "nix:flake:narHash";
+pub const FLAKE_INPUT_LAST_MODIFIED_PROPERTY_NAME: &str = "nix:flake:lastModified";
+
+pub(crate) fn get_flake_inputs(options: &crate::nix::DumpOptions) -> Vec<crate::flake_lock::FlakeInput> {
+    match &options.flake_lock {
+        Some(flake_lock) => flake_lock.get_flake_inputs(),
+        None => vec![],
     }
+}
 
-    if let Some(p) = &package_node.package {
-        if let Some(description) = &p.meta.description {
-            component_builder.description(description.to_string());
+// The inputs of the flake are built as JSON like the vulnerabilities. They are sources,
+// which CycloneDX does not have a type for, so they are described as libraries.
+pub(crate) fn get_flake_input_components(options: &crate::nix::DumpOptions) -> Vec<serde_json::Value> {
+    let mut components: Vec<serde_json::Value> = vec![];
+    for flake_input in get_flake_inputs(options) {
+        let locked = &flake_input.locked;
+        let mut component = serde_json::json!({
+            "type": "library",
+            "bom-ref": flake_input.get_ref(),
+            "name": flake_input.get_name(),
+            "scope": "required",
+        });
+        if let Some(version) = locked.rev.as_ref().or(locked.git_ref.as_ref()) {
+            component["version"] = serde_json::json!(version);
+        }
+        if let Some(url) = locked.get_url() {
+            if let Some(purl) = crate::purl::PackageURL::from_download_url(&url, locked.rev.as_deref()) {
+                component["purl"] = serde_json::json!(purl.to_string());
+            }
+            let reference_type = match locked.reference_type.as_str() {
+                "tarball" | "file" => "distribution",
+                _ => "vcs",
+            };
+            component["externalReferences"] = serde_json::json!([{ "type": reference_type, "url": url }]);
         }
+        // The narHash is the hash of the serialization of the source tree, not of an artifact
+        // which could be downloaded, so it is only recorded as a property.
+        let mut properties = vec![serde_json::json!({
+            "name": FLAKE_INPUT_TYPE_PROPERTY_NAME, "value": locked.reference_type,
+        })];
+        if let Some(rev) = &locked.rev {
+            properties.push(serde_json::json!({ "name": FLAKE_INPUT_REV_PROPERTY_NAME, "value": rev }));
+        }
+        if let Some(nar_hash) = &locked.nar_hash {
+            properties
+                .push(serde_json::json!({ "name": FLAKE_INPUT_NAR_HASH_PROPERTY_NAME, "value": nar_hash }));
+        }
+        if let Some(last_modified) = locked.last_modified {
+            properties.push(serde_json::json!({
+                "name": FLAKE_INPUT_LAST_MODIFIED_PROPERTY_NAME, "value": last_modified.to_string(),
+            }));
+        }
+        component["properties"] = serde_json::json!(properties);
+        components.push(component);
     }
+    components
+}
 
-    if let Some(author) = get_author(&package_node) {
-        component_builder.author(author);
+pub const NIXOS_VERSION_PROPERTY_NAME: &str = "nixos:version";
+pub const NIXPKGS_REVISION_PROPERTY_NAME: &str = "nixpkgs:revision";
+pub const NIXPKGS_REF_PROPERTY_NAME: &str = "nixpkgs:ref";
+pub const NIXPKGS_LAST_MODIFIED_PROPERTY_NAME: &str = "nixpkgs:lastModified";
+
+// The bom-ref of the component describing the NixOS system in the metadata.
+pub const SYSTEM_COMPONENT_REF: &str = "nixos-system";
+
+// The properties of the metadata, with the revision of nixpkgs which produced the closure.
+pub(crate) fn get_metadata_properties(options: &crate::nix::DumpOptions) -> Vec<(String, String)> {
+    let system_metadata = &options.system_metadata;
+    let mut properties: Vec<(String, String)> = vec![];
+    if let Some(nixos_version) = &system_metadata.nixos_version {
+        properties.push((NIXOS_VERSION_PROPERTY_NAME.to_string(), nixos_version.to_string()));
+    }
+    if let Some(nixpkgs_revision) = &system_metadata.nixpkgs_revision {
+        properties.push((
+            NIXPKGS_REVISION_PROPERTY_NAME.to_string(),
+            nixpkgs_revision.to_string(),
+        ));
+    }
+    if let Some(nixpkgs_ref) = &system_metadata.nixpkgs_ref {
+        properties.push((NIXPKGS_REF_PROPERTY_NAME.to_string(), nixpkgs_ref.to_string()));
     }
+    if let Some(last_modified) = system_metadata.nixpkgs_last_modified {
+        properties.push((
+            NIXPKGS_LAST_MODIFIED_PROPERTY_NAME.to_string(),
+            last_modified.to_string(),
+        ));
+    }
+    properties
+}
 
-    let external_references: Vec<ExternalReference> = get_external_references(&package_node);
-    if external_references.len() != 0 {
-        component_builder.external_references(external_references);
+// The component the SBOM describes, when the closure is the one of a NixOS system.
+pub(crate) fn get_metadata_component(options: &crate::nix::DumpOptions) -> Option<serde_json::Value> {
+    let system_metadata = &options.system_metadata;
+    if !system_metadata.is_nixos_system() {
+        return None;
     }
+    let mut component = serde_json::json!({
+        "type": "operating-system",
+        "bom-ref": SYSTEM_COMPONENT_REF,
+        "name": system_metadata.name.as_deref().unwrap_or(crate::nixos::DEFAULT_SYSTEM_NAME),
+    });
+    if let Some(nixos_version) = &system_metadata.nixos_version {
+        component["version"] = serde_json::json!(nixos_version);
+    }
+    let properties = get_metadata_properties(options);
+    if properties.len() != 0 {
+        component["properties"] = get_properties_value(properties);
+    }
+    Some(component)
+}
 
-    let commits = get_commits(&package_graph, &package_node.patches);
-    if commits.len() != 0 {
-        let mut pedigree_builder = ComponentPedigreeBuilder::default();
-        pedigree_builder.commits(commits);
-        component_builder.pedigree(pedigree_builder.build().unwrap());
+// The vulnerabilities have the same structure in all the versions of the spec, except for
+// the rating methods.
+pub(crate) fn get_vulnerabilities(
+    package_graph: &crate::nix::PackageGraph,
+    options: &crate::nix::DumpOptions,
+) -> Vec<serde_json::Value> {
+    let mut vulnerabilities: Vec<serde_json::Value> = vec![];
+    for vulnerability in crate::vulnerabilities::get_all_vulnerabilities(package_graph, options) {
+        let source = serde_json::json!({
+            "name": vulnerability.source_name,
+            "url": vulnerability.source_url,
+        });
+        let mut document = serde_json::json!({
+            "id": vulnerability.id,
+            "source": source,
+        });
+
+        let mut references: Vec<serde_json::Value> = vec![];
+        for alias in &vulnerability.aliases {
+            let (source_name, source_url) = crate::vulnerabilities::get_source(alias);
+            references.push(serde_json::json!({
+                "id": alias,
+                "source": { "name": source_name, "url": source_url },
+            }));
+        }
+        if references.len() != 0 {
+            document["references"] = serde_json::Value::Array(references);
+        }
+
+        let mut ratings: Vec<serde_json::Value> = vec![];
+        for rating in &vulnerability.ratings {
+            let mut rating_document = serde_json::json!({ "source": source });
+            if let Some(score) = rating.score {
+                rating_document["score"] = serde_json::json!(score);
+            }
+            if let Some(severity) = &rating.severity {
+                rating_document["severity"] = serde_json::json!(severity);
+            }
+            if let Some(method) = &rating.method {
+                // CVSS 4 was only added in 1.5.
+                let method = match (method.as_str(), &options.cyclone_dx_spec_version) {
+                    ("CVSSv4", SpecVersion::V1_4) => "other",
+                    (m, _) => m,
+                };
+                rating_document["method"] = serde_json::json!(method);
+            }
+            if let Some(vector) = &rating.vector {
+                rating_document["vector"] = serde_json::json!(vector);
+            }
+            ratings.push(rating_document);
+        }
+        if ratings.len() != 0 {
+            document["ratings"] = serde_json::Value::Array(ratings);
+        }
+
+        if let Some(description) = &vulnerability.description {
+            document["description"] = serde_json::json!(description);
+        }
+        if let Some(detail) = &vulnerability.detail {
+            document["detail"] = serde_json::json!(detail);
+        }
+        if vulnerability.advisories.len() != 0 {
+            let advisories: Vec<serde_json::Value> = vulnerability
+                .advisories
+                .iter()
+                .map(|url| serde_json::json!({ "url": url }))
+                .collect();
+            document["advisories"] = serde_json::Value::Array(advisories);
+        }
+
+        let mut affects: Vec<serde_json::Value> = vec![];
+        for (derivation_path, version) in &vulnerability.affected {
+            let mut affect = serde_json::json!({ "ref": derivation_path });
+            if let Some(version) = version {
+                affect["versions"] = serde_json::json!([{ "version": version, "status": "affected" }]);
+            }
+            affects.push(affect);
+        }
+        document["affects"] = serde_json::Value::Array(affects);
+
+        vulnerabilities.push(document);
     }
+    vulnerabilities
+}
 
-    let licenses = get_licenses(&package_node);
-    if licenses.len() != 0 {
-        component_builder.licenses(licenses);
+pub const INSECURE_PROPERTY_NAME: &str = "nixpkgs:insecure";
+pub const KNOWN_VULNERABILITY_PROPERTY_NAME: &str = "nixpkgs:knownVulnerability";
+pub const UNFREE_PROPERTY_NAME: &str = "nixpkgs:unfree";
+pub const REDISTRIBUTABLE_PROPERTY_NAME: &str = "nixpkgs:redistributable";
+pub const LICENSE_FREE_PROPERTY_NAME: &str = "nixpkgs:license:free";
+pub const LICENSE_REDISTRIBUTABLE_PROPERTY_NAME: &str = "nixpkgs:license:redistributable";
+pub const LICENSE_DEPRECATED_PROPERTY_NAME: &str = "nixpkgs:license:deprecated";
+pub const META_MATCH_PROPERTY_NAME: &str = "nixpkgs:meta:match";
+
+// Properties of the component, as name and value pairs.
+pub(crate) fn get_properties(package_node: &crate::nix::PackageNode) -> Vec<(String, String)> {
+    let mut properties: Vec<(String, String)> = vec![];
+    if let Some(hash) = package_node.main_derivation.get_output_hash() {
+        if hash.mode == crate::hash::HashMode::Recursive {
+            properties.push((NAR_HASH_PROPERTY_NAME.to_string(), hash.to_checksum()));
+        }
+    }
+    let package = match &package_node.package {
+        Some(p) => p,
+        None => return properties,
+    };
+    if package.meta.is_insecure() {
+        properties.push((INSECURE_PROPERTY_NAME.to_string(), "true".to_string()));
+    }
+    for known_vulnerability in package.meta.get_known_vulnerabilities() {
+        properties.push((KNOWN_VULNERABILITY_PROPERTY_NAME.to_string(), known_vulnerability));
+    }
+    if let Some(unfree) = package.meta.get_unfree() {
+        properties.push((UNFREE_PROPERTY_NAME.to_string(), unfree.to_string()));
+    }
+    if let Some(redistributable) = package.meta.get_redistributable() {
+        properties.push((
+            REDISTRIBUTABLE_PROPERTY_NAME.to_string(),
+            redistributable.to_string(),
+        ));
+    }
+    if let Some(package_match) = &package_node.package_match {
+        properties.push((META_MATCH_PROPERTY_NAME.to_string(), package_match.to_string()));
     }
+    properties
+}
 
-    Some(component_builder.build().unwrap())
+// Properties of a license, from the flags of the license in nixpkgs.
+pub(crate) fn get_license_properties(license: &crate::license::NormalizedLicense) -> Vec<(String, String)> {
+    let mut properties: Vec<(String, String)> = vec![];
+    let details = match &license.license {
+        crate::nix::PackageLicense::Details(d) => d,
+        crate::nix::PackageLicense::Name(_) => return properties,
+    };
+    if let Some(free) = details.free {
+        properties.push((LICENSE_FREE_PROPERTY_NAME.to_string(), free.to_string()));
+    }
+    if let Some(redistributable) = details.redistributable {
+        properties.push((
+            LICENSE_REDISTRIBUTABLE_PROPERTY_NAME.to_string(),
+            redistributable.to_string(),
+        ));
+    }
+    if let Some(deprecated) = details.deprecated {
+        properties.push((
+            LICENSE_DEPRECATED_PROPERTY_NAME.to_string(),
+            deprecated.to_string(),
+        ));
+    }
+    properties
 }
 
-fn get_author(package_node: &crate::nix::PackageNode) -> Option<String> {
+pub(crate) fn get_author(package_node: &crate::nix::PackageNode) -> Option<String> {
     let maintainers = match &package_node.package {
         Some(p) => p.meta.get_maintainers(),
         None => vec![],
@@ -202,44 +813,40 @@ fn get_author(package_node: &crate::nix::PackageNode) -> Option<String> {
     None
 }
 
-fn get_commits(package_graph: &crate::nix::PackageGraph, patches: &BTreeSet<String>) -> Vec<Commit> {
-    let response: Vec<Commit> = vec![];
-    if patches.len() != 0 {
-        let mut commits: Vec<Commit> = vec![];
-        for patch in patches {
-            let patch = &package_graph.nodes.get(patch).unwrap().main_derivation;
-            let mut commit = CommitBuilder::default();
-            let commit_url = match patch.get_url() {
-                Some(u) => u,
-                None => {
-                    log::warn!(
-                        "No URL found for {}",
-                        patch.get_name().unwrap_or("unknow derivation".to_string())
-                    );
-                    continue;
-                }
-            };
-            commit.url(commit_url);
-            // TODO we could also populate the uid, which is the commit SHA
-            commits.push(commit.build().unwrap())
-        }
+pub(crate) fn get_commit_urls(
+    package_graph: &crate::nix::PackageGraph,
+    patches: &BTreeSet<String>,
+) -> Vec<String> {
+    let mut commit_urls: Vec<String> = vec![];
+    for patch in patches {
+        let patch = &package_graph.nodes.get(patch).unwrap().main_derivation;
+        let commit_url = match patch.get_url() {
+            Some(u) => u,
+            None => {
+                log::warn!(
+                    "No URL found for {}",
+                    patch.get_name().unwrap_or("unknow derivation".to_string())
+                );
+                continue;
+            }
+        };
+        // TODO we could also populate the uid, which is the commit SHA
+        commit_urls.push(commit_url);
     }
-    response
+    commit_urls
 }
 
-fn get_external_references(package_node: &crate::nix::PackageNode) -> Vec<ExternalReference> {
-    let mut external_references: Vec<ExternalReference> = vec![];
+// Returns the type and the url of the external references of a package.
+// See https://cyclonedx.org/docs/1.4/json/#components_items_externalReferences_items_type
+// for all the available external reference types
+pub(crate) fn get_external_references(package_node: &crate::nix::PackageNode) -> Vec<(String, String)> {
+    let mut external_references: Vec<(String, String)> = vec![];
     let homepages = match &package_node.package {
         Some(p) => p.meta.get_homepages(),
         None => vec![],
     };
     for homepage in homepages {
-        let mut external_reference_builder = ExternalReferenceBuilder::default();
-        // See https://docs.rs/serde-cyclonedx/latest/serde_cyclonedx/cyclonedx/v_1_5/struct.ExternalReference.html#structfield.type_
-        // for all the available external reference types
-        external_reference_builder.type_("website");
-        external_reference_builder.url(homepage.to_string());
-        external_references.push(external_reference_builder.build().unwrap());
+        external_references.push(("website".to_string(), homepage.to_string()));
     }
     // for source in &package_node.sources {
     //     let source_url = match source.get_url() {
@@ -248,53 +855,567 @@ fn get_external_references(package_node: &crate::nix::PackageNode) -> Vec<Extern
     //     };
     //     if let Some(git_url) = crate::utils::get_git_url_from_generic_url(&source_url) {
     //         log::debug!("Found git url {} for source URL {}", &git_url, &source_url);
-    //         let mut external_reference_builder = ExternalReferenceBuilder::default();
-    //         external_reference_builder.type_("vcs");
-    //         external_reference_builder.url(git_url);
-    //         external_references.push(external_reference_builder.build().unwrap());
+    //         external_references.push(("vcs".to_string(), git_url));
     //     }
     // }
     for url in &package_node.main_derivation.get_urls() {
         if let Some(git_url) = crate::utils::get_git_url_from_generic_url(&url) {
             log::debug!("Found git url {} for source URL {}", &git_url, &url);
-            let mut external_reference_builder = ExternalReferenceBuilder::default();
-            external_reference_builder.type_("vcs");
-            external_reference_builder.url(git_url);
-            external_references.push(external_reference_builder.build().unwrap());
+            external_references.push(("vcs".to_string(), git_url));
         }
     }
     external_references
 }
 
-fn get_licenses(package_node: &crate::nix::PackageNode) -> Vec<LicenseChoice> {
-    let mut response: Vec<LicenseChoice> = vec![];
-    let licenses = match &package_node.package {
+// The licenses of a component, as a single license or as a SPDX expression combining them.
+pub(crate) enum ComponentLicense {
+    License(crate::license::NormalizedLicense),
+    Expression(String),
+}
+
+pub(crate) fn get_licenses(
+    package_node: &crate::nix::PackageNode,
+    options: &crate::nix::DumpOptions,
+) -> Option<ComponentLicense> {
+    let package_licenses = match &package_node.package {
         Some(p) => p.meta.get_licenses(),
-        None => vec![],
+        None => return None,
     };
-    for license in licenses {
-        match license {
-            crate::nix::PackageLicense::Name(n) => {
-                response.push(LicenseChoice {
-                    expression: Some(n.to_string()),
-                    license: None,
-                });
+    let licenses = crate::license::normalize_licenses(&package_licenses);
+    if let [license] = licenses.as_slice() {
+        if !license.is_expression {
+            return Some(ComponentLicense::License(license.clone()));
+        }
+    }
+    crate::license::get_license_expression(&licenses, &options.license_join_policy)
+        .map(ComponentLicense::Expression)
+}
+
+// CycloneDX only accepts the IDs of the SPDX license list, the other licenses are identified by name.
+pub(crate) fn get_license_id_and_name(
+    license: &crate::license::NormalizedLicense,
+) -> (Option<String>, Option<String>) {
+    if license.is_license_ref {
+        return (None, Some(license.name.clone().unwrap_or(license.id.to_string())));
+    }
+    (Some(license.id.to_string()), license.name.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_spec_version_from_string() {
+        assert_eq!(SpecVersion::from_string("1.4"), Some(SpecVersion::V1_4));
+        assert_eq!(SpecVersion::from_string("1.5"), Some(SpecVersion::V1_5));
+        assert_eq!(SpecVersion::from_string("1.6"), Some(SpecVersion::V1_6));
+        assert_eq!(SpecVersion::from_string("1.3"), None);
+        assert_eq!(SpecVersion::default().to_string(), "1.4");
+    }
+
+    #[test]
+    pub fn test_dump_is_reproducible() {
+        let package_graph = crate::nix::get_test_package_graph();
+        for format in [
+            crate::sbom::SerializationFormat::JSON,
+            crate::sbom::SerializationFormat::XML,
+        ] {
+            for spec_version in [SpecVersion::V1_4, SpecVersion::V1_5, SpecVersion::V1_6] {
+                let mut options = crate::nix::DumpOptions::default();
+                options.cyclone_dx_spec_version = spec_version;
+                options.timestamp = crate::sbom::parse_timestamp("1700000000");
+                let first_dump = dump(&package_graph, &format, &options).unwrap();
+                let second_dump = dump(&package_graph, &format, &options).unwrap();
+                assert_eq!(first_dump, second_dump);
+                assert!(first_dump.contains("2023-11-14T22:13:20Z"));
+                assert!(first_dump.contains("urn:uuid:"));
             }
-            crate::nix::PackageLicense::Details(license_details) => {
-                let mut license_builder = LicenseBuilder::default();
-                match &license_details.spdx_id {
-                    None => continue,
-                    Some(id) => license_builder.id(id),
-                };
-                if let Some(full_name) = &license_details.full_name {
-                    license_builder.name(full_name);
-                }
-                response.push(LicenseChoice {
-                    expression: None,
-                    license: Some(license_builder.build().unwrap()),
-                });
+        }
+    }
+
+    #[test]
+    pub fn test_sort_arrays() {
+        let mut document = serde_json::json!({
+            "components": [{"bom-ref": "b", "name": "b"}, {"bom-ref": "a", "name": "a"}],
+            "dependencies": [
+                {"ref": "b", "dependsOn": ["d", "c", "c"]},
+                {"ref": "a", "dependsOn": []},
+            ],
+            "pedigree": {"commits": [{"url": "b.patch"}, {"url": "a.patch"}]},
+        });
+        sort_arrays(&mut document);
+        assert_eq!(
+            document,
+            serde_json::json!({
+                "components": [{"bom-ref": "a", "name": "a"}, {"bom-ref": "b", "name": "b"}],
+                "dependencies": [
+                    {"ref": "a", "dependsOn": []},
+                    {"ref": "b", "dependsOn": ["c", "d"]},
+                ],
+                "pedigree": {"commits": [{"url": "b.patch"}, {"url": "a.patch"}]},
+            })
+        );
+    }
+
+    #[test]
+    pub fn test_dump_all_spec_versions() {
+        let package_graph = crate::nix::get_test_package_graph();
+        for spec_version in [SpecVersion::V1_4, SpecVersion::V1_5, SpecVersion::V1_6] {
+            let mut options = crate::nix::DumpOptions::default();
+            options.cyclone_dx_spec_version = spec_version.clone();
+            let dump = dump(&package_graph, &crate::sbom::SerializationFormat::JSON, &options).unwrap();
+            let document: serde_json::Value = serde_json::from_str(&dump).unwrap();
+            assert_eq!(document["specVersion"], spec_version.to_string());
+            assert_eq!(document["components"].as_array().unwrap().len(), 4);
+            let dependencies = document["dependencies"].as_array().unwrap();
+            assert_eq!(dependencies.len(), 1);
+            assert_eq!(
+                dependencies[0]["ref"],
+                "/nix/store/aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa-hello-2.12.1.drv"
+            );
+            let patch = document["components"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .find(|c| c["bom-ref"] == "/nix/store/cccccccccccccccccccccccccccccccc-fix.patch.drv")
+                .unwrap();
+            let glibc = document["components"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .find(|c| c["name"] == "glibc")
+                .unwrap();
+            assert_eq!(glibc["cpe"], "cpe:2.3:a:gnu:glibc:2.38:*:*:*:*:*:*:*");
+            assert_eq!(glibc["type"], "application");
+            assert_eq!(patch["type"], "file");
+            assert_eq!(
+                patch["hashes"],
+                serde_json::json!([{
+                    "alg": "SHA-256",
+                    "content": "8d99142afd92576f30b0cd7cb42a8dc6809998bc5d607d88761f512e26c7db20"
+                }])
+            );
+        }
+    }
+
+    #[test]
+    pub fn test_dump_recursive_hash() {
+        let patch_path = "/nix/store/cccccccccccccccccccccccccccccccc-fix.patch.drv";
+        let mut derivations = crate::nix::get_test_derivations();
+        derivations
+            .get_mut(patch_path)
+            .unwrap()
+            .env
+            .insert("outputHashMode".to_string(), "recursive".to_string());
+        let package_graph = crate::nix::get_package_graph_next(&derivations, &crate::nix::Packages::default());
+
+        let options = crate::nix::DumpOptions::default();
+        let output = dump(&package_graph, &crate::sbom::SerializationFormat::JSON, &options).unwrap();
+        let document: serde_json::Value = serde_json::from_str(&output).unwrap();
+        let patch = document["components"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|c| c["bom-ref"] == patch_path)
+            .unwrap();
+        assert!(patch.get("hashes").is_none());
+        assert_eq!(
+            patch["properties"],
+            serde_json::json!([{
+                "name": NAR_HASH_PROPERTY_NAME,
+                "value": "sha256:8d99142afd92576f30b0cd7cb42a8dc6809998bc5d607d88761f512e26c7db20"
+            }])
+        );
+    }
+
+    #[test]
+    pub fn test_dump_formulation() {
+        let package_graph = crate::nix::get_test_package_graph();
+        let hello_path = "/nix/store/aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa-hello-2.12.1.drv";
+
+        let mut options = crate::nix::DumpOptions::default();
+        options.cyclone_dx_spec_version = SpecVersion::V1_4;
+        let output = dump(&package_graph, &crate::sbom::SerializationFormat::JSON, &options).unwrap();
+        let document: serde_json::Value = serde_json::from_str(&output).unwrap();
+        assert!(document.get("formulation").is_none());
+
+        for spec_version in [SpecVersion::V1_5, SpecVersion::V1_6] {
+            options.cyclone_dx_spec_version = spec_version;
+            let output = dump(&package_graph, &crate::sbom::SerializationFormat::JSON, &options).unwrap();
+            let document: serde_json::Value = serde_json::from_str(&output).unwrap();
+            let formulation = document["formulation"].as_array().unwrap();
+            assert_eq!(formulation.len(), 1);
+            assert_eq!(formulation[0]["bom-ref"], FORMULA_REF);
+            let workflows = formulation[0]["workflows"].as_array().unwrap();
+            assert_eq!(workflows.len(), 4);
+            let hello = workflows.iter().find(|w| w["uid"] == hello_path).unwrap();
+            assert_eq!(hello["bom-ref"], format!("{}{}", WORKFLOW_REF_PREFIX, hello_path));
+            assert_eq!(hello["name"], "hello-2.12.1");
+            assert_eq!(
+                hello["steps"][0]["commands"][0]["executed"],
+                "/nix/store/0rwyq0j954a7143p0wzd4rhycny8i967-bash-5.2-p15/bin/bash"
+            );
+            assert_eq!(hello["inputs"].as_array().unwrap().len(), 3);
+            assert_eq!(hello["outputs"][0]["resource"]["ref"], hello_path);
+            assert!(hello["properties"]
+                .as_array()
+                .unwrap()
+                .contains(&serde_json::json!({ "name": "nix:system", "value": "x86_64-linux" })));
+
+            let patch = workflows
+                .iter()
+                .find(|w| w["uid"] == "/nix/store/cccccccccccccccccccccccccccccccc-fix.patch.drv")
+                .unwrap();
+            assert_eq!(patch["steps"][0]["commands"][0]["executed"], "builtin:fetchurl");
+            assert!(patch.get("inputs").is_none());
+        }
+    }
+
+    #[test]
+    pub fn test_dump_vulnerabilities() {
+        let package_graph = crate::nix::get_test_package_graph();
+        let mut vulnerability = crate::vulnerabilities::Vulnerability::new("CVE-2023-4911");
+        vulnerability.aliases = vec!["GHSA-m77w-6vjw-wh2f".to_string()];
+        vulnerability.ratings.push(crate::vulnerabilities::Rating {
+            score: Some(7.8),
+            severity: Some("high".to_string()),
+            method: Some("CVSSv31".to_string()),
+            vector: None,
+        });
+        vulnerability.affected.insert(
+            "/nix/store/dddddddddddddddddddddddddddddddd-glibc-2.38.drv".to_string(),
+            Some("2.38".to_string()),
+        );
+
+        for spec_version in [SpecVersion::V1_4, SpecVersion::V1_5, SpecVersion::V1_6] {
+            let mut options = crate::nix::DumpOptions::default();
+            options.cyclone_dx_spec_version = spec_version;
+            options.vulnerabilities = vec![vulnerability.clone()];
+            let json = dump(&package_graph, &crate::sbom::SerializationFormat::JSON, &options).unwrap();
+            let document: serde_json::Value = serde_json::from_str(&json).unwrap();
+            let vulnerabilities = document["vulnerabilities"].as_array().unwrap();
+            assert_eq!(vulnerabilities.len(), 1);
+            assert_eq!(vulnerabilities[0]["id"], "CVE-2023-4911");
+            assert_eq!(vulnerabilities[0]["source"]["name"], "NVD");
+            assert_eq!(vulnerabilities[0]["references"][0]["source"]["name"], "OSV");
+            assert_eq!(vulnerabilities[0]["ratings"][0]["severity"], "high");
+            assert_eq!(
+                vulnerabilities[0]["affects"][0]["ref"],
+                "/nix/store/dddddddddddddddddddddddddddddddd-glibc-2.38.drv"
+            );
+
+            let xml = dump(&package_graph, &crate::sbom::SerializationFormat::XML, &options).unwrap();
+            assert!(xml.contains("<id>CVE-2023-4911</id>"));
+        }
+    }
+
+    #[test]
+    pub fn test_dump_insecure_component() {
+        let mut package_graph = crate::nix::get_test_package_graph();
+        crate::nix::set_test_package(
+            &mut package_graph,
+            "/nix/store/dddddddddddddddddddddddddddddddd-glibc-2.38.drv",
+            r###"{
+              "name": "glibc-2.38",
+              "pname": "glibc",
+              "version": "2.38",
+              "system": "x86_64-linux",
+              "outputName": "out",
+              "meta": { "insecure": true, "knownVulnerabilities": ["CVE-2023-4911"] }
+            }"###,
+        );
+
+        let options = crate::nix::DumpOptions::default();
+        let json = dump(&package_graph, &crate::sbom::SerializationFormat::JSON, &options).unwrap();
+        let document: serde_json::Value = serde_json::from_str(&json).unwrap();
+        let glibc = document["components"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|c| c["name"] == "glibc")
+            .unwrap();
+        assert_eq!(
+            glibc["properties"],
+            serde_json::json!([
+                { "name": INSECURE_PROPERTY_NAME, "value": "true" },
+                { "name": KNOWN_VULNERABILITY_PROPERTY_NAME, "value": "CVE-2023-4911" },
+            ])
+        );
+        assert_eq!(document["vulnerabilities"][0]["id"], "CVE-2023-4911");
+        assert_eq!(
+            document["vulnerabilities"][0]["affects"][0]["ref"],
+            "/nix/store/dddddddddddddddddddddddddddddddd-glibc-2.38.drv"
+        );
+    }
+
+    #[test]
+    pub fn test_dump_package_metadata() {
+        let package_graph = crate::nix::get_package_graph_next(
+            &crate::nix::get_test_derivations(),
+            &crate::nix::get_test_packages(),
+        );
+        for spec_version in [SpecVersion::V1_4, SpecVersion::V1_5, SpecVersion::V1_6] {
+            let options = crate::nix::DumpOptions {
+                cyclone_dx_spec_version: spec_version,
+                ..Default::default()
+            };
+            let json = dump(&package_graph, &crate::sbom::SerializationFormat::JSON, &options).unwrap();
+            let document: serde_json::Value = serde_json::from_str(&json).unwrap();
+            let components = document["components"].as_array().unwrap();
+
+            let hello = components.iter().find(|c| c["name"] == "hello").unwrap();
+            assert_eq!(hello["type"], "application");
+            assert_eq!(hello["licenses"][0]["license"]["id"], "GPL-3.0-or-later");
+
+            let glibc = components.iter().find(|c| c["name"] == "glibc").unwrap();
+            assert_eq!(
+                glibc["licenses"],
+                serde_json::json!([{ "expression": "LGPL-2.1-or-later AND LicenseRef-nixpkgs-unfree" }])
+            );
+            let properties = glibc["properties"].as_array().unwrap();
+            assert!(
+                properties.contains(&serde_json::json!({ "name": INSECURE_PROPERTY_NAME, "value": "true" }))
+            );
+            assert!(properties.contains(&serde_json::json!({
+                "name": KNOWN_VULNERABILITY_PROPERTY_NAME,
+                "value": "CVE-2023-4911: buffer overflow in the dynamic loader",
+            })));
+
+            let vulnerabilities = document["vulnerabilities"].as_array().unwrap();
+            assert_eq!(vulnerabilities.len(), 1);
+            assert_eq!(vulnerabilities[0]["id"], "CVE-2023-4911");
+            assert_eq!(
+                vulnerabilities[0]["affects"][0]["ref"],
+                "/nix/store/dddddddddddddddddddddddddddddddd-glibc-2.38.drv"
+            );
+        }
+    }
+
+    #[test]
+    pub fn test_dump_licenses() {
+        let mut package_graph = crate::nix::get_test_package_graph();
+        let packages = [
+            (
+                "/nix/store/aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa-hello-2.12.1.drv",
+                r###"{ "name": "hello-2.12.1", "pname": "hello", "version": "2.12.1", "system": "x86_64-linux",
+                       "outputName": "out", "meta": { "license": { "shortName": "unfree", "fullName": "Unfree" } } }"###,
+            ),
+            (
+                "/nix/store/dddddddddddddddddddddddddddddddd-glibc-2.38.drv",
+                r###"{ "name": "glibc-2.38", "pname": "glibc", "version": "2.38", "system": "x86_64-linux",
+                       "outputName": "out", "meta": { "license": [
+                         { "spdxId": "LGPL-2.1-or-later", "shortName": "lgpl21Plus" },
+                         { "shortName": "unfree", "fullName": "Unfree" }
+                       ] } }"###,
+            ),
+        ];
+        for (derivation_path, package) in packages {
+            crate::nix::set_test_package(&mut package_graph, derivation_path, package);
+        }
+
+        for spec_version in [SpecVersion::V1_4, SpecVersion::V1_5, SpecVersion::V1_6] {
+            let options = crate::nix::DumpOptions {
+                cyclone_dx_spec_version: spec_version,
+                license_join_policy: crate::license::LicenseJoinPolicy::Or,
+                ..Default::default()
+            };
+            let json = dump(&package_graph, &crate::sbom::SerializationFormat::JSON, &options).unwrap();
+            let document: serde_json::Value = serde_json::from_str(&json).unwrap();
+            let components = document["components"].as_array().unwrap();
+            let hello = components.iter().find(|c| c["name"] == "hello").unwrap();
+            assert_eq!(
+                hello["licenses"],
+                serde_json::json!([{ "license": { "name": "Unfree" } }])
+            );
+            let glibc = components.iter().find(|c| c["name"] == "glibc").unwrap();
+            assert_eq!(
+                glibc["licenses"],
+                serde_json::json!([{ "expression": "LGPL-2.1-or-later OR LicenseRef-nixpkgs-unfree" }])
+            );
+        }
+    }
+
+    #[test]
+    pub fn test_dump_license_flags() {
+        let mut package_graph = crate::nix::get_test_package_graph();
+        crate::nix::set_test_package(
+            &mut package_graph,
+            "/nix/store/dddddddddddddddddddddddddddddddd-glibc-2.38.drv",
+            r###"{ "name": "glibc-2.38", "pname": "glibc", "version": "2.38", "system": "x86_64-linux",
+                   "outputName": "out", "meta": { "unfree": true, "license": {
+                     "shortName": "unfreeRedistributable", "fullName": "Unfree redistributable",
+                     "free": false, "redistributable": true, "deprecated": false
+                   } } }"###,
+        );
+
+        for spec_version in [SpecVersion::V1_4, SpecVersion::V1_5, SpecVersion::V1_6] {
+            let options = crate::nix::DumpOptions {
+                cyclone_dx_spec_version: spec_version.clone(),
+                ..Default::default()
+            };
+            let json = dump(&package_graph, &crate::sbom::SerializationFormat::JSON, &options).unwrap();
+            let document: serde_json::Value = serde_json::from_str(&json).unwrap();
+            let glibc = document["components"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .find(|c| c["name"] == "glibc")
+                .unwrap();
+            assert_eq!(
+                glibc["properties"],
+                serde_json::json!([
+                    { "name": REDISTRIBUTABLE_PROPERTY_NAME, "value": "true" },
+                    { "name": UNFREE_PROPERTY_NAME, "value": "true" },
+                ])
+            );
+            // Licenses only have properties starting with 1.5.
+            let license_properties = match spec_version {
+                SpecVersion::V1_4 => serde_json::Value::Null,
+                _ => serde_json::json!([
+                    { "name": LICENSE_DEPRECATED_PROPERTY_NAME, "value": "false" },
+                    { "name": LICENSE_FREE_PROPERTY_NAME, "value": "false" },
+                    { "name": LICENSE_REDISTRIBUTABLE_PROPERTY_NAME, "value": "true" },
+                ]),
+            };
+            assert_eq!(glibc["licenses"][0]["license"]["properties"], license_properties);
+
+            let xml = dump(&package_graph, &crate::sbom::SerializationFormat::XML, &options).unwrap();
+            assert_eq!(
+                xml.contains(&format!(
+                    "<property name=\"{}\">false</property>",
+                    LICENSE_FREE_PROPERTY_NAME
+                )),
+                spec_version != SpecVersion::V1_4
+            );
+            assert!(!xml.contains("<name>nixpkgs:license:"));
+        }
+    }
+
+    #[test]
+    pub fn test_dump_flake_inputs() {
+        let package_graph = crate::nix::get_test_package_graph();
+        for spec_version in [SpecVersion::V1_4, SpecVersion::V1_5, SpecVersion::V1_6] {
+            let options = crate::nix::DumpOptions {
+                cyclone_dx_spec_version: spec_version,
+                flake_lock: Some(crate::flake_lock::get_test_flake_lock()),
+                ..Default::default()
+            };
+            let json = dump(&package_graph, &crate::sbom::SerializationFormat::JSON, &options).unwrap();
+            let document: serde_json::Value = serde_json::from_str(&json).unwrap();
+            let nixpkgs = document["components"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .find(|c| c["bom-ref"] == "flake-input:nixpkgs")
+                .unwrap();
+            assert_eq!(nixpkgs["name"], "nixpkgs");
+            assert_eq!(nixpkgs["version"], "20f77aa09916374aa3141cbc605c955626762c9a");
+            assert_eq!(
+                nixpkgs["purl"],
+                "pkg:github/nixos/nixpkgs@20f77aa09916374aa3141cbc605c955626762c9a"
+            );
+            assert_eq!(
+                nixpkgs["externalReferences"],
+                serde_json::json!([{ "type": "vcs", "url": "https://github.com/NixOS/nixpkgs" }])
+            );
+            assert!(nixpkgs.get("hashes").is_none());
+            assert_eq!(
+                nixpkgs["properties"],
+                serde_json::json!([
+                    { "name": FLAKE_INPUT_LAST_MODIFIED_PROPERTY_NAME, "value": "1711001935" },
+                    { "name": FLAKE_INPUT_NAR_HASH_PROPERTY_NAME, "value": "sha256-URtGpHue7HHZK0mrHnSf8wJ6OmMKYSsoLmJybrOLFSQ=" },
+                    { "name": FLAKE_INPUT_REV_PROPERTY_NAME, "value": "20f77aa09916374aa3141cbc605c955626762c9a" },
+                    { "name": FLAKE_INPUT_TYPE_PROPERTY_NAME, "value": "github" },
+                ])
+            );
+
+            let dependencies = document["dependencies"].as_array().unwrap();
+            let get_depends_on = |bom_ref: &str| {
+                dependencies
+                    .iter()
+                    .find(|d| d["ref"] == bom_ref)
+                    .map(|d| d["dependsOn"].clone())
+                    .unwrap()
+            };
+            let hello_dependencies =
+                get_depends_on("/nix/store/aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa-hello-2.12.1.drv");
+            for input in [
+                "flake-input:flake-utils",
+                "flake-input:home-manager",
+                "flake-input:nixpkgs",
+            ] {
+                assert!(hello_dependencies
+                    .as_array()
+                    .unwrap()
+                    .contains(&serde_json::json!(input)));
             }
+            assert_eq!(
+                get_depends_on("flake-input:home-manager"),
+                serde_json::json!(["flake-input:nixpkgs"])
+            );
+            assert_eq!(
+                get_depends_on("flake-input:flake-utils"),
+                serde_json::json!(["flake-input:systems"])
+            );
+        }
+    }
+
+    #[test]
+    pub fn test_dump_system_metadata() {
+        let package_graph = crate::nix::get_test_package_graph();
+        for spec_version in [SpecVersion::V1_4, SpecVersion::V1_5, SpecVersion::V1_6] {
+            let options = crate::nix::DumpOptions {
+                cyclone_dx_spec_version: spec_version,
+                system_metadata: crate::nixos::SystemMetadata {
+                    name: Some("laptop".to_string()),
+                    nixos_version: Some("24.05.20240315.20f77aa".to_string()),
+                    nixpkgs_revision: Some("20f77aa09916374aa3141cbc605c955626762c9a".to_string()),
+                    nixpkgs_ref: Some("nixos-unstable".to_string()),
+                    nixpkgs_last_modified: Some(1711001935),
+                },
+                ..Default::default()
+            };
+            let json = dump(&package_graph, &crate::sbom::SerializationFormat::JSON, &options).unwrap();
+            let document: serde_json::Value = serde_json::from_str(&json).unwrap();
+            let expected_properties = serde_json::json!([
+                { "name": NIXOS_VERSION_PROPERTY_NAME, "value": "24.05.20240315.20f77aa" },
+                { "name": NIXPKGS_LAST_MODIFIED_PROPERTY_NAME, "value": "1711001935" },
+                { "name": NIXPKGS_REF_PROPERTY_NAME, "value": "nixos-unstable" },
+                { "name": NIXPKGS_REVISION_PROPERTY_NAME, "value": "20f77aa09916374aa3141cbc605c955626762c9a" },
+            ]);
+            assert_eq!(document["metadata"]["properties"], expected_properties);
+
+            let system = &document["metadata"]["component"];
+            assert_eq!(system["type"], "operating-system");
+            assert_eq!(system["bom-ref"], SYSTEM_COMPONENT_REF);
+            assert_eq!(system["name"], "laptop");
+            assert_eq!(system["version"], "24.05.20240315.20f77aa");
+            assert_eq!(system["properties"], expected_properties);
+
+            let system_dependencies = document["dependencies"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .find(|d| d["ref"] == SYSTEM_COMPONENT_REF)
+                .map(|d| d["dependsOn"].clone())
+                .unwrap();
+            assert_eq!(
+                system_dependencies,
+                serde_json::json!(["/nix/store/aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa-hello-2.12.1.drv"])
+            );
         }
+
+        // Building a flake which is not a NixOS system only records the revision of nixpkgs.
+        let options = crate::nix::DumpOptions {
+            system_metadata: crate::nixos::SystemMetadata::from_flake_lock(
+                &crate::flake_lock::get_test_flake_lock(),
+            ),
+            ..Default::default()
+        };
+        let json = dump(&package_graph, &crate::sbom::SerializationFormat::JSON, &options).unwrap();
+        let document: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert!(document["metadata"].get("component").is_none());
+        assert_eq!(document["metadata"]["properties"].as_array().unwrap().len(), 3);
     }
-    response
 }
@@ -0,0 +1,933 @@
+// XML writer for the CycloneDX documents.
+//
+// The writer works on the JSON representation of the document, so that it can be shared
+// between all the versions of the spec. The XML schema is stricter than the JSON schema:
+// some fields are attributes, lists have a wrapper element, and the children of an
+// element have to follow the order of the xs:sequence defined in the schema.
+// See https://cyclonedx.org/docs/1.6/xml/
+use serde_json::Value;
+
+const XML_NAMESPACE_PREFIX: &str = "http://cyclonedx.org/schema/bom/";
+
+// The order of the top-level elements in the bom element.
+const BOM_FIELDS: &[&str] = &[
+    "metadata",
+    "components",
+    "services",
+    "externalReferences",
+    "dependencies",
+    "compositions",
+    "properties",
+    "vulnerabilities",
+    "annotations",
+    "formulation",
+];
+
+const METADATA_FIELDS: &[&str] = &[
+    "timestamp",
+    "lifecycles",
+    "tools",
+    "authors",
+    "component",
+    "manufacture",
+    "manufacturer",
+    "supplier",
+    "licenses",
+    "properties",
+];
+
+const COMPONENT_FIELDS: &[&str] = &[
+    "supplier",
+    "manufacturer",
+    "authors",
+    "author",
+    "publisher",
+    "group",
+    "name",
+    "version",
+    "description",
+    "scope",
+    "hashes",
+    "licenses",
+    "copyright",
+    "cpe",
+    "purl",
+    "omniborId",
+    "swhid",
+    "swid",
+    "modified",
+    "pedigree",
+    "externalReferences",
+    "properties",
+    "components",
+    "evidence",
+    "releaseNotes",
+];
+
+const PEDIGREE_FIELDS: &[&str] = &[
+    "ancestors",
+    "descendants",
+    "variants",
+    "commits",
+    "patches",
+    "notes",
+];
+
+const COMMIT_FIELDS: &[&str] = &["uid", "url", "author", "committer", "message"];
+
+const TOOL_FIELDS: &[&str] = &["vendor", "name", "version", "hashes", "externalReferences"];
+
+const LICENSE_FIELDS: &[&str] = &["id", "name", "text", "url", "licensing", "properties"];
+
+// The order of the elements of a vulnerability. We only use a subset of them.
+const VULNERABILITY_FIELDS: &[&str] = &[
+    "id",
+    "source",
+    "references",
+    "ratings",
+    "cwes",
+    "description",
+    "detail",
+    "recommendation",
+    "workaround",
+    "advisories",
+    "created",
+    "published",
+    "updated",
+    "affects",
+    "properties",
+];
+
+// The order of the elements of the formulas, and of the workflows, steps, inputs and outputs
+// they contain. The bom-refs are attributes.
+const FORMULA_FIELDS: &[&str] = &["components", "services", "workflows", "properties"];
+
+const WORKFLOW_FIELDS: &[&str] = &[
+    "uid",
+    "name",
+    "description",
+    "resourceReferences",
+    "tasks",
+    "taskDependencies",
+    "taskTypes",
+    "trigger",
+    "steps",
+    "inputs",
+    "outputs",
+    "timeStart",
+    "timeEnd",
+    "workspaces",
+    "runtimeTopology",
+    "properties",
+];
+
+const STEP_FIELDS: &[&str] = &["name", "description", "commands", "properties"];
+
+const COMMAND_FIELDS: &[&str] = &["executed", "properties"];
+
+const INPUT_FIELDS: &[&str] = &[
+    "source",
+    "target",
+    "resource",
+    "parameters",
+    "environmentVars",
+    "data",
+    "properties",
+];
+
+const OUTPUT_FIELDS: &[&str] = &[
+    "type",
+    "source",
+    "target",
+    "resource",
+    "environmentVars",
+    "data",
+    "properties",
+];
+
+const VULNERABILITY_SOURCE_FIELDS: &[&str] = &["name", "url"];
+
+const RATING_FIELDS: &[&str] = &["source", "score", "severity", "method", "vector", "justification"];
+
+const ORGANIZATIONAL_ENTITY_FIELDS: &[&str] = &["name", "url", "contact"];
+
+const ORGANIZATIONAL_CONTACT_FIELDS: &[&str] = &["name", "email", "phone"];
+
+struct XmlWriter {
+    lines: Vec<String>,
+    depth: usize,
+}
+
+impl XmlWriter {
+    fn new() -> XmlWriter {
+        XmlWriter {
+            lines: vec![r#"<?xml version="1.0" encoding="UTF-8"?>"#.to_string()],
+            depth: 0,
+        }
+    }
+
+    fn open(&mut self, name: &str, attributes: &[(&str, String)]) {
+        self.push(format!("<{}{}>", name, format_attributes(attributes)));
+        self.depth += 1;
+    }
+
+    fn close(&mut self, name: &str) {
+        self.depth -= 1;
+        self.push(format!("</{}>", name));
+    }
+
+    fn leaf(&mut self, name: &str, attributes: &[(&str, String)], text: &str) {
+        self.push(format!(
+            "<{}{}>{}</{}>",
+            name,
+            format_attributes(attributes),
+            escape(text),
+            name
+        ));
+    }
+
+    fn empty(&mut self, name: &str, attributes: &[(&str, String)]) {
+        self.push(format!("<{}{} />", name, format_attributes(attributes)));
+    }
+
+    fn push(&mut self, line: String) {
+        self.lines.push("  ".repeat(self.depth) + &line);
+    }
+
+    fn to_string(&self) -> String {
+        self.lines.join("\n") + "\n"
+    }
+}
+
+pub fn to_xml(document: &Value, spec_version: &str) -> Result<String, String> {
+    let document = match document.as_object() {
+        Some(d) => d,
+        None => return Err("The CycloneDX document is not an object".to_string()),
+    };
+
+    let mut writer = XmlWriter::new();
+    let mut attributes: Vec<(&str, String)> =
+        vec![("xmlns", format!("{}{}", XML_NAMESPACE_PREFIX, spec_version))];
+    if let Some(serial_number) = document.get("serialNumber") {
+        attributes.push(("serialNumber", get_text(serial_number)));
+    }
+    if let Some(version) = document.get("version") {
+        attributes.push(("version", get_text(version)));
+    }
+    writer.open("bom", &attributes);
+
+    for field in BOM_FIELDS {
+        let value = match document.get(*field) {
+            Some(v) => v,
+            None => continue,
+        };
+        match *field {
+            "metadata" => write_metadata(&mut writer, value),
+            "components" => write_components(&mut writer, "components", value),
+            "externalReferences" => write_external_references(&mut writer, value),
+            "dependencies" => write_dependencies(&mut writer, value),
+            "properties" => write_properties(&mut writer, value),
+            "vulnerabilities" => write_vulnerabilities(&mut writer, value),
+            "formulation" => write_formulation(&mut writer, value),
+            _ => write_generic(&mut writer, field, value),
+        }
+    }
+
+    writer.close("bom");
+    Ok(writer.to_string())
+}
+
+fn write_metadata(writer: &mut XmlWriter, metadata: &Value) {
+    writer.open("metadata", &[]);
+    for field in METADATA_FIELDS {
+        let value = match metadata.get(*field) {
+            Some(v) => v,
+            None => continue,
+        };
+        match *field {
+            "lifecycles" => write_lifecycles(writer, value),
+            "tools" => write_tools(writer, value),
+            "authors" => write_list(writer, "authors", "author", value, ORGANIZATIONAL_CONTACT_FIELDS),
+            "component" => write_component(writer, value),
+            "manufacture" | "manufacturer" | "supplier" => {
+                write_object(writer, field, &[], value, ORGANIZATIONAL_ENTITY_FIELDS)
+            }
+            "licenses" => write_licenses(writer, value),
+            "properties" => write_properties(writer, value),
+            _ => write_generic(writer, field, value),
+        }
+    }
+    writer.close("metadata");
+}
+
+fn write_lifecycles(writer: &mut XmlWriter, lifecycles: &Value) {
+    write_list(
+        writer,
+        "lifecycles",
+        "lifecycle",
+        lifecycles,
+        &["phase", "name", "description"],
+    );
+}
+
+fn write_tools(writer: &mut XmlWriter, tools: &Value) {
+    writer.open("tools", &[]);
+    match tools {
+        // The legacy format, which is the only one supported by 1.4.
+        Value::Array(tools) => {
+            for tool in tools {
+                write_object(writer, "tool", &[], tool, TOOL_FIELDS);
+            }
+        }
+        _ => {
+            if let Some(components) = tools.get("components") {
+                write_components(writer, "components", components);
+            }
+            if let Some(services) = tools.get("services") {
+                write_generic(writer, "services", services);
+            }
+        }
+    }
+    writer.close("tools");
+}
+
+fn write_components(writer: &mut XmlWriter, name: &str, components: &Value) {
+    writer.open(name, &[]);
+    for component in components.as_array().unwrap_or(&vec![]) {
+        write_component(writer, component);
+    }
+    writer.close(name);
+}
+
+fn write_component(writer: &mut XmlWriter, component: &Value) {
+    let attributes = get_attributes(
+        component,
+        &[
+            ("type", "type"),
+            ("mime-type", "mime-type"),
+            ("bom-ref", "bom-ref"),
+        ],
+    );
+    writer.open("component", &attributes);
+    for field in COMPONENT_FIELDS {
+        let value = match component.get(*field) {
+            Some(v) => v,
+            None => continue,
+        };
+        match *field {
+            "supplier" | "manufacturer" => {
+                write_object(writer, field, &[], value, ORGANIZATIONAL_ENTITY_FIELDS)
+            }
+            "authors" => write_list(writer, "authors", "author", value, ORGANIZATIONAL_CONTACT_FIELDS),
+            "hashes" => write_hashes(writer, value),
+            "licenses" => write_licenses(writer, value),
+            "pedigree" => write_pedigree(writer, value),
+            "externalReferences" => write_external_references(writer, value),
+            "properties" => write_properties(writer, value),
+            "components" => write_components(writer, "components", value),
+            _ => write_generic(writer, field, value),
+        }
+    }
+    writer.close("component");
+}
+
+fn write_hashes(writer: &mut XmlWriter, hashes: &Value) {
+    writer.open("hashes", &[]);
+    for hash in hashes.as_array().unwrap_or(&vec![]) {
+        let attributes = get_attributes(hash, &[("alg", "alg")]);
+        let content = hash.get("content").map(get_text).unwrap_or_default();
+        writer.leaf("hash", &attributes, &content);
+    }
+    writer.close("hashes");
+}
+
+// A license choice is either a list of licenses, or a license expression.
+fn write_licenses(writer: &mut XmlWriter, licenses: &Value) {
+    writer.open("licenses", &[]);
+    for license_choice in licenses.as_array().unwrap_or(&vec![]) {
+        if let Some(license) = license_choice.get("license") {
+            let attributes = get_attributes(
+                license,
+                &[("bom-ref", "bom-ref"), ("acknowledgement", "acknowledgement")],
+            );
+            write_object(writer, "license", &attributes, license, LICENSE_FIELDS);
+        }
+        if let Some(expression) = license_choice.get("expression") {
+            let attributes = get_attributes(
+                license_choice,
+                &[("bom-ref", "bom-ref"), ("acknowledgement", "acknowledgement")],
+            );
+            writer.leaf("expression", &attributes, &get_text(expression));
+        }
+    }
+    writer.close("licenses");
+}
+
+fn write_pedigree(writer: &mut XmlWriter, pedigree: &Value) {
+    writer.open("pedigree", &[]);
+    for field in PEDIGREE_FIELDS {
+        let value = match pedigree.get(*field) {
+            Some(v) => v,
+            None => continue,
+        };
+        match *field {
+            "ancestors" | "descendants" | "variants" => write_components(writer, field, value),
+            "commits" => write_list(writer, "commits", "commit", value, COMMIT_FIELDS),
+            _ => write_generic(writer, field, value),
+        }
+    }
+    writer.close("pedigree");
+}
+
+fn write_external_references(writer: &mut XmlWriter, external_references: &Value) {
+    writer.open("externalReferences", &[]);
+    for external_reference in external_references.as_array().unwrap_or(&vec![]) {
+        let attributes = get_attributes(external_reference, &[("type", "type")]);
+        writer.open("reference", &attributes);
+        if let Some(url) = external_reference.get("url") {
+            writer.leaf("url", &[], &get_text(url));
+        }
+        if let Some(comment) = external_reference.get("comment") {
+            writer.leaf("comment", &[], &get_text(comment));
+        }
+        if let Some(hashes) = external_reference.get("hashes") {
+            write_hashes(writer, hashes);
+        }
+        writer.close("reference");
+    }
+    writer.close("externalReferences");
+}
+
+fn write_dependencies(writer: &mut XmlWriter, dependencies: &Value) {
+    writer.open("dependencies", &[]);
+    for dependency in dependencies.as_array().unwrap_or(&vec![]) {
+        let attributes = get_attributes(dependency, &[("ref", "ref")]);
+        let depends_on = dependency
+            .get("dependsOn")
+            .and_then(|d| d.as_array().cloned())
+            .unwrap_or_default();
+        if depends_on.len() == 0 {
+            writer.empty("dependency", &attributes);
+            continue;
+        }
+        writer.open("dependency", &attributes);
+        for child in depends_on {
+            writer.empty("dependency", &[("ref", get_text(&child))]);
+        }
+        writer.close("dependency");
+    }
+    writer.close("dependencies");
+}
+
+fn write_properties(writer: &mut XmlWriter, properties: &Value) {
+    writer.open("properties", &[]);
+    for property in properties.as_array().unwrap_or(&vec![]) {
+        let attributes = get_attributes(property, &[("name", "name")]);
+        let value = property.get("value").map(get_text).unwrap_or_default();
+        writer.leaf("property", &attributes, &value);
+    }
+    writer.close("properties");
+}
+
+fn write_vulnerabilities(writer: &mut XmlWriter, vulnerabilities: &Value) {
+    writer.open("vulnerabilities", &[]);
+    for vulnerability in vulnerabilities.as_array().unwrap_or(&vec![]) {
+        let attributes = get_attributes(vulnerability, &[("bom-ref", "bom-ref")]);
+        writer.open("vulnerability", &attributes);
+        for field in VULNERABILITY_FIELDS {
+            let value = match vulnerability.get(*field) {
+                Some(v) => v,
+                None => continue,
+            };
+            match *field {
+                "source" => write_object(writer, "source", &[], value, VULNERABILITY_SOURCE_FIELDS),
+                "references" => {
+                    writer.open("references", &[]);
+                    for reference in value.as_array().unwrap_or(&vec![]) {
+                        writer.open("reference", &[]);
+                        if let Some(id) = reference.get("id") {
+                            writer.leaf("id", &[], &get_text(id));
+                        }
+                        if let Some(source) = reference.get("source") {
+                            write_object(writer, "source", &[], source, VULNERABILITY_SOURCE_FIELDS);
+                        }
+                        writer.close("reference");
+                    }
+                    writer.close("references");
+                }
+                "ratings" => {
+                    writer.open("ratings", &[]);
+                    for rating in value.as_array().unwrap_or(&vec![]) {
+                        writer.open("rating", &[]);
+                        for rating_field in RATING_FIELDS {
+                            let rating_value = match rating.get(*rating_field) {
+                                Some(v) => v,
+                                None => continue,
+                            };
+                            match *rating_field {
+                                "source" => write_object(
+                                    writer,
+                                    "source",
+                                    &[],
+                                    rating_value,
+                                    VULNERABILITY_SOURCE_FIELDS,
+                                ),
+                                _ => write_generic(writer, rating_field, rating_value),
+                            }
+                        }
+                        writer.close("rating");
+                    }
+                    writer.close("ratings");
+                }
+                "cwes" => write_list_of_scalars(writer, "cwes", "cwe", value),
+                "advisories" => write_list(writer, "advisories", "advisory", value, &["title", "url"]),
+                "affects" => write_affects(writer, value),
+                "properties" => write_properties(writer, value),
+                _ => write_generic(writer, field, value),
+            }
+        }
+        writer.close("vulnerability");
+    }
+    writer.close("vulnerabilities");
+}
+
+fn write_affects(writer: &mut XmlWriter, affects: &Value) {
+    writer.open("affects", &[]);
+    for target in affects.as_array().unwrap_or(&vec![]) {
+        writer.open("target", &[]);
+        if let Some(reference) = target.get("ref") {
+            writer.leaf("ref", &[], &get_text(reference));
+        }
+        if let Some(versions) = target.get("versions") {
+            write_list(
+                writer,
+                "versions",
+                "version",
+                versions,
+                &["version", "range", "status"],
+            );
+        }
+        writer.close("target");
+    }
+    writer.close("affects");
+}
+
+fn write_formulation(writer: &mut XmlWriter, formulation: &Value) {
+    writer.open("formulation", &[]);
+    for formula in formulation.as_array().unwrap_or(&vec![]) {
+        writer.open("formula", &get_attributes(formula, &[("bom-ref", "bom-ref")]));
+        for field in FORMULA_FIELDS {
+            let value = match formula.get(*field) {
+                Some(v) => v,
+                None => continue,
+            };
+            match *field {
+                "components" => write_components(writer, "components", value),
+                "workflows" => write_workflows(writer, value),
+                "properties" => write_properties(writer, value),
+                _ => write_generic(writer, field, value),
+            }
+        }
+        writer.close("formula");
+    }
+    writer.close("formulation");
+}
+
+fn write_workflows(writer: &mut XmlWriter, workflows: &Value) {
+    writer.open("workflows", &[]);
+    for workflow in workflows.as_array().unwrap_or(&vec![]) {
+        writer.open("workflow", &get_attributes(workflow, &[("bom-ref", "bom-ref")]));
+        for field in WORKFLOW_FIELDS {
+            let value = match workflow.get(*field) {
+                Some(v) => v,
+                None => continue,
+            };
+            match *field {
+                "taskTypes" => write_list_of_scalars(writer, "taskTypes", "taskType", value),
+                "steps" => write_list_with_properties(writer, "steps", "step", value, STEP_FIELDS),
+                "inputs" => write_list_with_properties(writer, "inputs", "input", value, INPUT_FIELDS),
+                "outputs" => write_list_with_properties(writer, "outputs", "output", value, OUTPUT_FIELDS),
+                "properties" => write_properties(writer, value),
+                _ => write_generic(writer, field, value),
+            }
+        }
+        writer.close("workflow");
+    }
+    writer.close("workflows");
+}
+
+// Like write_list, for the items which can have properties or commands.
+fn write_list_with_properties(
+    writer: &mut XmlWriter,
+    name: &str,
+    item_name: &str,
+    items: &Value,
+    item_fields: &[&str],
+) {
+    writer.open(name, &[]);
+    for item in items.as_array().unwrap_or(&vec![]) {
+        writer.open(item_name, &[]);
+        for field in item_fields {
+            let value = match item.get(*field) {
+                Some(v) => v,
+                None => continue,
+            };
+            match *field {
+                "commands" => write_list_with_properties(writer, "commands", "command", value, COMMAND_FIELDS),
+                "properties" => write_properties(writer, value),
+                _ => write_generic(writer, field, value),
+            }
+        }
+        writer.close(item_name);
+    }
+    writer.close(name);
+}
+
+fn write_list_of_scalars(writer: &mut XmlWriter, name: &str, item_name: &str, items: &Value) {
+    writer.open(name, &[]);
+    for item in items.as_array().unwrap_or(&vec![]) {
+        writer.leaf(item_name, &[], &get_text(item));
+    }
+    writer.close(name);
+}
+
+// Writes a list wrapped in a parent element, for example <commits><commit>...</commit></commits>
+fn write_list(writer: &mut XmlWriter, name: &str, item_name: &str, items: &Value, item_fields: &[&str]) {
+    writer.open(name, &[]);
+    for item in items.as_array().unwrap_or(&vec![]) {
+        write_object(writer, item_name, &[], item, item_fields);
+    }
+    writer.close(name);
+}
+
+// Writes an object using the provided order for the child elements.
+fn write_object(
+    writer: &mut XmlWriter,
+    name: &str,
+    attributes: &[(&str, String)],
+    object: &Value,
+    fields: &[&str],
+) {
+    writer.open(name, attributes);
+    for field in fields {
+        let value = match object.get(*field) {
+            Some(v) => v,
+            None => continue,
+        };
+        match *field {
+            "properties" => write_properties(writer, value),
+            _ => write_generic(writer, field, value),
+        }
+    }
+    writer.close(name);
+}
+
+// Fallback used for the fields that don't need any special handling.
+// Lists of scalar values are written as repeated elements.
+fn write_generic(writer: &mut XmlWriter, name: &str, value: &Value) {
+    match value {
+        Value::Null => {}
+        Value::Array(items) => {
+            for item in items {
+                write_generic(writer, name, item);
+            }
+        }
+        Value::Object(object) => {
+            writer.open(name, &[]);
+            for (key, child) in object {
+                write_generic(writer, key, child);
+            }
+            writer.close(name);
+        }
+        _ => writer.leaf(name, &[], &get_text(value)),
+    }
+}
+
+fn get_attributes<'a>(object: &Value, attribute_names: &[(&'a str, &str)]) -> Vec<(&'a str, String)> {
+    let mut attributes: Vec<(&str, String)> = vec![];
+    for (attribute_name, field_name) in attribute_names {
+        if let Some(value) = object.get(*field_name) {
+            attributes.push((attribute_name, get_text(value)));
+        }
+    }
+    attributes
+}
+
+fn get_text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.to_string(),
+        Value::Null => "".to_string(),
+        _ => value.to_string(),
+    }
+}
+
+fn format_attributes(attributes: &[(&str, String)]) -> String {
+    attributes
+        .iter()
+        .map(|(name, value)| format!(" {}=\"{}\"", name, escape(value)))
+        .collect::<Vec<String>>()
+        .join("")
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_escape() {
+        assert_eq!(escape("a < b && \"c\""), "a &lt; b &amp;&amp; &quot;c&quot;");
+    }
+
+    #[test]
+    pub fn test_component_to_xml() {
+        let document = serde_json::json!({
+            "bomFormat": "CycloneDX",
+            "specVersion": "1.4",
+            "version": 1,
+            "components": [
+                {
+                    "type": "application",
+                    "bom-ref": "/nix/store/aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa-hello-2.12.1.drv",
+                    "purl": "pkg:generic/hello@2.12.1",
+                    "name": "hello",
+                    "version": "2.12.1",
+                    "licenses": [
+                        { "license": { "id": "GPL-3.0-or-later" } },
+                    ],
+                    "externalReferences": [
+                        { "type": "website", "url": "https://www.gnu.org/software/hello/" },
+                    ],
+                },
+            ],
+            "dependencies": [
+                {
+                    "ref": "/nix/store/aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa-hello-2.12.1.drv",
+                    "dependsOn": ["/nix/store/dddddddddddddddddddddddddddddddd-glibc-2.38.drv"],
+                },
+            ],
+        });
+        let xml = to_xml(&document, "1.4").unwrap();
+        let expected = r#"<?xml version="1.0" encoding="UTF-8"?>
+<bom xmlns="http://cyclonedx.org/schema/bom/1.4" version="1">
+  <components>
+    <component type="application" bom-ref="/nix/store/aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa-hello-2.12.1.drv">
+      <name>hello</name>
+      <version>2.12.1</version>
+      <licenses>
+        <license>
+          <id>GPL-3.0-or-later</id>
+        </license>
+      </licenses>
+      <purl>pkg:generic/hello@2.12.1</purl>
+      <externalReferences>
+        <reference type="website">
+          <url>https://www.gnu.org/software/hello/</url>
+        </reference>
+      </externalReferences>
+    </component>
+  </components>
+  <dependencies>
+    <dependency ref="/nix/store/aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa-hello-2.12.1.drv">
+      <dependency ref="/nix/store/dddddddddddddddddddddddddddddddd-glibc-2.38.drv" />
+    </dependency>
+  </dependencies>
+</bom>
+"#;
+        assert_eq!(xml, expected);
+    }
+
+    #[test]
+    pub fn test_license_properties_to_xml() {
+        for spec_version in ["1.5", "1.6"] {
+            let document = serde_json::json!({
+                "bomFormat": "CycloneDX",
+                "specVersion": spec_version,
+                "version": 1,
+                "components": [
+                    {
+                        "type": "library",
+                        "bom-ref": "/nix/store/dddddddddddddddddddddddddddddddd-glibc-2.38.drv",
+                        "name": "glibc",
+                        "licenses": [
+                            {
+                                "license": {
+                                    "name": "Unfree redistributable",
+                                    "properties": [
+                                        { "name": "nixpkgs:license:free", "value": "false" },
+                                        { "name": "nixpkgs:license:redistributable", "value": "true" },
+                                    ],
+                                },
+                            },
+                        ],
+                    },
+                ],
+            });
+            let xml = to_xml(&document, spec_version).unwrap();
+            let expected = format!(
+                r#"<?xml version="1.0" encoding="UTF-8"?>
+<bom xmlns="http://cyclonedx.org/schema/bom/{}" version="1">
+  <components>
+    <component type="library" bom-ref="/nix/store/dddddddddddddddddddddddddddddddd-glibc-2.38.drv">
+      <name>glibc</name>
+      <licenses>
+        <license>
+          <name>Unfree redistributable</name>
+          <properties>
+            <property name="nixpkgs:license:free">false</property>
+            <property name="nixpkgs:license:redistributable">true</property>
+          </properties>
+        </license>
+      </licenses>
+    </component>
+  </components>
+</bom>
+"#,
+                spec_version
+            );
+            assert_eq!(xml, expected);
+        }
+    }
+
+    #[test]
+    pub fn test_vulnerability_to_xml() {
+        let document = serde_json::json!({
+            "bomFormat": "CycloneDX",
+            "specVersion": "1.5",
+            "version": 1,
+            "vulnerabilities": [
+                {
+                    "id": "CVE-2023-4911",
+                    "source": { "name": "NVD", "url": "https://nvd.nist.gov/vuln/detail/CVE-2023-4911" },
+                    "ratings": [
+                        { "score": 7.8, "severity": "high", "method": "CVSSv31" },
+                    ],
+                    "affects": [
+                        {
+                            "ref": "/nix/store/dddddddddddddddddddddddddddddddd-glibc-2.38.drv",
+                            "versions": [{ "version": "2.38", "status": "affected" }],
+                        },
+                    ],
+                },
+            ],
+        });
+        let xml = to_xml(&document, "1.5").unwrap();
+        let expected = r#"<?xml version="1.0" encoding="UTF-8"?>
+<bom xmlns="http://cyclonedx.org/schema/bom/1.5" version="1">
+  <vulnerabilities>
+    <vulnerability>
+      <id>CVE-2023-4911</id>
+      <source>
+        <name>NVD</name>
+        <url>https://nvd.nist.gov/vuln/detail/CVE-2023-4911</url>
+      </source>
+      <ratings>
+        <rating>
+          <score>7.8</score>
+          <severity>high</severity>
+          <method>CVSSv31</method>
+        </rating>
+      </ratings>
+      <affects>
+        <target>
+          <ref>/nix/store/dddddddddddddddddddddddddddddddd-glibc-2.38.drv</ref>
+          <versions>
+            <version>
+              <version>2.38</version>
+              <status>affected</status>
+            </version>
+          </versions>
+        </target>
+      </affects>
+    </vulnerability>
+  </vulnerabilities>
+</bom>
+"#;
+        assert_eq!(xml, expected);
+    }
+
+    #[test]
+    pub fn test_formulation_to_xml() {
+        let document = serde_json::json!({
+            "bomFormat": "CycloneDX",
+            "specVersion": "1.5",
+            "version": 1,
+            "formulation": [
+                {
+                    "bom-ref": "nix-build",
+                    "workflows": [
+                        {
+                            "bom-ref": "nix-build:/nix/store/aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa-hello-2.12.1.drv",
+                            "uid": "/nix/store/aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa-hello-2.12.1.drv",
+                            "taskTypes": ["build"],
+                            "steps": [{ "name": "builder", "commands": [{ "executed": "/bin/bash -e builder.sh" }] }],
+                            "inputs": [
+                                { "resource": { "ref": "/nix/store/dddddddddddddddddddddddddddddddd-glibc-2.38.drv" } },
+                            ],
+                            "outputs": [
+                                {
+                                    "type": "artifact",
+                                    "resource": { "ref": "/nix/store/aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa-hello-2.12.1.drv" },
+                                },
+                            ],
+                            "properties": [{ "name": "nix:system", "value": "x86_64-linux" }],
+                        },
+                    ],
+                },
+            ],
+        });
+        let xml = to_xml(&document, "1.5").unwrap();
+        let expected = r#"<?xml version="1.0" encoding="UTF-8"?>
+<bom xmlns="http://cyclonedx.org/schema/bom/1.5" version="1">
+  <formulation>
+    <formula bom-ref="nix-build">
+      <workflows>
+        <workflow bom-ref="nix-build:/nix/store/aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa-hello-2.12.1.drv">
+          <uid>/nix/store/aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa-hello-2.12.1.drv</uid>
+          <taskTypes>
+            <taskType>build</taskType>
+          </taskTypes>
+          <steps>
+            <step>
+              <name>builder</name>
+              <commands>
+                <command>
+                  <executed>/bin/bash -e builder.sh</executed>
+                </command>
+              </commands>
+            </step>
+          </steps>
+          <inputs>
+            <input>
+              <resource>
+                <ref>/nix/store/dddddddddddddddddddddddddddddddd-glibc-2.38.drv</ref>
+              </resource>
+            </input>
+          </inputs>
+          <outputs>
+            <output>
+              <type>artifact</type>
+              <resource>
+                <ref>/nix/store/aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa-hello-2.12.1.drv</ref>
+              </resource>
+            </output>
+          </outputs>
+          <properties>
+            <property name="nix:system">x86_64-linux</property>
+          </properties>
+        </workflow>
+      </workflows>
+    </formula>
+  </formulation>
+</bom>
+"#;
+        assert_eq!(xml, expected);
+    }
+}
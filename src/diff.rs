@@ -0,0 +1,549 @@
+// Comparison of two SBOMs, to review what changes when bumping nixpkgs. The inputs are
+// reduced to a list of components with their version, licenses and patches, so that a
+// package graph can be compared with another package graph or with a CycloneDX SBOM.
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::Serialize;
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(PartialEq)]
+pub struct DiffComponent {
+    pub name: String,
+    pub version: Option<String>,
+    // The SPDX ID, the name or the SPDX expression of the licenses.
+    pub license: Option<String>,
+    // The URLs of the patches applied to the component.
+    pub patches: BTreeSet<String>,
+    // The locked flake inputs are the sources of the build, and their version is a revision.
+    pub flake_input: bool,
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Default)]
+#[derive(PartialEq)]
+#[derive(Serialize)]
+pub struct Change {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub old_version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub new_version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub old_license: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub new_license: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub added_patches: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub removed_patches: Vec<String>,
+}
+
+impl Change {
+    fn new(name: &str, old: Option<&DiffComponent>, new: Option<&DiffComponent>) -> Change {
+        Change {
+            name: name.to_string(),
+            old_version: old.and_then(|c| c.version.clone()),
+            new_version: new.and_then(|c| c.version.clone()),
+            ..Default::default()
+        }
+    }
+
+    fn get_name_and_version(&self) -> String {
+        match self.new_version.as_ref().or(self.old_version.as_ref()) {
+            Some(version) => format!("{} {}", self.name, version),
+            None => self.name.to_string(),
+        }
+    }
+}
+
+// The changes between two SBOMs, grouped by kind.
+#[derive(Debug)]
+#[derive(Default)]
+#[derive(PartialEq)]
+#[derive(Serialize)]
+pub struct SbomDiff {
+    pub added: Vec<Change>,
+    pub removed: Vec<Change>,
+    pub upgraded: Vec<Change>,
+    pub downgraded: Vec<Change>,
+    pub relicensed: Vec<Change>,
+    pub patches_changed: Vec<Change>,
+    pub flake_inputs_changed: Vec<Change>,
+}
+
+impl SbomDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.len() == 0
+            && self.removed.len() == 0
+            && self.upgraded.len() == 0
+            && self.downgraded.len() == 0
+            && self.relicensed.len() == 0
+            && self.patches_changed.len() == 0
+            && self.flake_inputs_changed.len() == 0
+    }
+
+    pub fn to_text(&self) -> String {
+        if self.is_empty() {
+            return "No changes".to_string();
+        }
+        let mut lines: Vec<String> = vec![];
+        let mut add_section = |title: &str, changes: &[Change], get_line: &dyn Fn(&Change) -> String| {
+            if changes.len() == 0 {
+                return;
+            }
+            if lines.len() != 0 {
+                lines.push("".to_string());
+            }
+            lines.push(format!("{} ({}):", title, changes.len()));
+            for change in changes {
+                lines.push(format!("  {}", get_line(change)));
+            }
+        };
+        add_section("Added", &self.added, &|c| c.get_name_and_version());
+        add_section("Removed", &self.removed, &|c| c.get_name_and_version());
+        let get_version_change = |c: &Change| {
+            format!(
+                "{} {} -> {}",
+                c.name,
+                c.old_version.as_deref().unwrap_or("unknown"),
+                c.new_version.as_deref().unwrap_or("unknown")
+            )
+        };
+        add_section("Upgraded", &self.upgraded, &get_version_change);
+        add_section("Downgraded", &self.downgraded, &get_version_change);
+        add_section("Re-licensed", &self.relicensed, &|c| {
+            format!(
+                "{}: {} -> {}",
+                c.get_name_and_version(),
+                c.old_license.as_deref().unwrap_or("no license"),
+                c.new_license.as_deref().unwrap_or("no license")
+            )
+        });
+        add_section("Patches changed", &self.patches_changed, &|c| {
+            let mut patches: Vec<String> = c.added_patches.iter().map(|p| format!("+{}", p)).collect();
+            patches.extend(c.removed_patches.iter().map(|p| format!("-{}", p)));
+            format!("{}: {}", c.get_name_and_version(), patches.join(", "))
+        });
+        add_section(
+            "Flake inputs changed",
+            &self.flake_inputs_changed,
+            &get_version_change,
+        );
+        lines.join("\n")
+    }
+
+    pub fn to_json(&self) -> Result<String, String> {
+        serde_json::to_string_pretty(self).map_err(|e| e.to_string())
+    }
+
+    // Records the license and patch changes between two versions of a component.
+    fn compare(&mut self, name: &str, old: &DiffComponent, new: &DiffComponent) {
+        if old.license != new.license {
+            let mut change = Change::new(name, Some(old), Some(new));
+            change.old_license = old.license.clone();
+            change.new_license = new.license.clone();
+            self.relicensed.push(change);
+        }
+        if old.patches != new.patches {
+            let mut change = Change::new(name, Some(old), Some(new));
+            change.added_patches = new.patches.difference(&old.patches).cloned().collect();
+            change.removed_patches = old.patches.difference(&new.patches).cloned().collect();
+            self.patches_changed.push(change);
+        }
+    }
+}
+
+pub fn get_components_from_package_graph(
+    package_graph: &crate::nix::PackageGraph,
+    options: &crate::nix::DumpOptions,
+) -> Vec<DiffComponent> {
+    let mut components: Vec<DiffComponent> = vec![];
+    for package_node in package_graph.nodes.values() {
+        // Same components as in the CycloneDX SBOM.
+        if package_node.is_inline_script() {
+            continue;
+        }
+        let name = match package_node.get_name() {
+            Some(n) => n,
+            None => continue,
+        };
+        let license = match crate::cyclone_dx::get_licenses(package_node, options) {
+            Some(crate::cyclone_dx::ComponentLicense::License(license)) => {
+                match crate::cyclone_dx::get_license_id_and_name(&license) {
+                    (Some(id), _) => Some(id),
+                    (None, name) => name,
+                }
+            }
+            Some(crate::cyclone_dx::ComponentLicense::Expression(expression)) => Some(expression),
+            None => None,
+        };
+        components.push(DiffComponent {
+            name,
+            version: package_node.get_version(),
+            license,
+            patches: crate::cyclone_dx::get_commit_urls(package_graph, &package_node.patches)
+                .into_iter()
+                .collect(),
+            flake_input: false,
+        });
+    }
+    components
+}
+
+pub fn get_components_from_cyclone_dx(document: &serde_json::Value) -> Result<Vec<DiffComponent>, String> {
+    let cyclone_dx_components = match document["components"].as_array() {
+        Some(c) => c,
+        None => return Ok(vec![]),
+    };
+    let mut components: Vec<DiffComponent> = vec![];
+    for component in cyclone_dx_components {
+        // Several flake inputs can have the same repository name, like nixpkgs and
+        // nixpkgs-unstable, so they are named after their node in the lock file.
+        let flake_input_name = component["bom-ref"]
+            .as_str()
+            .and_then(|r| r.strip_prefix(crate::flake_lock::FLAKE_INPUT_REF_PREFIX));
+        let name = match flake_input_name.or(component["name"].as_str()) {
+            Some(n) => n.to_string(),
+            None => return Err("Found a CycloneDX component without a name".to_string()),
+        };
+        let mut licenses: Vec<String> = vec![];
+        for license_choice in component["licenses"].as_array().unwrap_or(&vec![]) {
+            if let Some(expression) = license_choice["expression"].as_str() {
+                licenses.push(expression.to_string());
+            }
+            let license = &license_choice["license"];
+            if let Some(id) = license["id"].as_str().or(license["name"].as_str()) {
+                licenses.push(id.to_string());
+            }
+        }
+        let patches: BTreeSet<String> = component["pedigree"]["commits"]
+            .as_array()
+            .unwrap_or(&vec![])
+            .iter()
+            .filter_map(|c| c["url"].as_str())
+            .map(|u| u.to_string())
+            .collect();
+        components.push(DiffComponent {
+            name,
+            version: component["version"].as_str().map(|v| v.to_string()),
+            license: match licenses.len() {
+                0 => None,
+                _ => Some(licenses.join(" AND ")),
+            },
+            patches,
+            flake_input: flake_input_name.is_some(),
+        });
+    }
+    Ok(components)
+}
+
+// Reads the components from a package graph dump (see the graph format), or from a
+// CycloneDX SBOM in JSON or YAML.
+pub fn read_components(
+    input_path: &str,
+    options: &crate::nix::DumpOptions,
+) -> Result<Vec<DiffComponent>, String> {
+    let content = std::fs::read(input_path).map_err(|e| format!("Could not read {}: {}", input_path, e))?;
+    // YAML is a superset of JSON, so this also parses JSON files.
+    let document: serde_json::Value =
+        serde_yaml::from_slice(&content).map_err(|e| format!("Could not parse {}: {}", input_path, e))?;
+    if document["bomFormat"] == crate::sbom::CYCLONE_DX_NAME {
+        return get_components_from_cyclone_dx(&document);
+    }
+    if document.get("nodes").is_some() && document.get("root_nodes").is_some() {
+        let package_graph: crate::nix::PackageGraph = serde_json::from_value(document)
+            .map_err(|e| format!("Could not parse the package graph {}: {}", input_path, e))?;
+        return Ok(get_components_from_package_graph(&package_graph, options));
+    }
+    Err(format!(
+        "{} is neither a CycloneDX SBOM nor a package graph",
+        input_path
+    ))
+}
+
+pub fn diff(old_components: &[DiffComponent], new_components: &[DiffComponent]) -> SbomDiff {
+    let mut sbom_diff = SbomDiff::default();
+
+    // The flake inputs are the sources of the build, not components of the closure. They are
+    // only compared when both SBOMs have them, since package graphs do not.
+    let (old_flake_inputs, old_components): (Vec<&DiffComponent>, Vec<&DiffComponent>) =
+        old_components.iter().partition(|c| c.flake_input);
+    let (new_flake_inputs, new_components): (Vec<&DiffComponent>, Vec<&DiffComponent>) =
+        new_components.iter().partition(|c| c.flake_input);
+    if old_flake_inputs.len() != 0 && new_flake_inputs.len() != 0 {
+        let old_flake_inputs: BTreeMap<&String, &DiffComponent> =
+            old_flake_inputs.into_iter().map(|c| (&c.name, c)).collect();
+        let new_flake_inputs: BTreeMap<&String, &DiffComponent> =
+            new_flake_inputs.into_iter().map(|c| (&c.name, c)).collect();
+        let names: BTreeSet<&String> = old_flake_inputs
+            .keys()
+            .chain(new_flake_inputs.keys())
+            .cloned()
+            .collect();
+        for name in names {
+            match (old_flake_inputs.get(name), new_flake_inputs.get(name)) {
+                (Some(old), Some(new)) if old.version != new.version => sbom_diff
+                    .flake_inputs_changed
+                    .push(Change::new(name, Some(old), Some(new))),
+                (Some(old), None) => sbom_diff.removed.push(Change::new(name, Some(old), None)),
+                (None, Some(new)) => sbom_diff.added.push(Change::new(name, None, Some(new))),
+                _ => {}
+            }
+        }
+    }
+
+    let old_components = get_components_by_name(&old_components);
+    let new_components = get_components_by_name(&new_components);
+    let names: BTreeSet<&String> = old_components.keys().chain(new_components.keys()).collect();
+
+    let no_versions: BTreeMap<Option<String>, &DiffComponent> = BTreeMap::default();
+    for name in names {
+        let old_versions = old_components.get(name).unwrap_or(&no_versions);
+        let new_versions = new_components.get(name).unwrap_or(&no_versions);
+
+        let mut removed: Vec<&DiffComponent> = vec![];
+        for (version, old) in old_versions {
+            match new_versions.get(version) {
+                Some(new) => sbom_diff.compare(name, old, new),
+                None => removed.push(old),
+            }
+        }
+        let added: Vec<&DiffComponent> = new_versions
+            .iter()
+            .filter(|(version, _)| !old_versions.contains_key(*version))
+            .map(|(_, new)| *new)
+            .collect();
+
+        // When a version was replaced by another, the component was upgraded or downgraded.
+        let mut removed = removed.into_iter();
+        let mut added = added.into_iter();
+        loop {
+            match (removed.next(), added.next()) {
+                (Some(old), Some(new)) => {
+                    let change = Change::new(name, Some(old), Some(new));
+                    let ordering = crate::utils::compare_versions(
+                        old.version.as_deref().unwrap_or(""),
+                        new.version.as_deref().unwrap_or(""),
+                    );
+                    match ordering {
+                        std::cmp::Ordering::Greater => sbom_diff.downgraded.push(change),
+                        _ => sbom_diff.upgraded.push(change),
+                    }
+                    sbom_diff.compare(name, old, new);
+                }
+                (Some(old), None) => sbom_diff.removed.push(Change::new(name, Some(old), None)),
+                (None, Some(new)) => sbom_diff.added.push(Change::new(name, None, Some(new))),
+                (None, None) => break,
+            }
+        }
+    }
+    sbom_diff
+}
+
+fn get_components_by_name<'a>(
+    components: &[&'a DiffComponent],
+) -> BTreeMap<String, BTreeMap<Option<String>, &'a DiffComponent>> {
+    let mut components_by_name: BTreeMap<String, BTreeMap<Option<String>, &DiffComponent>> =
+        BTreeMap::default();
+    for component in components.iter().cloned() {
+        components_by_name
+            .entry(component.name.to_string())
+            .or_default()
+            .insert(component.version.clone(), component);
+    }
+    components_by_name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_component(name: &str, version: &str, license: &str, patches: &[&str]) -> DiffComponent {
+        DiffComponent {
+            name: name.to_string(),
+            version: Some(version.to_string()),
+            license: Some(license.to_string()),
+            patches: patches.iter().map(|p| p.to_string()).collect(),
+            flake_input: false,
+        }
+    }
+
+    #[test]
+    pub fn test_diff() {
+        let old_components = vec![
+            get_component("openssl", "3.0.12", "Apache-2.0", &[]),
+            get_component(
+                "hello",
+                "2.12.1",
+                "GPL-3.0-or-later",
+                &["https://example.com/fix.patch"],
+            ),
+            get_component("python3", "3.10.13", "Python-2.0", &[]),
+            get_component("python3", "3.11.6", "Python-2.0", &[]),
+            get_component("libfoo", "1.0", "MIT", &[]),
+        ];
+        let new_components = vec![
+            get_component("openssl", "3.0.13", "Apache-2.0", &[]),
+            get_component("hello", "2.12.1", "GPL-3.0-only", &[]),
+            get_component("python3", "3.11.6", "Python-2.0", &[]),
+            get_component("libbar", "2.0", "MIT", &[]),
+        ];
+        let sbom_diff = diff(&old_components, &new_components);
+        assert_eq!(sbom_diff.added.len(), 1);
+        assert_eq!(sbom_diff.added[0].name, "libbar");
+        let removed: Vec<String> = sbom_diff
+            .removed
+            .iter()
+            .map(|c| c.get_name_and_version())
+            .collect();
+        assert_eq!(removed, vec!["libfoo 1.0", "python3 3.10.13"]);
+        assert_eq!(sbom_diff.upgraded.len(), 1);
+        assert_eq!(sbom_diff.upgraded[0].old_version, Some("3.0.12".to_string()));
+        assert_eq!(sbom_diff.upgraded[0].new_version, Some("3.0.13".to_string()));
+        assert_eq!(sbom_diff.relicensed.len(), 1);
+        assert_eq!(
+            sbom_diff.relicensed[0].new_license,
+            Some("GPL-3.0-only".to_string())
+        );
+        assert_eq!(
+            sbom_diff.patches_changed[0].removed_patches,
+            vec!["https://example.com/fix.patch".to_string()]
+        );
+
+        assert_eq!(
+            sbom_diff.to_text(),
+            [
+                "Added (1):",
+                "  libbar 2.0",
+                "",
+                "Removed (2):",
+                "  libfoo 1.0",
+                "  python3 3.10.13",
+                "",
+                "Upgraded (1):",
+                "  openssl 3.0.12 -> 3.0.13",
+                "",
+                "Re-licensed (1):",
+                "  hello 2.12.1: GPL-3.0-or-later -> GPL-3.0-only",
+                "",
+                "Patches changed (1):",
+                "  hello 2.12.1: -https://example.com/fix.patch",
+            ]
+            .join("\n")
+        );
+        let json: serde_json::Value = serde_json::from_str(&sbom_diff.to_json().unwrap()).unwrap();
+        assert_eq!(json["upgraded"][0]["name"], "openssl");
+        assert_eq!(json["downgraded"], serde_json::json!([]));
+
+        assert!(diff(&new_components, &new_components).is_empty());
+        assert_eq!(diff(&new_components, &new_components).to_text(), "No changes");
+        assert_eq!(diff(&new_components, &old_components).downgraded.len(), 1);
+    }
+
+    #[test]
+    pub fn test_diff_cyclone_dx_and_package_graph() {
+        let package_graph = crate::nix::get_test_package_graph();
+        let options = crate::nix::DumpOptions::default();
+        let json =
+            crate::cyclone_dx::dump(&package_graph, &crate::sbom::SerializationFormat::JSON, &options).unwrap();
+        let document: serde_json::Value = serde_json::from_str(&json).unwrap();
+        let cyclone_dx_components = get_components_from_cyclone_dx(&document).unwrap();
+        let package_graph_components = get_components_from_package_graph(&package_graph, &options);
+        assert_ne!(cyclone_dx_components.len(), 0);
+        assert!(diff(&cyclone_dx_components, &package_graph_components).is_empty());
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dump_path = temp_dir.path().join("diff-graph.json");
+        std::fs::write(&dump_path, serde_json::to_string(&package_graph).unwrap()).unwrap();
+        let components = read_components(dump_path.to_str().unwrap(), &options).unwrap();
+        assert_eq!(components, package_graph_components);
+    }
+
+    #[test]
+    pub fn test_diff_cyclone_dx_with_flake_inputs() {
+        let package_graph = crate::nix::get_test_package_graph();
+        let options = crate::nix::DumpOptions {
+            flake_lock: Some(crate::flake_lock::get_test_flake_lock()),
+            ..Default::default()
+        };
+        let json =
+            crate::cyclone_dx::dump(&package_graph, &crate::sbom::SerializationFormat::JSON, &options).unwrap();
+        let document: serde_json::Value = serde_json::from_str(&json).unwrap();
+        let cyclone_dx_components = get_components_from_cyclone_dx(&document).unwrap();
+        assert!(cyclone_dx_components
+            .iter()
+            .any(|c| c.name == "nixpkgs" && c.flake_input));
+        let package_graph_components = get_components_from_package_graph(&package_graph, &options);
+        assert!(diff(&cyclone_dx_components, &package_graph_components).is_empty());
+        assert!(diff(&package_graph_components, &cyclone_dx_components).is_empty());
+
+        // Bumping nixpkgs changes the revision of its flake input.
+        let mut flake_lock = crate::flake_lock::get_test_flake_lock();
+        let nixpkgs = flake_lock
+            .nodes
+            .get_mut("nixpkgs")
+            .unwrap()
+            .locked
+            .as_mut()
+            .unwrap();
+        nixpkgs.rev = Some("e8b5f9d1e8ba1ec4f1ad7fd32fb5da1e21e2cd06".to_string());
+        flake_lock.nodes.remove("systems");
+        let options = crate::nix::DumpOptions {
+            flake_lock: Some(flake_lock),
+            ..Default::default()
+        };
+        let json =
+            crate::cyclone_dx::dump(&package_graph, &crate::sbom::SerializationFormat::JSON, &options).unwrap();
+        let document: serde_json::Value = serde_json::from_str(&json).unwrap();
+        let new_components = get_components_from_cyclone_dx(&document).unwrap();
+        let sbom_diff = diff(&cyclone_dx_components, &new_components);
+        assert_eq!(sbom_diff.upgraded.len(), 0);
+        assert_eq!(
+            sbom_diff.to_text(),
+            [
+                "Removed (1):",
+                "  systems",
+                "",
+                "Flake inputs changed (1):",
+                "  nixpkgs 20f77aa09916374aa3141cbc605c955626762c9a -> e8b5f9d1e8ba1ec4f1ad7fd32fb5da1e21e2cd06",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    pub fn test_diff_package_metadata() {
+        // The licenses only come from the metadata, so each closure needs the metadata of the
+        // nixpkgs it was built from to find the re-licensed packages.
+        let derivations = crate::nix::get_test_derivations();
+        let old_packages = crate::nix::get_test_packages();
+        let mut new_packages = crate::nix::get_test_packages();
+        new_packages.get_mut("hello").unwrap().meta =
+            serde_json::from_str(r###"{ "license": { "spdxId": "GPL-3.0-only", "shortName": "gpl3Only" } }"###)
+                .unwrap();
+
+        let options = crate::nix::DumpOptions::default();
+        let old_components = get_components_from_package_graph(
+            &crate::nix::get_package_graph_next(&derivations, &old_packages),
+            &options,
+        );
+        let new_components = get_components_from_package_graph(
+            &crate::nix::get_package_graph_next(&derivations, &new_packages),
+            &options,
+        );
+        let sbom_diff = diff(&old_components, &new_components);
+        assert_eq!(sbom_diff.relicensed.len(), 1);
+        assert_eq!(sbom_diff.relicensed[0].name, "hello");
+        assert_eq!(
+            sbom_diff.relicensed[0].old_license,
+            Some("GPL-3.0-or-later".to_string())
+        );
+        assert_eq!(
+            sbom_diff.relicensed[0].new_license,
+            Some("GPL-3.0-only".to_string())
+        );
+        assert!(diff(&old_components, &old_components).is_empty());
+    }
+}
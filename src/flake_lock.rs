@@ -0,0 +1,354 @@
+// Parsing of the flake.lock files, which pin the inputs of a flake to exact revisions.
+// See https://nix.dev/manual/nix/stable/command-ref/new-cli/nix3-flake.html#lock-files
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::Deserialize;
+
+pub const FLAKE_LOCK_FILE_NAME: &str = "flake.lock";
+
+// Prefix of the bom-refs of the flake inputs, which are keyed by the name of their node.
+pub const FLAKE_INPUT_REF_PREFIX: &str = "flake-input:";
+
+// A follows can point to another follows, so we stop after this many indirections
+// instead of looping forever on a broken lock file.
+const MAX_FOLLOWS_DEPTH: usize = 64;
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(PartialEq)]
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum InputReference {
+    // The name of a node of the lock file.
+    Node(String),
+    // `inputs.x.follows`, as the path of the input starting from the root node.
+    Follows(Vec<String>),
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Default)]
+#[derive(PartialEq)]
+#[derive(Deserialize)]
+pub struct LockedReference {
+    // github, gitlab, sourcehut, git, mercurial, tarball, file or path.
+    #[serde(rename = "type")]
+    pub reference_type: String,
+    pub owner: Option<String>,
+    pub repo: Option<String>,
+    pub host: Option<String>,
+    pub url: Option<String>,
+    pub path: Option<String>,
+    pub rev: Option<String>,
+    #[serde(rename = "ref")]
+    pub git_ref: Option<String>,
+    #[serde(rename = "narHash")]
+    pub nar_hash: Option<String>,
+    #[serde(rename = "lastModified")]
+    pub last_modified: Option<u64>,
+}
+
+impl LockedReference {
+    // Returns the URL of the repository or of the archive of the input.
+    pub fn get_url(&self) -> Option<String> {
+        let default_host = match self.reference_type.as_str() {
+            "github" => "github.com",
+            "gitlab" => "gitlab.com",
+            "sourcehut" => "git.sr.ht",
+            "path" => return self.path.clone(),
+            _ => return self.url.clone(),
+        };
+        let owner = self.owner.as_ref()?;
+        let repo = self.repo.as_ref()?;
+        Some(format!(
+            "https://{}/{}/{}",
+            self.host.as_deref().unwrap_or(default_host),
+            owner,
+            repo
+        ))
+    }
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(PartialEq)]
+#[derive(Deserialize)]
+pub struct FlakeLockNode {
+    #[serde(default)]
+    pub inputs: BTreeMap<String, InputReference>,
+    // Only the root node is not locked.
+    pub locked: Option<LockedReference>,
+    // The reference as written in flake.nix, which has the branch the input follows.
+    pub original: Option<LockedReference>,
+    // Set to false for the inputs which are not flakes.
+    pub flake: Option<bool>,
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(PartialEq)]
+pub struct FlakeInput {
+    // The name of the node in the lock file, which is unique.
+    pub node_name: String,
+    pub locked: LockedReference,
+    // The names of the nodes of the inputs of this input, with the follows resolved.
+    pub inputs: BTreeSet<String>,
+}
+
+impl FlakeInput {
+    pub fn get_ref(&self) -> String {
+        get_flake_input_ref(&self.node_name)
+    }
+
+    // The name of the repository when there is one, or else the name of the node.
+    pub fn get_name(&self) -> String {
+        match &self.locked.repo {
+            Some(repo) => repo.to_string(),
+            None => self.node_name.to_string(),
+        }
+    }
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(PartialEq)]
+#[derive(Deserialize)]
+pub struct FlakeLock {
+    pub nodes: BTreeMap<String, FlakeLockNode>,
+    pub root: String,
+    pub version: u32,
+}
+
+impl FlakeLock {
+    pub fn read(lock_path: &str) -> Result<FlakeLock, String> {
+        log::info!("Reading the flake inputs from {}", lock_path);
+        let content = std::fs::read(lock_path).map_err(|e| format!("Could not read {}: {}", lock_path, e))?;
+        FlakeLock::parse(&content).map_err(|e| format!("Could not parse {}: {}", lock_path, e))
+    }
+
+    pub fn parse(content: &[u8]) -> Result<FlakeLock, String> {
+        let flake_lock: FlakeLock = serde_json::from_slice(content).map_err(|e| e.to_string())?;
+        if !flake_lock.nodes.contains_key(&flake_lock.root) {
+            return Err(format!("The root node {} does not exist", flake_lock.root));
+        }
+        Ok(flake_lock)
+    }
+
+    // Returns the name of the node an input points to.
+    pub fn resolve(&self, reference: &InputReference) -> Option<String> {
+        self.resolve_with_depth(reference, 0)
+    }
+
+    fn resolve_with_depth(&self, reference: &InputReference, depth: usize) -> Option<String> {
+        if depth > MAX_FOLLOWS_DEPTH {
+            log::warn!("Too many follows when resolving {:?}", reference);
+            return None;
+        }
+        let input_path = match reference {
+            InputReference::Node(node_name) => return Some(node_name.to_string()),
+            InputReference::Follows(p) => p,
+        };
+        let mut node_name = self.root.to_string();
+        for input_name in input_path {
+            let node = self.nodes.get(&node_name)?;
+            let input_reference = node.inputs.get(input_name)?;
+            node_name = self.resolve_with_depth(input_reference, depth + 1)?;
+        }
+        Some(node_name)
+    }
+
+    // Returns the names of the nodes of the inputs of a node.
+    pub fn get_inputs(&self, node_name: &str) -> BTreeSet<String> {
+        let node = match self.nodes.get(node_name) {
+            Some(n) => n,
+            None => return BTreeSet::default(),
+        };
+        node.inputs
+            .values()
+            .filter_map(|reference| self.resolve(reference))
+            .collect()
+    }
+
+    pub fn get_root_inputs(&self) -> BTreeSet<String> {
+        self.get_inputs(&self.root)
+    }
+
+    // Returns all the locked inputs reachable from the root node, including the nested ones.
+    pub fn get_flake_inputs(&self) -> Vec<FlakeInput> {
+        let mut flake_inputs: Vec<FlakeInput> = vec![];
+        let mut visited: BTreeSet<String> = BTreeSet::default();
+        let mut to_visit: Vec<String> = self.get_root_inputs().into_iter().collect();
+        while let Some(node_name) = to_visit.pop() {
+            if !visited.insert(node_name.to_string()) || node_name == self.root {
+                continue;
+            }
+            let locked = match self.nodes.get(&node_name).and_then(|n| n.locked.as_ref()) {
+                Some(l) => l,
+                None => continue,
+            };
+            let inputs = self.get_inputs(&node_name);
+            to_visit.extend(inputs.iter().cloned());
+            flake_inputs.push(FlakeInput {
+                node_name,
+                locked: locked.clone(),
+                inputs,
+            });
+        }
+        flake_inputs.sort_by(|a, b| a.node_name.cmp(&b.node_name));
+        flake_inputs
+    }
+}
+
+pub fn get_flake_input_ref(node_name: &str) -> String {
+    format!("{}{}", FLAKE_INPUT_REF_PREFIX, node_name)
+}
+
+// Finds the lock file of an installable which points to a local flake, like `.#hello`.
+pub fn get_local_lock_path(installable: &str) -> Option<String> {
+    let flake_ref = installable.split('#').next().unwrap_or(installable);
+    let flake_ref = flake_ref.strip_prefix("path:").unwrap_or(flake_ref);
+    if !(flake_ref == "." || flake_ref.starts_with("./") || flake_ref.starts_with('/')) {
+        return None;
+    }
+    // Path flake references can have parameters, like ./flake?dir=sub, where dir is the
+    // subdirectory containing the flake.
+    let (flake_dir, parameters) = flake_ref.split_once('?').unwrap_or((flake_ref, ""));
+    let mut flake_dir = std::path::PathBuf::from(flake_dir);
+    if let Some(sub_dir) = parameters.split('&').find_map(|p| p.strip_prefix("dir=")) {
+        flake_dir = flake_dir.join(sub_dir);
+    }
+    let lock_path = flake_dir.join(FLAKE_LOCK_FILE_NAME);
+    if !lock_path.is_file() {
+        return None;
+    }
+    lock_path.to_str().map(|p| p.to_string())
+}
+
+// Lock file of a flake with nested inputs and a follows, used in the unit tests.
+#[cfg(test)]
+pub(crate) fn get_test_flake_lock() -> FlakeLock {
+    FlakeLock::parse(
+        br###"{
+          "nodes": {
+            "flake-utils": {
+              "inputs": { "systems": "systems" },
+              "locked": {
+                "lastModified": 1710146030, "narHash": "sha256-SZ5L6eA7HJ/nmkzGG7/ISclqe6oZdOZTNoesiInkXPQ=",
+                "owner": "numtide", "repo": "flake-utils", "rev": "b1d9ab70662946ef0850d488da1c9019f3a9752a",
+                "type": "github"
+              },
+              "original": { "owner": "numtide", "repo": "flake-utils", "type": "github" }
+            },
+            "home-manager": {
+              "inputs": { "nixpkgs": [ "nixpkgs" ] },
+              "locked": {
+                "lastModified": 1711133180, "narHash": "sha256-WJOahf+6115+GMl3wUfURu8fszuNeJLv9qAWFQl3Vmo=",
+                "owner": "nix-community", "repo": "home-manager", "rev": "1c2c5e4cabba4c43504ef0f8cc3f3dfa284e2dbb",
+                "type": "github"
+              },
+              "original": { "owner": "nix-community", "repo": "home-manager", "type": "github" }
+            },
+            "nixpkgs": {
+              "locked": {
+                "lastModified": 1711001935, "narHash": "sha256-URtGpHue7HHZK0mrHnSf8wJ6OmMKYSsoLmJybrOLFSQ=",
+                "owner": "NixOS", "repo": "nixpkgs", "rev": "20f77aa09916374aa3141cbc605c955626762c9a",
+                "type": "github"
+              },
+              "original": { "owner": "NixOS", "ref": "nixos-unstable", "repo": "nixpkgs", "type": "github" }
+            },
+            "root": {
+              "inputs": { "flake-utils": "flake-utils", "home-manager": "home-manager", "nixpkgs": "nixpkgs" }
+            },
+            "systems": {
+              "locked": {
+                "lastModified": 1681028828, "narHash": "sha256-Vy1rq5AaRuLzOxct8nz4T6wlgyUR7zLU309k9mBC768=",
+                "url": "https://github.com/nix-systems/default/archive/main.tar.gz", "type": "tarball"
+              },
+              "original": { "url": "https://github.com/nix-systems/default/archive/main.tar.gz", "type": "tarball" }
+            }
+          },
+          "root": "root",
+          "version": 7
+        }"###,
+    )
+    .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_get_flake_inputs() {
+        let flake_lock = get_test_flake_lock();
+        let flake_inputs = flake_lock.get_flake_inputs();
+        let node_names: Vec<&str> = flake_inputs.iter().map(|i| i.node_name.as_str()).collect();
+        assert_eq!(
+            node_names,
+            vec!["flake-utils", "home-manager", "nixpkgs", "systems"]
+        );
+        // The nixpkgs input of home-manager follows the nixpkgs of the root.
+        assert_eq!(flake_inputs[1].inputs, BTreeSet::from(["nixpkgs".to_string()]));
+        assert_eq!(
+            flake_inputs[2].locked.get_url(),
+            Some("https://github.com/NixOS/nixpkgs".to_string())
+        );
+        assert_eq!(
+            flake_inputs[3].locked.get_url(),
+            Some("https://github.com/nix-systems/default/archive/main.tar.gz".to_string())
+        );
+        assert_eq!(flake_inputs[3].get_name(), "systems");
+        assert_eq!(flake_inputs[0].get_ref(), "flake-input:flake-utils");
+    }
+
+    #[test]
+    pub fn test_get_local_lock_path() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let flake_dir = temp_dir.path().to_str().unwrap();
+        std::fs::create_dir(temp_dir.path().join("sub")).unwrap();
+        std::fs::write(temp_dir.path().join("sub").join(FLAKE_LOCK_FILE_NAME), "{}").unwrap();
+        let lock_path = format!("{}/sub/{}", flake_dir, FLAKE_LOCK_FILE_NAME);
+
+        assert_eq!(get_local_lock_path(&format!("{}#hello", flake_dir)), None);
+        assert_eq!(
+            get_local_lock_path(&format!("{}?dir=sub#hello", flake_dir)),
+            Some(lock_path.clone())
+        );
+        assert_eq!(
+            get_local_lock_path(&format!("path:{}?ref=main&dir=sub", flake_dir)),
+            Some(lock_path)
+        );
+        assert_eq!(get_local_lock_path("github:NixOS/nixpkgs?dir=lib#hello"), None);
+    }
+
+    #[test]
+    pub fn test_resolve_nested_follows() {
+        let flake_lock = FlakeLock::parse(
+            br###"{
+              "nodes": {
+                "a": { "inputs": { "b": "b", "nixpkgs": [ "a", "b", "nixpkgs" ] }, "locked": { "type": "path", "path": "/a" } },
+                "b": { "inputs": { "nixpkgs": "nixpkgs" }, "locked": { "type": "path", "path": "/b" } },
+                "nixpkgs": { "locked": { "type": "path", "path": "/nixpkgs" } },
+                "loop": { "inputs": { "self": [ "loop" ] }, "locked": { "type": "path", "path": "/loop" } },
+                "root": { "inputs": { "a": "a", "loop": [ "loop" ] } }
+              },
+              "root": "root",
+              "version": 7
+            }"###,
+        )
+        .unwrap();
+        assert_eq!(
+            flake_lock.resolve(&InputReference::Follows(vec![
+                "a".to_string(),
+                "b".to_string(),
+                "nixpkgs".to_string()
+            ])),
+            Some("nixpkgs".to_string())
+        );
+        assert_eq!(
+            flake_lock.get_inputs("a"),
+            BTreeSet::from(["b".to_string(), "nixpkgs".to_string()])
+        );
+        assert_eq!(flake_lock.get_flake_inputs().len(), 3);
+    }
+}
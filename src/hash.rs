@@ -0,0 +1,203 @@
+// Decoding of the hashes used by Nix in fixed-output derivations. Nix accepts hashes
+// in the SRI format (sha256-<base64>), optionally prefixed with the algorithm
+// (sha256:<hash>), and encoded in hex, nix-base32 or base64.
+use base64::Engine;
+
+// The alphabet used by Nix for base32, which omits the letters e, o, u and t.
+const NIX_BASE32_ALPHABET: &[u8] = b"0123456789abcdfghijklmnpqrsvwxyz";
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(PartialEq)]
+pub enum HashAlgorithm {
+    MD5,
+    SHA1,
+    SHA256,
+    SHA512,
+}
+
+impl HashAlgorithm {
+    pub fn from_string(algorithm: &str) -> Option<HashAlgorithm> {
+        // Nix prefixes the algorithm with r: or text: in the outputs of some derivations.
+        let algorithm = match algorithm.rsplit(':').next() {
+            Some(a) => a,
+            None => return None,
+        };
+        match algorithm.to_lowercase().as_str() {
+            "md5" => Some(HashAlgorithm::MD5),
+            "sha1" => Some(HashAlgorithm::SHA1),
+            "sha256" => Some(HashAlgorithm::SHA256),
+            "sha512" => Some(HashAlgorithm::SHA512),
+            _ => None,
+        }
+    }
+
+    // Name of the algorithm as used by Nix and in the purl checksum qualifier.
+    pub fn to_string(&self) -> String {
+        match self {
+            HashAlgorithm::MD5 => "md5".to_string(),
+            HashAlgorithm::SHA1 => "sha1".to_string(),
+            HashAlgorithm::SHA256 => "sha256".to_string(),
+            HashAlgorithm::SHA512 => "sha512".to_string(),
+        }
+    }
+
+    pub fn get_size(&self) -> usize {
+        match self {
+            HashAlgorithm::MD5 => 16,
+            HashAlgorithm::SHA1 => 20,
+            HashAlgorithm::SHA256 => 32,
+            HashAlgorithm::SHA512 => 64,
+        }
+    }
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(PartialEq)]
+pub enum HashMode {
+    // The hash of the downloaded file itself.
+    Flat,
+    // The hash of the NAR serialization of the output, used for unpacked sources.
+    Recursive,
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(PartialEq)]
+pub struct Hash {
+    pub algorithm: HashAlgorithm,
+    pub mode: HashMode,
+    // The hash, encoded in lowercase hex.
+    pub value: String,
+}
+
+impl Hash {
+    // Parses a hash in any of the formats accepted by Nix. The algorithm is only
+    // required when it is not part of the hash itself.
+    pub fn parse(hash: &str, algorithm: Option<&str>, mode: HashMode) -> Option<Hash> {
+        let (algorithm, encoded_hash, is_sri) = if let Some((a, h)) = hash.split_once(':') {
+            (a, h, false)
+        } else if let Some((a, h)) = hash.split_once('-') {
+            (a, h, true)
+        } else {
+            match algorithm {
+                Some(a) if a.len() != 0 => (a, hash, false),
+                _ => return None,
+            }
+        };
+        let algorithm = HashAlgorithm::from_string(algorithm)?;
+        let size = algorithm.get_size();
+
+        let bytes = if is_sri {
+            decode_base64(encoded_hash)?
+        } else if encoded_hash.len() == size * 2 {
+            decode_hex(encoded_hash)?
+        } else if encoded_hash.len() == (size * 8).div_ceil(5) {
+            decode_nix_base32(encoded_hash, size)?
+        } else {
+            decode_base64(encoded_hash)?
+        };
+        if bytes.len() != size {
+            return None;
+        }
+
+        Some(Hash {
+            algorithm,
+            mode,
+            value: encode_hex(&bytes),
+        })
+    }
+
+    // Format used by the purl checksum qualifier, for example sha256:de4d501267da...
+    pub fn to_checksum(&self) -> String {
+        format!("{}:{}", self.algorithm.to_string(), self.value)
+    }
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<Vec<String>>()
+        .join("")
+}
+
+fn decode_hex(hash: &str) -> Option<Vec<u8>> {
+    if !hash.len().is_multiple_of(2) || !hash.is_ascii() {
+        return None;
+    }
+    let mut bytes: Vec<u8> = vec![];
+    for i in (0..hash.len()).step_by(2) {
+        match u8::from_str_radix(&hash[i..i + 2], 16) {
+            Ok(b) => bytes.push(b),
+            Err(_) => return None,
+        }
+    }
+    Some(bytes)
+}
+
+fn decode_base64(hash: &str) -> Option<Vec<u8>> {
+    base64::engine::general_purpose::STANDARD.decode(hash).ok()
+}
+
+// Port of the decoding function of Nix, in which the last character of the string
+// encodes the first 5 bits of the hash.
+fn decode_nix_base32(hash: &str, size: usize) -> Option<Vec<u8>> {
+    let mut bytes: Vec<u8> = vec![0; size];
+    for (n, c) in hash.bytes().rev().enumerate() {
+        let digit = NIX_BASE32_ALPHABET.iter().position(|a| *a == c)? as u16;
+        let b = n * 5;
+        let i = b / 8;
+        let j = b % 8;
+        bytes[i] |= (digit << j) as u8;
+        let carry = digit >> (8 - j);
+        if i < size - 1 {
+            bytes[i + 1] |= carry as u8;
+        } else if carry != 0 {
+            return None;
+        }
+    }
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The different encodings of the same sha256 hash.
+    const SHA256_HEX: &str = "8d99142afd92576f30b0cd7cb42a8dc6809998bc5d607d88761f512e26c7db20";
+    const SHA256_NIX_BASE32: &str = "086vqwk2wl8zfs47sq2xpjc9k066ilmb8z6dn0q6ymwjzlm196cd";
+    const SHA256_SRI: &str = "sha256-jZkUKv2SV28wsM18tCqNxoCZmLxdYH2Idh9RLibH2yA=";
+
+    #[test]
+    pub fn test_parse_hash() {
+        let hash = Hash::parse(SHA256_SRI, None, HashMode::Flat).unwrap();
+        assert_eq!(hash.algorithm, HashAlgorithm::SHA256);
+        assert_eq!(hash.value, SHA256_HEX);
+        assert_eq!(hash.to_checksum(), format!("sha256:{}", SHA256_HEX));
+
+        let hash = Hash::parse(SHA256_NIX_BASE32, Some("sha256"), HashMode::Flat).unwrap();
+        assert_eq!(hash.value, SHA256_HEX);
+
+        let hash = Hash::parse(&format!("sha256:{}", SHA256_NIX_BASE32), None, HashMode::Flat).unwrap();
+        assert_eq!(hash.value, SHA256_HEX);
+
+        let hash = Hash::parse(SHA256_HEX, Some("r:sha256"), HashMode::Recursive).unwrap();
+        assert_eq!(hash.value, SHA256_HEX);
+        assert_eq!(hash.mode, HashMode::Recursive);
+    }
+
+    #[test]
+    pub fn test_parse_invalid_hash() {
+        assert!(Hash::parse(SHA256_HEX, None, HashMode::Flat).is_none());
+        assert!(Hash::parse(SHA256_HEX, Some("sha3"), HashMode::Flat).is_none());
+        assert!(Hash::parse("sha256-abcd", None, HashMode::Flat).is_none());
+        assert!(Hash::parse(
+            &SHA256_NIX_BASE32.replace("0", "e"),
+            Some("sha256"),
+            HashMode::Flat
+        )
+        .is_none());
+    }
+}
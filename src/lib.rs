@@ -1,8 +1,21 @@
+pub mod aterm;
+pub mod component_type;
 pub mod consts;
+pub mod cpe;
 pub mod cyclone_dx;
+pub mod diff;
 pub mod errors;
+pub mod flake_lock;
+pub mod hash;
+pub mod license;
+pub mod license_policy;
 pub mod logger;
 pub mod mirrors;
 pub mod nix;
+pub mod nixos;
+pub mod purl;
 pub mod sbom;
+pub mod spdx;
+pub mod store_db;
 pub mod utils;
+pub mod vulnerabilities;
@@ -0,0 +1,318 @@
+// Normalisation of the licenses of nixpkgs into SPDX license identifiers and expressions.
+// Most licenses in nixpkgs have a SPDX ID, but some only have a short name, and the
+// licenses which are not on the SPDX license list (like unfree) need a LicenseRef-
+// identifier to be used in an expression. See https://spdx.github.io/spdx-spec/v2.3/SPDX-license-expressions/
+use std::collections::HashMap;
+
+use lazy_static::lazy_static;
+
+pub const LICENSE_REF_PREFIX: &str = "LicenseRef-nixpkgs-";
+
+// The SPDX IDs of the licenses of nixpkgs, keyed by their short name. This is used for the
+// licenses which only have a short name, and for the licenses listed by name.
+const SHORT_NAME_TO_SPDX_ID: &[(&str, &str)] = &[
+    ("afl21", "AFL-2.1"),
+    ("agpl3Only", "AGPL-3.0-only"),
+    ("agpl3Plus", "AGPL-3.0-or-later"),
+    ("apsl20", "APSL-2.0"),
+    ("artistic1", "Artistic-1.0"),
+    ("artistic2", "Artistic-2.0"),
+    ("asl20", "Apache-2.0"),
+    ("boost", "BSL-1.0"),
+    ("bsd0", "0BSD"),
+    ("bsd1", "BSD-1-Clause"),
+    ("bsd2", "BSD-2-Clause"),
+    ("bsd3", "BSD-3-Clause"),
+    ("bsdOriginal", "BSD-4-Clause"),
+    ("bsl11", "BUSL-1.1"),
+    ("cc0", "CC0-1.0"),
+    ("ccBy30", "CC-BY-3.0"),
+    ("ccBy40", "CC-BY-4.0"),
+    ("ccBySa30", "CC-BY-SA-3.0"),
+    ("ccBySa40", "CC-BY-SA-4.0"),
+    ("cddl", "CDDL-1.0"),
+    ("curl", "curl"),
+    ("epl10", "EPL-1.0"),
+    ("epl20", "EPL-2.0"),
+    ("eupl12", "EUPL-1.2"),
+    ("fdl12Plus", "GFDL-1.2-or-later"),
+    ("fdl13Plus", "GFDL-1.3-or-later"),
+    ("ftl", "FTL"),
+    ("gpl1Plus", "GPL-1.0-or-later"),
+    ("gpl2", "GPL-2.0-only"),
+    ("gpl2Only", "GPL-2.0-only"),
+    ("gpl2Plus", "GPL-2.0-or-later"),
+    ("gpl3", "GPL-3.0-only"),
+    ("gpl3Only", "GPL-3.0-only"),
+    ("gpl3Plus", "GPL-3.0-or-later"),
+    ("hpnd", "HPND"),
+    ("icu", "ICU"),
+    ("ijg", "IJG"),
+    ("ipl10", "IPL-1.0"),
+    ("isc", "ISC"),
+    ("lgpl2", "LGPL-2.0-only"),
+    ("lgpl2Only", "LGPL-2.0-only"),
+    ("lgpl2Plus", "LGPL-2.0-or-later"),
+    ("lgpl21", "LGPL-2.1-only"),
+    ("lgpl21Only", "LGPL-2.1-only"),
+    ("lgpl21Plus", "LGPL-2.1-or-later"),
+    ("lgpl3", "LGPL-3.0-only"),
+    ("lgpl3Only", "LGPL-3.0-only"),
+    ("lgpl3Plus", "LGPL-3.0-or-later"),
+    ("libpng", "Libpng"),
+    ("libpng2", "libpng-2.0"),
+    ("libtiff", "libtiff"),
+    ("mit", "MIT"),
+    ("mit0", "MIT-0"),
+    ("mpl11", "MPL-1.1"),
+    ("mpl20", "MPL-2.0"),
+    ("ncsa", "NCSA"),
+    ("ofl", "OFL-1.1"),
+    ("openldap", "OLDAP-2.8"),
+    ("openssl", "OpenSSL"),
+    ("php301", "PHP-3.01"),
+    ("postgresql", "PostgreSQL"),
+    ("psfl", "Python-2.0"),
+    ("ruby", "Ruby"),
+    ("sgi-b-20", "SGI-B-2.0"),
+    ("sleepycat", "Sleepycat"),
+    ("sspl", "SSPL-1.0"),
+    ("unicode-dfs-2016", "Unicode-DFS-2016"),
+    ("unlicense", "Unlicense"),
+    ("upl", "UPL-1.0"),
+    ("vim", "Vim"),
+    ("w3c", "W3C"),
+    ("wtfpl", "WTFPL"),
+    ("x11", "X11"),
+    ("zlib", "Zlib"),
+    ("zpl21", "ZPL-2.1"),
+];
+
+// Operators of the SPDX license expressions.
+const EXPRESSION_OPERATORS: &[&str] = &[" AND ", " OR ", " WITH "];
+
+lazy_static! {
+    static ref SPDX_IDS_BY_SHORT_NAME: HashMap<String, String> = SHORT_NAME_TO_SPDX_ID
+        .iter()
+        .map(|(short_name, spdx_id)| (short_name.to_lowercase(), spdx_id.to_string()))
+        .collect();
+    static ref KNOWN_SPDX_IDS: HashMap<String, String> = SHORT_NAME_TO_SPDX_ID
+        .iter()
+        .map(|(_, spdx_id)| (spdx_id.to_lowercase(), spdx_id.to_string()))
+        .collect();
+}
+
+// nixpkgs does not say if all the licenses of a package apply, or if one of them can be
+// chosen, so the operator used to combine them is configurable.
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(PartialEq)]
+#[derive(Default)]
+pub enum LicenseJoinPolicy {
+    // All the licenses apply, which is the conservative choice.
+    #[default]
+    And,
+    // Any of the licenses can be chosen.
+    Or,
+}
+
+impl LicenseJoinPolicy {
+    pub fn from_string(policy: &str) -> Option<LicenseJoinPolicy> {
+        match policy.to_lowercase().as_str() {
+            "and" => Some(LicenseJoinPolicy::And),
+            "or" => Some(LicenseJoinPolicy::Or),
+            _ => None,
+        }
+    }
+
+    pub fn to_operator(&self) -> String {
+        match self {
+            LicenseJoinPolicy::And => "AND".to_string(),
+            LicenseJoinPolicy::Or => "OR".to_string(),
+        }
+    }
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(PartialEq)]
+pub struct NormalizedLicense {
+    // A SPDX license ID, a LicenseRef- identifier, or a SPDX license expression.
+    pub id: String,
+    // The full name of the license, when known.
+    pub name: Option<String>,
+    pub is_license_ref: bool,
+    pub is_expression: bool,
+    // The license as found in the metadata of the package.
+    pub license: crate::nix::PackageLicense,
+}
+
+pub fn normalize_license(license: &crate::nix::PackageLicense) -> NormalizedLicense {
+    let mut normalized_license = NormalizedLicense {
+        id: "".to_string(),
+        name: None,
+        is_license_ref: false,
+        is_expression: false,
+        license: license.clone(),
+    };
+    match license {
+        crate::nix::PackageLicense::Name(name) => {
+            if let Some(spdx_id) = get_spdx_id(name) {
+                normalized_license.id = spdx_id;
+            } else if EXPRESSION_OPERATORS.iter().any(|o| name.contains(o)) {
+                normalized_license.id = name.to_string();
+                normalized_license.is_expression = true;
+            } else {
+                normalized_license.id = get_license_ref(name);
+                normalized_license.name = Some(name.to_string());
+                normalized_license.is_license_ref = true;
+            }
+        }
+        crate::nix::PackageLicense::Details(details) => {
+            normalized_license.name = details.full_name.clone();
+            let spdx_id = match &details.spdx_id {
+                Some(id) => Some(id.to_string()),
+                None => details.short_name.as_ref().and_then(|n| get_spdx_id(n)),
+            };
+            if let Some(spdx_id) = spdx_id {
+                normalized_license.id = spdx_id;
+            } else {
+                let name = details
+                    .short_name
+                    .as_ref()
+                    .or(details.full_name.as_ref())
+                    .map(|n| n.to_string())
+                    .unwrap_or("unknown".to_string());
+                normalized_license.id = get_license_ref(&name);
+                normalized_license.is_license_ref = true;
+            }
+        }
+    }
+    normalized_license
+}
+
+pub fn normalize_licenses(licenses: &[crate::nix::PackageLicense]) -> Vec<NormalizedLicense> {
+    let mut normalized_licenses: Vec<NormalizedLicense> = vec![];
+    for license in licenses {
+        let normalized_license = normalize_license(license);
+        if normalized_licenses.iter().any(|l| l.id == normalized_license.id) {
+            continue;
+        }
+        normalized_licenses.push(normalized_license);
+    }
+    normalized_licenses
+}
+
+// Combines the licenses into a single SPDX license expression.
+pub fn get_license_expression(
+    licenses: &[NormalizedLicense],
+    join_policy: &LicenseJoinPolicy,
+) -> Option<String> {
+    if licenses.len() == 0 {
+        return None;
+    }
+    if licenses.len() == 1 {
+        return Some(licenses[0].id.to_string());
+    }
+    let terms: Vec<String> = licenses
+        .iter()
+        .map(|l| match l.is_expression {
+            true => format!("({})", l.id),
+            false => l.id.to_string(),
+        })
+        .collect();
+    Some(terms.join(&format!(" {} ", join_policy.to_operator())))
+}
+
+// Returns the SPDX ID for a nixpkgs short name, or for a SPDX ID with the wrong case.
+fn get_spdx_id(name: &str) -> Option<String> {
+    let name = name.to_lowercase();
+    if let Some(spdx_id) = SPDX_IDS_BY_SHORT_NAME.get(&name) {
+        return Some(spdx_id.to_string());
+    }
+    KNOWN_SPDX_IDS.get(&name).map(|s| s.to_string())
+}
+
+// The idstring of a LicenseRef can only contain letters, numbers, `.` and `-`.
+fn get_license_ref(name: &str) -> String {
+    let id_string: String = name
+        .chars()
+        .map(|c| match c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+            true => c,
+            false => '-',
+        })
+        .collect();
+    format!("{}{}", LICENSE_REF_PREFIX, id_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_details(
+        spdx_id: Option<&str>,
+        short_name: Option<&str>,
+        full_name: Option<&str>,
+    ) -> crate::nix::PackageLicense {
+        crate::nix::PackageLicense::Details(crate::nix::LicenseDetails {
+            free: None,
+            redistributable: None,
+            deprecated: None,
+            short_name: short_name.map(|s| s.to_string()),
+            full_name: full_name.map(|s| s.to_string()),
+            spdx_id: spdx_id.map(|s| s.to_string()),
+        })
+    }
+
+    #[test]
+    pub fn test_normalize_license() {
+        let license = normalize_license(&get_details(Some("GPL-3.0-or-later"), Some("gpl3Plus"), None));
+        assert_eq!(license.id, "GPL-3.0-or-later");
+        assert!(!license.is_license_ref);
+
+        let license = normalize_license(&get_details(None, Some("lgpl21Plus"), None));
+        assert_eq!(license.id, "LGPL-2.1-or-later");
+
+        let license = normalize_license(&get_details(None, Some("unfree"), Some("Unfree")));
+        assert_eq!(license.id, "LicenseRef-nixpkgs-unfree");
+        assert_eq!(license.name, Some("Unfree".to_string()));
+        assert!(license.is_license_ref);
+
+        let license = normalize_license(&get_details(None, None, Some("Public Domain")));
+        assert_eq!(license.id, "LicenseRef-nixpkgs-Public-Domain");
+
+        let license = normalize_license(&crate::nix::PackageLicense::Name("mit".to_string()));
+        assert_eq!(license.id, "MIT");
+
+        let license = normalize_license(&crate::nix::PackageLicense::Name("MIT OR Apache-2.0".to_string()));
+        assert_eq!(license.id, "MIT OR Apache-2.0");
+        assert!(license.is_expression);
+
+        let license = normalize_license(&crate::nix::PackageLicense::Name("custom license".to_string()));
+        assert_eq!(license.id, "LicenseRef-nixpkgs-custom-license");
+    }
+
+    #[test]
+    pub fn test_get_license_expression() {
+        let licenses = normalize_licenses(&[
+            get_details(Some("GPL-2.0-or-later"), Some("gpl2Plus"), None),
+            get_details(None, Some("unfree"), Some("Unfree")),
+            crate::nix::PackageLicense::Name("MIT OR Apache-2.0".to_string()),
+            get_details(None, Some("gpl2Plus"), None),
+        ]);
+        assert_eq!(licenses.len(), 3);
+        assert_eq!(
+            get_license_expression(&licenses, &LicenseJoinPolicy::And),
+            Some("GPL-2.0-or-later AND LicenseRef-nixpkgs-unfree AND (MIT OR Apache-2.0)".to_string())
+        );
+        assert_eq!(
+            get_license_expression(&licenses[..2], &LicenseJoinPolicy::Or),
+            Some("GPL-2.0-or-later OR LicenseRef-nixpkgs-unfree".to_string())
+        );
+        assert_eq!(
+            get_license_expression(&licenses[2..], &LicenseJoinPolicy::And),
+            Some("MIT OR Apache-2.0".to_string())
+        );
+        assert_eq!(get_license_expression(&[], &LicenseJoinPolicy::And), None);
+    }
+}
@@ -0,0 +1,340 @@
+// Checks the licenses of the packages against a policy, so that CI can fail when a
+// closure pulls in licenses which are not allowed for a product. The policy is a YAML
+// (or JSON) file like this one:
+//
+// allow: ["MIT", "Apache-2.0", "BSD-*", "lgpl21Plus"]
+// deny: ["AGPL-*", "unfree"]
+// require_free: true
+// require_redistributable: true
+// require_license: false
+// require_metadata: false
+// exceptions: ["linux-firmware"]
+//
+// The licenses are matched with their SPDX ID or their nixpkgs short name, and a
+// trailing `*` matches any suffix.
+use serde::Deserialize;
+
+// Exit status used when the policy is violated, to distinguish it from other errors.
+pub const POLICY_VIOLATION_EXIT_CODE: u8 = 2;
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Default)]
+#[derive(PartialEq)]
+#[derive(Deserialize)]
+pub struct LicensePolicy {
+    // When not empty, every license has to match one of these.
+    #[serde(default)]
+    pub allow: Vec<String>,
+
+    // Licenses which cannot be used, even if they are in the allow list.
+    #[serde(default)]
+    pub deny: Vec<String>,
+
+    // Reject the licenses which are not free, and the packages marked as unfree.
+    #[serde(default)]
+    pub require_free: bool,
+
+    // Reject the licenses which do not allow redistributing the package.
+    #[serde(default)]
+    pub require_redistributable: bool,
+
+    // Reject the packages which have metadata, but no license.
+    #[serde(default)]
+    pub require_license: bool,
+
+    // Reject the packages which were not found in the metadata, since their licenses
+    // cannot be checked.
+    #[serde(default)]
+    pub require_metadata: bool,
+
+    // Names of the packages which are not checked.
+    #[serde(default)]
+    pub exceptions: Vec<String>,
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(PartialEq)]
+pub struct LicenseViolation {
+    pub derivation_path: String,
+    pub package_name: String,
+    // The SPDX ID or the name of the license, if the violation is about a license.
+    pub license: Option<String>,
+    pub reason: String,
+}
+
+impl LicenseViolation {
+    pub fn to_string(&self) -> String {
+        match &self.license {
+            Some(license) => format!(
+                "{} ({}): {} {}",
+                self.package_name, self.derivation_path, license, self.reason
+            ),
+            None => format!(
+                "{} ({}): {}",
+                self.package_name, self.derivation_path, self.reason
+            ),
+        }
+    }
+}
+
+impl LicensePolicy {
+    pub fn read(policy_path: &str) -> Result<LicensePolicy, String> {
+        log::info!("Using the license policy from {}", policy_path);
+        let content =
+            std::fs::read(policy_path).map_err(|e| format!("Could not read {}: {}", policy_path, e))?;
+        // YAML is a superset of JSON, so this also parses JSON policies.
+        serde_yaml::from_slice(&content)
+            .map_err(|e| format!("Could not parse the license policy {}: {}", policy_path, e))
+    }
+
+    // Returns the violations of the policy, sorted by derivation path. The licenses are only
+    // known for the packages found in the metadata, so the check fails when none was found,
+    // for example with --no-meta, instead of passing without checking anything.
+    pub fn check(&self, package_graph: &crate::nix::PackageGraph) -> Result<Vec<LicenseViolation>, String> {
+        if package_graph.nodes.len() != 0 && package_graph.nodes.values().all(|n| n.package.is_none()) {
+            return Err(
+                "Cannot check the license policy: none of the packages were found in the metadata".to_string(),
+            );
+        }
+        let mut violations: Vec<LicenseViolation> = vec![];
+        for (derivation_path, package_node) in package_graph.nodes.iter() {
+            // Only the packages found in the metadata have license information.
+            let package = match &package_node.package {
+                Some(p) => p,
+                None => {
+                    if !self.require_metadata {
+                        continue;
+                    }
+                    let name = package_node.get_name().unwrap_or(derivation_path.to_string());
+                    if self.exceptions.contains(&name) {
+                        continue;
+                    }
+                    violations.push(LicenseViolation {
+                        derivation_path: derivation_path.to_string(),
+                        package_name: package_node
+                            .main_derivation
+                            .env
+                            .get("name")
+                            .cloned()
+                            .unwrap_or(name),
+                        license: None,
+                        reason: "was not found in the metadata".to_string(),
+                    });
+                    continue;
+                }
+            };
+            let name = package_node.get_name().unwrap_or(package.name.to_string());
+            if self.exceptions.contains(&name) || self.exceptions.contains(&package.name) {
+                continue;
+            }
+            let mut add_violation = |license: Option<String>, reason: &str| {
+                violations.push(LicenseViolation {
+                    derivation_path: derivation_path.to_string(),
+                    package_name: package.name.to_string(),
+                    license,
+                    reason: reason.to_string(),
+                });
+            };
+
+            if self.require_free && package.meta.unfree.unwrap_or(false) {
+                add_violation(None, "is marked as unfree");
+            }
+
+            let licenses = package.meta.get_licenses();
+            if self.require_license && licenses.len() == 0 {
+                add_violation(None, "has no license");
+            }
+            for license in licenses {
+                let (license_name, identifiers) = get_license_identifiers(&license);
+                if self.deny.iter().any(|p| matches_any(p, &identifiers)) {
+                    add_violation(Some(license_name.to_string()), "is denied by the policy");
+                    continue;
+                }
+                if self.allow.len() != 0 && !self.allow.iter().any(|p| matches_any(p, &identifiers)) {
+                    add_violation(Some(license_name.to_string()), "is not allowed by the policy");
+                    continue;
+                }
+                if let crate::nix::PackageLicense::Details(details) = &license {
+                    if self.require_free && details.free == Some(false) {
+                        add_violation(Some(license_name.to_string()), "is not free");
+                    } else if self.require_redistributable && details.redistributable == Some(false) {
+                        add_violation(Some(license_name.to_string()), "is not redistributable");
+                    }
+                }
+            }
+        }
+        Ok(violations)
+    }
+}
+
+// Human-readable report of the violations, one line per violation.
+pub fn get_report(violations: &[LicenseViolation]) -> String {
+    let mut lines: Vec<String> = vec![format!(
+        "Found {} license policy violation{}:",
+        violations.len(),
+        if violations.len() == 1 { "" } else { "s" }
+    )];
+    for violation in violations {
+        lines.push(format!("  {}", violation.to_string()));
+    }
+    lines.join("\n")
+}
+
+// Returns the name used to report a license, and all the identifiers it can be matched with.
+fn get_license_identifiers(license: &crate::nix::PackageLicense) -> (String, Vec<String>) {
+    match license {
+        crate::nix::PackageLicense::Name(n) => (n.to_string(), vec![n.to_string()]),
+        crate::nix::PackageLicense::Details(details) => {
+            let mut identifiers: Vec<String> = vec![];
+            if let Some(spdx_id) = &details.spdx_id {
+                identifiers.push(spdx_id.to_string());
+            }
+            if let Some(short_name) = &details.short_name {
+                identifiers.push(short_name.to_string());
+            }
+            let license_name = match identifiers.first() {
+                Some(i) => i.to_string(),
+                None => details.full_name.clone().unwrap_or("unknown".to_string()),
+            };
+            (license_name, identifiers)
+        }
+    }
+}
+
+// Matches a pattern of the policy, where a trailing `*` matches any suffix. The licenses
+// are compared without case, like SPDX does.
+fn matches_any(pattern: &str, identifiers: &[String]) -> bool {
+    let pattern = pattern.to_lowercase();
+    identifiers.iter().any(|identifier| {
+        let identifier = identifier.to_lowercase();
+        match pattern.strip_suffix('*') {
+            Some(prefix) => identifier.starts_with(prefix),
+            None => identifier == pattern,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The licenses come from the metadata of the test packages, like in a real run.
+    fn get_test_package_graph() -> crate::nix::PackageGraph {
+        crate::nix::get_package_graph_next(
+            &crate::nix::get_test_derivations(),
+            &crate::nix::get_test_packages(),
+        )
+    }
+
+    #[test]
+    pub fn test_read_policy() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let policy_path = temp_dir.path().join("license-policy.yaml");
+        std::fs::write(&policy_path, "allow: [MIT, \"BSD-*\"]\nrequire_free: true\n").unwrap();
+        let policy = LicensePolicy::read(policy_path.to_str().unwrap()).unwrap();
+        assert_eq!(policy.allow, vec!["MIT".to_string(), "BSD-*".to_string()]);
+        assert!(policy.require_free);
+        assert!(!policy.require_redistributable);
+    }
+
+    #[test]
+    pub fn test_check_policy() {
+        let package_graph = get_test_package_graph();
+
+        let policy = LicensePolicy::default();
+        assert_eq!(policy.check(&package_graph).unwrap().len(), 0);
+
+        let policy = LicensePolicy {
+            deny: vec!["GPL-*".to_string()],
+            require_free: true,
+            ..LicensePolicy::default()
+        };
+        let violations = policy.check(&package_graph).unwrap();
+        let report: Vec<String> = violations.iter().map(|v| v.to_string()).collect();
+        assert_eq!(
+            report,
+            vec![
+                "hello-2.12.1 (/nix/store/aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa-hello-2.12.1.drv): GPL-3.0-or-later is denied by the policy",
+                "glibc-2.38 (/nix/store/dddddddddddddddddddddddddddddddd-glibc-2.38.drv): unfree is not free",
+            ]
+        );
+
+        let policy = LicensePolicy {
+            allow: vec!["gpl3Plus".to_string(), "LGPL-2.1-or-later".to_string()],
+            ..LicensePolicy::default()
+        };
+        let violations = policy.check(&package_graph).unwrap();
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].license, Some("unfree".to_string()));
+        assert_eq!(violations[0].reason, "is not allowed by the policy");
+
+        let policy = LicensePolicy {
+            require_redistributable: true,
+            exceptions: vec!["glibc".to_string()],
+            ..LicensePolicy::default()
+        };
+        assert_eq!(policy.check(&package_graph).unwrap().len(), 0);
+    }
+
+    #[test]
+    pub fn test_check_policy_with_unmatched_packages() {
+        // Only hello and glibc are found in the metadata.
+        let package_graph = get_test_package_graph();
+
+        let policy = LicensePolicy {
+            require_license: true,
+            ..LicensePolicy::default()
+        };
+        assert_eq!(policy.check(&package_graph).unwrap().len(), 0);
+
+        let policy = LicensePolicy {
+            require_metadata: true,
+            ..LicensePolicy::default()
+        };
+        let violations = policy.check(&package_graph).unwrap();
+        let report: Vec<String> = violations.iter().map(|v| v.to_string()).collect();
+        assert_eq!(
+            report,
+            vec![
+                "gettext-0.21.1 (/nix/store/bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb-gettext-0.21.1.drv): was not found in the metadata",
+                "fix.patch (/nix/store/cccccccccccccccccccccccccccccccc-fix.patch.drv): was not found in the metadata",
+            ]
+        );
+
+        let policy = LicensePolicy {
+            require_metadata: true,
+            exceptions: vec!["gettext".to_string(), "fix.patch".to_string()],
+            ..LicensePolicy::default()
+        };
+        assert_eq!(policy.check(&package_graph).unwrap().len(), 0);
+    }
+
+    #[test]
+    pub fn test_check_policy_without_metadata() {
+        let package_graph = crate::nix::get_test_package_graph();
+        assert!(LicensePolicy::default().check(&package_graph).is_err());
+        assert_eq!(
+            LicensePolicy::default()
+                .check(&crate::nix::PackageGraph::default())
+                .unwrap()
+                .len(),
+            0
+        );
+    }
+
+    #[test]
+    pub fn test_get_report() {
+        let violations = vec![LicenseViolation {
+            derivation_path: "/nix/store/x.drv".to_string(),
+            package_name: "x-1.0".to_string(),
+            license: None,
+            reason: "has no license".to_string(),
+        }];
+        assert_eq!(
+            get_report(&violations),
+            "Found 1 license policy violation:\n  x-1.0 (/nix/store/x.drv): has no license"
+        );
+    }
+}
@@ -14,36 +14,171 @@ use clap::Parser;
 #[clap(version = env!("CARGO_PKG_VERSION"))]
 #[clap(about = "nix2sbom extracts the SBOM (Software Bill of Materials) from a Nix derivation", long_about = None)]
 struct NixToSBOM {
+    /// Installables to extract a SBOM manifest from, like nixpkgs#hello, github:org/repo#pkg or
+    /// .#nixosConfigurations.host. Each installable is resolved to its closure with the nix
+    /// command, and the closures are merged in the same SBOM.
+    installables: Vec<String>,
+
     /// Path of the file to extract a SBOM manifest from.
     #[clap(long, short)]
     file_path: Option<String>,
 
-    /// Output format for the SBOM manifest. Defaults to cdx (CycloneDX).
+    /// Output format for the SBOM manifest. Defaults to cdx (CycloneDX). Use spdx for SPDX 2.3,
+    /// and graph to dump the package graph so that it can be compared with --diff.
     #[clap(long)]
     format: Option<String>,
 
-    /// Which format to use for serializing the SBOM. CycloneDX supports yaml and json.
+    /// Which format to use for serializing the SBOM. CycloneDX supports json, yaml and xml.
+    /// SPDX supports json, yaml and the tag-value format (spdx).
     #[clap(short, long)]
     serialization_format: Option<String>,
 
+    /// Version of the CycloneDX specification to use. Supports 1.4, 1.5 and 1.6. Defaults to 1.4.
+    #[clap(long)]
+    spec_version: Option<String>,
+
+    /// Timestamp to use in the SBOM, as a RFC 3339 date or a number of seconds since the Unix epoch.
+    /// Defaults to the value of SOURCE_DATE_EPOCH if set, and to the current time otherwise.
+    #[clap(long)]
+    timestamp: Option<String>,
+
+    /// Path of an existing derivations file, or - to read it from stdin. When provided,
+    /// Nix is not invoked to get the derivations.
+    ///
+    /// This file can be generated by using the following command:
+    /// nix derivation show -r <installable>
+    #[clap(long)]
+    derivations_path: Option<String>,
+
+    /// Path of a .drv file to read directly from the Nix store. The input derivations are
+    /// read recursively, and Nix is not invoked to get the derivations.
+    #[clap(long)]
+    drv_path: Option<String>,
+
+    /// Root directory of the Nix store, when the store is not mounted at /. Used to read the
+    /// .drv files and the Nix database. Defaults to /, except with --derivations-path, for
+    /// which the Nix database is only used when this is set explicitly.
+    #[clap(long)]
+    store_root: Option<String>,
+
     /// Path of an existing package metadata file.
     ///
     /// This file can be generated by using the following command:
-    /// nix-env -q -a --meta --json '.*'
+    /// nix-env -q -a --meta --out-path --drv-path --json '.*'
+    ///
+    /// Without the paths, the metadata is matched with the derivations by name only.
     #[clap(long)]
     metadata_path: Option<String>,
 
+    /// Path of a JSON file mapping package names to the vendor and product of their CPE,
+    /// which take precedence over the built-in mappings. For example:
+    /// { "openssl": { "vendor": "openssl", "product": "openssl" } }
+    #[clap(long)]
+    cpe_mappings_path: Option<String>,
+
+    /// Path of a local vulnerability database to match the packages against. This can be a
+    /// directory of OSV advisories, a single OSV advisory, or an uncompressed NVD JSON 2.0 feed.
+    /// Can be used multiple times. The matches are added to the CycloneDX SBOM and to the stats.
+    #[clap(long)]
+    vulnerability_database_path: Vec<String>,
+
+    /// Path of a license policy file, in YAML or JSON, with the allowed and denied licenses.
+    /// The licenses of every package are checked against the policy after generating the SBOM,
+    /// and the violations are reported with exit status 2.
+    #[clap(long)]
+    license_policy_path: Option<String>,
+
+    /// Operator used to combine the licenses of the packages which have more than one, either
+    /// and or or. nixpkgs does not say if all the licenses apply or if one can be chosen, so
+    /// this defaults to and.
+    #[clap(long)]
+    license_join_policy: Option<String>,
+
+    /// Prefix of the namespace of the SPDX documents, which should be a URL controlled by the
+    /// creator of the document. The name of the document and a UUID are appended to it.
+    #[clap(long)]
+    spdx_namespace_prefix: Option<String>,
+
+    /// Path of the flake.lock file of the flake, whose locked inputs are added to the SBOM as
+    /// the sources of the root components. Defaults to the lock file of the first installable
+    /// which is a local flake, like .#hello.
+    #[clap(long)]
+    flake_lock_path: Option<String>,
+
     /// Do not use the metadata from the store to generate the SBOM.
     #[clap(long, short)]
     no_meta: bool,
 
-    /// Include only the runtime dependencies in the SBOM.
+    /// Include only the runtime dependencies in the SBOM. The references of the outputs found
+    /// in the Nix database are used when available, and the build inputs otherwise.
     #[clap(long, short)]
     runtime_only: bool,
 
     /// Generate a SBOM for the current system.
     #[clap(long, short)]
     current_system: bool,
+
+    /// Compare two inputs instead of generating a SBOM, and report the components which were
+    /// added, removed, upgraded, downgraded or re-licensed, and the patches which changed.
+    /// The inputs can be Nix files, package graphs dumped with --format graph, or CycloneDX
+    /// SBOMs. The report is printed as text, or as JSON with --serialization-format json.
+    #[clap(long, num_args = 2, value_names = ["OLD", "NEW"])]
+    diff: Vec<String>,
+
+    /// Paths of the package metadata files of the two Nix files compared with --diff, in the
+    /// same format as --metadata-path. The licenses come from the metadata, so the re-licensed
+    /// packages can only be found when each Nix file has the metadata of its own nixpkgs.
+    /// Defaults to the metadata of --metadata-path for both files.
+    #[clap(long, num_args = 2, value_names = ["OLD", "NEW"])]
+    diff_metadata_path: Vec<String>,
+}
+
+// Uses the references of the outputs in the Nix store to get the real runtime dependencies.
+// Without a store root, the types guessed from the build inputs are kept.
+fn set_runtime_references(
+    package_graph: &mut nix2sbom::nix::PackageGraph,
+    store_root: &Option<String>,
+    runtime_only: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let store_root = match store_root {
+        Some(r) => r,
+        None => return Ok(()),
+    };
+    match nix2sbom::store_db::StoreDatabase::open(store_root) {
+        Ok(store_database) => package_graph.set_runtime_references(&store_database)?,
+        Err(e) if runtime_only => log::warn!(
+            "{}. Using the build inputs to detect the runtime dependencies instead.",
+            e
+        ),
+        Err(e) => log::debug!("{}", e),
+    };
+    Ok(())
+}
+
+// Gets the components to compare from a Nix file or from a dump.
+fn get_diff_components(
+    input_path: &str,
+    metadata_path: &Option<String>,
+    no_meta: bool,
+    store_root: &Option<String>,
+    options: &nix2sbom::nix::DumpOptions,
+) -> Result<Vec<nix2sbom::diff::DiffComponent>, Box<dyn std::error::Error>> {
+    if !input_path.ends_with(".nix") {
+        return Ok(nix2sbom::diff::read_components(input_path, options)
+            .map_err(nix2sbom::errors::Error::UnknownError)?);
+    }
+    log::info!("Getting the derivations from {}", input_path);
+    let derivations = nix2sbom::nix::Derivation::get_derivations(input_path)?;
+    let packages = nix2sbom::nix::get_packages(metadata_path.clone(), no_meta)?;
+    let mut package_graph = nix2sbom::nix::get_package_graph_next(&derivations, &packages);
+    set_runtime_references(&mut package_graph, store_root, options.runtime_only)?;
+    if options.runtime_only {
+        package_graph.retain_runtime_closure();
+    }
+    Ok(nix2sbom::diff::get_components_from_package_graph(
+        &package_graph,
+        options,
+    ))
 }
 
 fn main() -> Result<std::process::ExitCode, Box<dyn std::error::Error>> {
@@ -61,8 +196,8 @@ fn main() -> Result<std::process::ExitCode, Box<dyn std::error::Error>> {
         None => nix2sbom::sbom::Format::default(),
     };
 
-    let serialization_format = match args.serialization_format {
-        Some(f) => match nix2sbom::sbom::SerializationFormat::from_string(&f) {
+    let serialization_format = match &args.serialization_format {
+        Some(f) => match nix2sbom::sbom::SerializationFormat::from_string(f) {
             Some(f) => f,
             None => {
                 eprintln!("Invalid serialization format {}", &f);
@@ -72,14 +207,128 @@ fn main() -> Result<std::process::ExitCode, Box<dyn std::error::Error>> {
         None => output_format.get_default_serialization_format(),
     };
 
-    let derivations: nix2sbom::nix::Derivations = if let Some(file_path) = args.file_path {
+    let cyclone_dx_spec_version = match args.spec_version {
+        Some(v) => match nix2sbom::cyclone_dx::SpecVersion::from_string(&v) {
+            Some(v) => v,
+            None => {
+                eprintln!("Invalid CycloneDX spec version {}", &v);
+                return Ok(std::process::ExitCode::FAILURE);
+            }
+        },
+        None => nix2sbom::cyclone_dx::SpecVersion::default(),
+    };
+
+    let license_join_policy = match args.license_join_policy {
+        Some(p) => match nix2sbom::license::LicenseJoinPolicy::from_string(&p) {
+            Some(p) => p,
+            None => {
+                eprintln!("Invalid license join policy {}", &p);
+                return Ok(std::process::ExitCode::FAILURE);
+            }
+        },
+        None => nix2sbom::license::LicenseJoinPolicy::default(),
+    };
+
+    let timestamp = match args
+        .timestamp
+        .or(std::env::var(nix2sbom::sbom::SOURCE_DATE_EPOCH_VAR_NAME).ok())
+    {
+        Some(t) => match nix2sbom::sbom::parse_timestamp(&t) {
+            Some(t) => Some(t),
+            None => {
+                eprintln!("Invalid timestamp {}", &t);
+                return Ok(std::process::ExitCode::FAILURE);
+            }
+        },
+        None => None,
+    };
+
+    if args.diff.len() != 0 {
+        let mut diff_options = nix2sbom::nix::DumpOptions::default();
+        diff_options.runtime_only = args.runtime_only;
+        diff_options.license_join_policy = license_join_policy;
+        let (old_metadata_path, new_metadata_path) = match args.diff_metadata_path.as_slice() {
+            [old, new] => (Some(old.to_string()), Some(new.to_string())),
+            _ => {
+                if !args.no_meta && args.diff.iter().all(|p| p.ends_with(".nix")) {
+                    log::warn!(
+                        "The same package metadata is used for both Nix files, so the re-licensed packages \
+                         cannot be found. Use --diff-metadata-path to compare their licenses."
+                    );
+                }
+                (args.metadata_path.clone(), args.metadata_path.clone())
+            }
+        };
+        // The Nix files are evaluated with the local store.
+        let store_root = Some(args.store_root.clone().unwrap_or("/".to_string()));
+        let old_components = get_diff_components(
+            &args.diff[0],
+            &old_metadata_path,
+            args.no_meta,
+            &store_root,
+            &diff_options,
+        )?;
+        let new_components = get_diff_components(
+            &args.diff[1],
+            &new_metadata_path,
+            args.no_meta,
+            &store_root,
+            &diff_options,
+        )?;
+        let sbom_diff = nix2sbom::diff::diff(&old_components, &new_components);
+        // The report is printed as text unless JSON was explicitly requested.
+        let report = if args.serialization_format.is_some()
+            && serialization_format == nix2sbom::sbom::SerializationFormat::JSON
+        {
+            sbom_diff.to_json()
+        } else {
+            Ok(sbom_diff.to_text())
+        };
+        match report {
+            Ok(r) => println!("{}", r),
+            Err(e) => {
+                eprintln!("{}", e);
+                return Ok(std::process::ExitCode::FAILURE);
+            }
+        }
+        return Ok(std::process::ExitCode::SUCCESS);
+    }
+
+    let mut system_metadata = nix2sbom::nixos::SystemMetadata::default();
+    let read_from_file = args.derivations_path.is_some();
+    let derivations: nix2sbom::nix::Derivations = if let Some(derivations_path) = args.derivations_path {
+        log::info!("Reading the derivations from {}", &derivations_path);
+        nix2sbom::nix::Derivation::read_derivations(&derivations_path)?
+    } else if let Some(drv_path) = args.drv_path {
+        let store_root = args.store_root.clone().unwrap_or("/".to_string());
+        log::info!("Reading the derivations from {} in {}", &drv_path, &store_root);
+        match nix2sbom::aterm::read_derivations(&drv_path, &store_root) {
+            Ok(d) => d,
+            Err(e) => {
+                eprintln!("{}", e);
+                return Ok(std::process::ExitCode::FAILURE);
+            }
+        }
+    } else if let Some(file_path) = args.file_path {
         log::info!("Getting the derivations from {}", &file_path);
         nix2sbom::nix::Derivation::get_derivations(&file_path)?
+    } else if args.installables.len() != 0 {
+        system_metadata.name = args
+            .installables
+            .iter()
+            .find_map(|i| nix2sbom::nix::get_nixos_configuration_name(i));
+        nix2sbom::nix::Derivation::get_derivations_for_installables(&args.installables)?
     } else if args.current_system {
         log::info!("Getting the derivations from the current system");
+        system_metadata = nix2sbom::nixos::SystemMetadata::read_system(
+            nix2sbom::nix::CURRENT_SYSTEM_PATH,
+            nix2sbom::nixos::NIXOS_CHANNEL_PATH,
+        );
         nix2sbom::nix::Derivation::get_derivations_for_current_system()?
     } else {
-        eprintln!("Error: Must provide a file or use the --curent-system argument");
+        eprintln!(
+            "Error: Must provide an installable, a file, a derivations file, a .drv file or use the --current-system argument"
+        );
         return Ok(std::process::ExitCode::FAILURE);
     };
     log::info!("Found {} derivations", derivations.len());
@@ -89,7 +338,18 @@ fn main() -> Result<std::process::ExitCode, Box<dyn std::error::Error>> {
 
     log::info!("Building the package graph");
     // let package_graph = nix2sbom::nix::get_package_graph(&derivations, &packages);
-    let package_graph = nix2sbom::nix::get_package_graph_next(&derivations, &packages);
+    let mut package_graph = nix2sbom::nix::get_package_graph_next(&derivations, &packages);
+
+    // The derivations read from a derivations file were not necessarily built on this host.
+    let store_root = match args.store_root {
+        Some(r) => Some(r),
+        None if !read_from_file => Some("/".to_string()),
+        None => None,
+    };
+    set_runtime_references(&mut package_graph, &store_root, args.runtime_only)?;
+    if args.runtime_only {
+        package_graph.retain_runtime_closure();
+    }
     log::info!("{} nodes in the package graph", package_graph.nodes.len());
     log::debug!(
         "{} root nodes in the package graph",
@@ -100,16 +360,99 @@ fn main() -> Result<std::process::ExitCode, Box<dyn std::error::Error>> {
 
     let mut dump_options = nix2sbom::nix::DumpOptions::default();
     dump_options.runtime_only = args.runtime_only;
+    dump_options.cyclone_dx_spec_version = cyclone_dx_spec_version;
+    dump_options.timestamp = timestamp;
+    dump_options.license_join_policy = license_join_policy;
+    dump_options.document_namespace_prefix = args.spdx_namespace_prefix.clone();
+    if let Some(cpe_mappings_path) = &args.cpe_mappings_path {
+        dump_options.cpe_mappings = match nix2sbom::cpe::read_mappings(cpe_mappings_path) {
+            Ok(m) => m,
+            Err(e) => {
+                eprintln!("{}", e);
+                return Ok(std::process::ExitCode::FAILURE);
+            }
+        };
+    }
+
+    let flake_lock_path = match &args.flake_lock_path {
+        Some(p) => Some(p.to_string()),
+        None => args
+            .installables
+            .iter()
+            .find_map(|i| nix2sbom::flake_lock::get_local_lock_path(i)),
+    };
+    if let Some(flake_lock_path) = flake_lock_path {
+        dump_options.flake_lock = match nix2sbom::flake_lock::FlakeLock::read(&flake_lock_path) {
+            Ok(l) => Some(l),
+            Err(e) => {
+                eprintln!("{}", e);
+                return Ok(std::process::ExitCode::FAILURE);
+            }
+        };
+    }
+
+    // The revision of nixpkgs is taken from the lock file when the system does not have it.
+    if let Some(flake_lock) = &dump_options.flake_lock {
+        system_metadata.merge(nix2sbom::nixos::SystemMetadata::from_flake_lock(flake_lock));
+    }
+    dump_options.system_metadata = system_metadata;
+
+    if args.vulnerability_database_path.len() != 0 {
+        let mut vulnerability_database = nix2sbom::vulnerabilities::VulnerabilityDatabase::default();
+        for database_path in &args.vulnerability_database_path {
+            if let Err(e) = vulnerability_database.read(database_path) {
+                eprintln!("{}", e);
+                return Ok(std::process::ExitCode::FAILURE);
+            }
+        }
+        log::info!(
+            "Found {} vulnerabilities in the vulnerability databases",
+            vulnerability_database.len()
+        );
+        dump_options.vulnerabilities =
+            vulnerability_database.get_vulnerabilities(&package_graph, &dump_options);
+        log::info!(
+            "{} vulnerabilities affect the packages",
+            dump_options.vulnerabilities.len()
+        );
+    }
+
+    let license_policy = match &args.license_policy_path {
+        Some(license_policy_path) => match nix2sbom::license_policy::LicensePolicy::read(license_policy_path) {
+            Ok(p) => Some(p),
+            Err(e) => {
+                eprintln!("{}", e);
+                return Ok(std::process::ExitCode::FAILURE);
+            }
+        },
+        None => None,
+    };
 
     let sbom_dump = match output_format.dump(&serialization_format, &package_graph, &dump_options) {
         Ok(d) => d,
         Err(e) => {
-            eprintln!("{}", e.to_string());
+            eprintln!("{}", e);
             return Ok(std::process::ExitCode::FAILURE);
         }
     };
 
     println!("{}", sbom_dump);
 
+    if let Some(license_policy) = license_policy {
+        let violations = match license_policy.check(&package_graph) {
+            Ok(v) => v,
+            Err(e) => {
+                eprintln!("{}", e);
+                return Ok(std::process::ExitCode::FAILURE);
+            }
+        };
+        if violations.len() != 0 {
+            eprintln!("{}", nix2sbom::license_policy::get_report(&violations));
+            return Ok(std::process::ExitCode::from(
+                nix2sbom::license_policy::POLICY_VIOLATION_EXIT_CODE,
+            ));
+        }
+    }
+
     Ok(std::process::ExitCode::SUCCESS)
 }
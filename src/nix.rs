@@ -2,13 +2,21 @@ use std::error::Error;
 
 use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::fs;
+use std::io::Read;
 use std::process::Command;
 
-use serde::{Deserialize, Deserializer, Serialize};
+use serde::{Deserialize, Serialize};
 
 // This is a special file used By NixOS to represent the derivations
 // that were used to build the current system.
-const CURRENT_SYSTEM_PATH: &str = "/run/current-system";
+pub const CURRENT_SYSTEM_PATH: &str = "/run/current-system";
+
+// Path used to read an input from stdin instead of a file.
+const STDIN_PATH: &str = "-";
+
+// A NixOS configuration is not a derivation, the system is built from its toplevel attribute.
+const NIXOS_CONFIGURATIONS_PREFIX: &str = "nixosConfigurations.";
+const NIXOS_TOPLEVEL_ATTRIBUTE_PATH: &str = "config.system.build.toplevel";
 
 #[derive(Debug)]
 #[derive(Deserialize)]
@@ -37,6 +45,23 @@ pub struct DisplayOptions {
 #[derive(Default)]
 pub struct DumpOptions {
     pub runtime_only: bool,
+    pub cyclone_dx_spec_version: crate::cyclone_dx::SpecVersion,
+    // Timestamp to use in the generated SBOM. The current time is used when not set,
+    // which makes the output non-reproducible.
+    pub timestamp: Option<chrono::DateTime<chrono::Utc>>,
+    // CPE mappings supplied by the user, which take precedence over the built-in ones.
+    pub cpe_mappings: crate::cpe::CpeMappings,
+    // Vulnerabilities affecting the packages, found in the vulnerability databases.
+    pub vulnerabilities: Vec<crate::vulnerabilities::Vulnerability>,
+    // Operator used to combine the licenses of the packages which have more than one.
+    pub license_join_policy: crate::license::LicenseJoinPolicy,
+    // The lock file of the flake, whose inputs are added to the SBOM.
+    pub flake_lock: Option<crate::flake_lock::FlakeLock>,
+    // The NixOS system and the revision of nixpkgs which produced the closure.
+    pub system_metadata: crate::nixos::SystemMetadata,
+    // Prefix of the namespace of the SPDX documents, which should be a URL controlled by the
+    // creator of the document.
+    pub document_namespace_prefix: Option<String>,
 }
 
 pub enum PackageScope {
@@ -87,7 +112,8 @@ pub fn is_stdenv(name: &str) -> bool {
 
 impl DerivationBuilder {
     pub fn from_string(builder: &str) -> Result<DerivationBuilder, String> {
-        if builder == "builtin:fetchurl" {
+        // The names of the variants are accepted for the package graphs dumped by older versions.
+        if builder == "builtin:fetchurl" || builder == "FetchURL" {
             return Ok(DerivationBuilder::FetchURL);
         }
         if builder.ends_with("/bin/bash") || builder == "Bash" {
@@ -101,18 +127,6 @@ impl DerivationBuilder {
         // builders found in the wild.
         // Err(format!("Invalid derivation builder {}.", builder))
     }
-
-    pub fn deserialize<'de, D>(deserializer: D) -> Result<DerivationBuilder, D::Error>
-    where
-        D: Deserializer<'de>,
-    {
-        let buf = String::deserialize(deserializer)?;
-
-        match DerivationBuilder::from_string(&buf) {
-            Ok(b) => Ok(b),
-            Err(e) => Err(e).map_err(serde::de::Error::custom),
-        }
-    }
 }
 
 #[derive(Debug)]
@@ -151,8 +165,8 @@ pub struct Derivation {
 
     pub system: String,
 
-    #[serde(deserialize_with = "DerivationBuilder::deserialize")]
-    pub builder: DerivationBuilder,
+    // The path of the builder, or the name of a builtin builder like builtin:fetchurl.
+    pub builder: String,
 
     pub args: Vec<String>,
 
@@ -213,6 +227,10 @@ impl BuildInputType {
 }
 
 impl Derivation {
+    pub fn get_builder(&self) -> DerivationBuilder {
+        DerivationBuilder::from_string(&self.builder).unwrap_or(DerivationBuilder::Unknown)
+    }
+
     pub fn get_derivations_for_current_system() -> Result<Derivations, Box<dyn Error>> {
         Derivation::get_derivations(CURRENT_SYSTEM_PATH)
     }
@@ -248,24 +266,39 @@ impl Derivation {
         return serde_json::to_string_pretty(self).map_err(|e| e.to_string());
     }
 
-    pub fn build_and_get_derivations(
-        file_path: &str,
-        derivation_ref: &str,
-    ) -> Result<Derivations, Box<dyn Error>> {
-        let derivation_path = format!("{}#{}", file_path, derivation_ref);
-        let output = Command::new("nix")
-            .arg("build")
-            // FIXME we might want to disable impure by default.
-            .arg("--impure")
-            .arg("--show-out-paths")
-            .arg(derivation_path)
-            .output()?;
+    // Reads the output of a previous `nix derivation show -r` invocation, from a file
+    // or from stdin if the path is `-`. This does not require Nix to be installed.
+    pub fn read_derivations(derivations_path: &str) -> Result<Derivations, Box<dyn Error>> {
+        let content: Vec<u8> = if derivations_path == STDIN_PATH {
+            let mut content: Vec<u8> = vec![];
+            std::io::stdin().read_to_end(&mut content)?;
+            content
+        } else {
+            fs::read(derivations_path)?
+        };
 
-        let flat_derivations: Derivations = serde_json::from_slice(&output.stdout)?;
+        let flat_derivations: Derivations = match serde_json::from_slice(&content) {
+            Ok(d) => d,
+            Err(e) => {
+                return Err(format!("Could not parse derivations from {}: {}", derivations_path, e).into());
+            }
+        };
 
         Ok(flat_derivations)
     }
 
+    // Gets the closures of installables like `github:org/repo#pkg` or `.#nixosConfigurations.host`,
+    // merged into a single set of derivations.
+    pub fn get_derivations_for_installables(installables: &[String]) -> Result<Derivations, Box<dyn Error>> {
+        let mut derivations = Derivations::default();
+        for installable in installables {
+            let installable = get_installable(installable);
+            log::info!("Getting the derivations from {}", &installable);
+            derivations.extend(Derivation::get_derivations(&installable)?);
+        }
+        Ok(derivations)
+    }
+
     pub fn get_name(&self) -> Option<String> {
         if let Some(pname) = self.env.get("pname") {
             return Some(pname.to_string());
@@ -304,6 +337,25 @@ impl Derivation {
         self.env.get("src")
     }
 
+    // Returns the hash of the output of a fixed-output derivation, which is usually
+    // the hash of the source archive being fetched.
+    pub fn get_output_hash(&self) -> Option<crate::hash::Hash> {
+        let output_hash = match self.env.get("outputHash") {
+            Some(h) => h,
+            None => return None,
+        };
+        let output_hash_mode = match self.env.get("outputHashMode").map(|m| m.as_str()) {
+            Some("recursive") | Some("nar") => crate::hash::HashMode::Recursive,
+            _ => crate::hash::HashMode::Flat,
+        };
+        let output_hash_algo = self.env.get("outputHashAlgo").map(|a| a.as_str());
+        let hash = crate::hash::Hash::parse(output_hash, output_hash_algo, output_hash_mode);
+        if hash.is_none() {
+            log::warn!("Could not decode output hash {}", output_hash);
+        }
+        hash
+    }
+
     // Returns the main url of the derivation
     pub fn get_url(&self) -> Option<String> {
         let urls = self.get_urls();
@@ -356,7 +408,7 @@ impl Derivation {
 
     pub fn pretty_print(&self, depth: usize, _display_options: &DisplayOptions) -> Vec<PrettyPrintLine> {
         let mut response: Vec<PrettyPrintLine> = vec![];
-        for url in self.get_urls() {
+        if let Some(url) = self.get_url() {
             response.push(PrettyPrintLine::new(url, depth + 1));
             return response;
         }
@@ -432,25 +484,12 @@ impl Derivation {
 #[derive(Clone)]
 #[derive(PartialEq)]
 pub struct Output {
-    path: String,
+    pub path: String,
 }
 
-// pub fn get_dependencies(path: &str) -> Vec<String> {
-//     // TODO nix-store -qR /an/executable/path
-//     vec![]
-// }
-
-// Get the derivation path associated with a store object
-// pub fn get_derivation_path(store_path: &str) -> String {
-//     // TODO nix-store -qd store_path
-//     "".to_string()
-// }
-
 pub fn get_packages(metadata_path: Option<String>, no_meta: bool) -> Result<Packages, String> {
-    let mut packages: Packages = Packages::default();
-
     if no_meta {
-        return Ok(packages);
+        return Ok(Packages::default());
     }
 
     let content: Vec<u8> = if let Some(path) = metadata_path {
@@ -461,11 +500,14 @@ pub fn get_packages(metadata_path: Option<String>, no_meta: bool) -> Result<Pack
         // There is currently no way with Nix to generate the meta information
         // only for a single derivation. We need to generate the meta for
         // all the derivations in the store and then extract the information
-        // we want from the global meta database.
+        // we want from the global meta database. The paths of the packages are
+        // used to match them exactly with the derivations.
         let output = Command::new("nix-env")
             .arg("-q")
             .arg("-a")
             .arg("--meta")
+            .arg("--out-path")
+            .arg("--drv-path")
             .arg("--json")
             .arg(".*")
             .output()
@@ -473,12 +515,8 @@ pub fn get_packages(metadata_path: Option<String>, no_meta: bool) -> Result<Pack
         output.stdout
     };
 
-    let raw_packages: Packages = serde_json::from_slice(&content).map_err(|e| e.to_string())?;
-
-    // Re-index the packages using the internal package name.
-    for package in raw_packages.values() {
-        packages.insert(package.name.to_string(), package.clone());
-    }
+    // The packages are keyed by their attribute path, which is unique, unlike their name.
+    let packages: Packages = serde_json::from_slice(&content).map_err(|e| e.to_string())?;
 
     Ok(packages)
 }
@@ -490,32 +528,7 @@ pub struct Meta {
     pub packages: HashMap<String, PackageMeta>,
 }
 
-#[derive(Debug)]
-#[derive(Default)]
-pub struct PackageURL {
-    pub scheme: String,
-    pub host: String,
-    pub version: Option<String>,
-    pub path: Vec<String>,
-    pub query_params: HashMap<String, String>,
-}
-
-impl PackageURL {
-    pub fn to_string(&self) -> String {
-        let mut response = format!("{}://", self.scheme);
-        response += &self.host.clone();
-
-        let full_path = self.path.join("/");
-        if !full_path.is_empty() {
-            response += &full_path;
-        }
-
-        if let Some(version) = &self.version {
-            response += &("@".to_string() + version);
-        }
-        response
-    }
-}
+pub use crate::purl::PackageURL;
 
 #[derive(Debug)]
 #[derive(Clone)]
@@ -539,6 +552,15 @@ pub struct Package {
     #[serde(rename = "outputName")]
     pub output_name: String,
 
+    // path of the derivation, only reported with --drv-path
+    #[serde(rename = "drvPath")]
+    #[serde(default)]
+    pub drv_path: Option<String>,
+
+    // paths of the outputs, which are only reported with --out-path
+    #[serde(default)]
+    pub outputs: BTreeMap<String, Option<String>>,
+
     pub meta: PackageMeta,
 }
 impl Package {
@@ -547,9 +569,15 @@ impl Package {
         if self.meta.broken.unwrap_or(false) {
             response.push(PrettyPrintLine::new("broken: true", depth + 1));
         }
-        if self.meta.insecure.unwrap_or(false) {
+        if self.meta.is_insecure() {
             response.push(PrettyPrintLine::new("insecure: true", depth + 1));
         }
+        for known_vulnerability in self.meta.get_known_vulnerabilities() {
+            response.push(PrettyPrintLine::new(
+                format!("known vulnerability: {}", known_vulnerability),
+                depth + 1,
+            ));
+        }
         if self.meta.unfree.unwrap_or(false) {
             response.push(PrettyPrintLine::new("unfree: true", depth + 1));
         }
@@ -583,8 +611,65 @@ pub struct PackageMeta {
     pub maintainers: Option<PackageMaintainers>,
 
     pub license: Option<License>,
+
+    // Name of the main executable of the package, which is only set for applications.
+    #[serde(rename = "mainProgram")]
+    pub main_program: Option<String>,
+
+    // Free-form descriptions of the vulnerabilities of the package, which usually
+    // contain the CVE IDs. nixpkgs marks the package as insecure when this is not empty.
+    #[serde(rename = "knownVulnerabilities")]
+    pub known_vulnerabilities: Option<Vec<String>>,
 }
 impl PackageMeta {
+    pub fn get_known_vulnerabilities(&self) -> Vec<String> {
+        match &self.known_vulnerabilities {
+            Some(v) => v.clone(),
+            None => vec![],
+        }
+    }
+
+    pub fn is_insecure(&self) -> bool {
+        self.insecure.unwrap_or(false) || self.get_known_vulnerabilities().len() != 0
+    }
+
+    // Uses the unfree flag of the package, or the free flags of its licenses when it is not set.
+    // Returns None when the metadata does not say if the package is free.
+    pub fn get_unfree(&self) -> Option<bool> {
+        if let Some(unfree) = self.unfree {
+            return Some(unfree);
+        }
+        let free_flags: Vec<bool> = self
+            .get_licenses()
+            .iter()
+            .filter_map(|l| match l {
+                PackageLicense::Details(d) => d.free,
+                PackageLicense::Name(_) => None,
+            })
+            .collect();
+        if free_flags.len() == 0 {
+            return None;
+        }
+        Some(free_flags.contains(&false))
+    }
+
+    // A package can only be redistributed if all its licenses allow it. Like in nixpkgs, the
+    // licenses are redistributable by default when they are free.
+    pub fn get_redistributable(&self) -> Option<bool> {
+        let redistributable_flags: Vec<bool> = self
+            .get_licenses()
+            .iter()
+            .filter_map(|l| match l {
+                PackageLicense::Details(d) => d.redistributable.or(d.free),
+                PackageLicense::Name(_) => None,
+            })
+            .collect();
+        if redistributable_flags.len() == 0 {
+            return None;
+        }
+        Some(!redistributable_flags.contains(&false))
+    }
+
     pub fn get_maintainers(&self) -> Vec<PackageMaintainer> {
         match &self.maintainers {
             Some(h) => match h {
@@ -630,11 +715,133 @@ impl PackageMeta {
     }
 }
 
-pub fn get_package_for_derivation(derivation_name: &str, packages: &Packages) -> Option<Package> {
-    if let Some(package) = packages.get(derivation_name) {
-        return Some(package.clone());
+// Returns the name of the NixOS configuration an installable points to, like `.#nixosConfigurations.laptop`.
+pub fn get_nixos_configuration_name(installable: &str) -> Option<String> {
+    let (_, attribute_path) = installable.split_once('#')?;
+    let host_name = attribute_path.strip_prefix(NIXOS_CONFIGURATIONS_PREFIX)?;
+    let is_quoted = host_name.len() > 1 && host_name.starts_with('"') && host_name.ends_with('"');
+    if host_name.len() == 0 || (host_name.contains('.') && !is_quoted) {
+        return None;
+    }
+    Some(host_name.trim_matches('"').to_string())
+}
+
+// Points the NixOS configurations of a flake to the derivation of the system.
+pub fn get_installable(installable: &str) -> String {
+    if get_nixos_configuration_name(installable).is_none() {
+        return installable.to_string();
+    }
+    format!("{}.{}", installable, NIXOS_TOPLEVEL_ATTRIBUTE_PATH)
+}
+
+// How the metadata of a package was matched with its derivation.
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(PartialEq)]
+#[derive(Serialize)]
+#[derive(Deserialize)]
+pub enum PackageMatch {
+    // The derivation path of the package is the one of the derivation.
+    DerivationPath,
+    // An output path of the package is an output of the derivation.
+    OutputPath,
+    // Only the names are the same, which attaches the wrong metadata when attributes share
+    // a name or when a package was overridden.
+    Name,
+}
+
+impl PackageMatch {
+    pub fn to_string(&self) -> String {
+        match self {
+            PackageMatch::DerivationPath => "drvPath".to_string(),
+            PackageMatch::OutputPath => "outPath".to_string(),
+            PackageMatch::Name => "name".to_string(),
+        }
+    }
+}
+
+// Index of the packages by derivation path, output path and name, to find the metadata of the
+// derivations. When more than one attribute has the same key, the first attribute path is used.
+#[derive(Debug)]
+pub struct PackageIndex<'a> {
+    packages: &'a Packages,
+    derivation_paths: HashMap<&'a str, &'a str>,
+    output_paths: HashMap<&'a str, &'a str>,
+    names: HashMap<&'a str, BTreeSet<&'a str>>,
+}
+
+impl<'a> PackageIndex<'a> {
+    pub fn new(packages: &'a Packages) -> PackageIndex<'a> {
+        let mut package_index = PackageIndex {
+            packages,
+            derivation_paths: HashMap::default(),
+            output_paths: HashMap::default(),
+            names: HashMap::default(),
+        };
+        for (attribute_path, package) in packages.iter() {
+            if let Some(drv_path) = &package.drv_path {
+                insert_attribute_path(&mut package_index.derivation_paths, drv_path, attribute_path);
+            }
+            for output_path in package.outputs.values().flatten() {
+                insert_attribute_path(&mut package_index.output_paths, output_path, attribute_path);
+            }
+            package_index
+                .names
+                .entry(&package.name)
+                .or_default()
+                .insert(attribute_path);
+        }
+        package_index
+    }
+
+    // Finds the package of a derivation, matching by path first and by name as a fallback.
+    pub fn get_package(
+        &self,
+        derivation_path: &str,
+        derivation: &Derivation,
+    ) -> Option<(&'a Package, PackageMatch)> {
+        let (attribute_path, package_match) = self.get_attribute_path(derivation_path, derivation)?;
+        Some((&self.packages[attribute_path], package_match))
+    }
+
+    // Finds the attribute path of the package of a derivation.
+    pub fn get_attribute_path(
+        &self,
+        derivation_path: &str,
+        derivation: &Derivation,
+    ) -> Option<(&'a str, PackageMatch)> {
+        if let Some(attribute_path) = self.derivation_paths.get(derivation_path) {
+            return Some((attribute_path, PackageMatch::DerivationPath));
+        }
+        let mut output_paths = derivation.get_output_paths();
+        output_paths.sort();
+        for output_path in output_paths {
+            if let Some(attribute_path) = self.output_paths.get(output_path.as_str()) {
+                return Some((attribute_path, PackageMatch::OutputPath));
+            }
+        }
+        let name = derivation.env.get("name")?;
+        let attribute_paths = self.names.get(name.as_str())?;
+        let attribute_path = attribute_paths.first()?;
+        if attribute_paths.len() > 1 {
+            log::debug!(
+                "{} attributes are named {}, using the metadata of {}",
+                attribute_paths.len(),
+                name,
+                attribute_path
+            );
+        }
+        Some((attribute_path, PackageMatch::Name))
+    }
+}
+
+fn insert_attribute_path<'a>(index: &mut HashMap<&'a str, &'a str>, key: &'a str, attribute_path: &'a str) {
+    match index.get(key) {
+        Some(existing) if *existing <= attribute_path => {}
+        _ => {
+            index.insert(key, attribute_path);
+        }
     }
-    None
 }
 
 #[derive(Debug)]
@@ -726,6 +933,16 @@ pub struct LicenseDetails {
     pub spdx_id: Option<String>,
 }
 
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(PartialEq)]
+#[derive(Serialize)]
+#[derive(Deserialize)]
+pub enum DependencyType {
+    Runtime,
+    BuildTime,
+}
+
 #[derive(Debug)]
 #[derive(PartialEq)]
 #[derive(Serialize)]
@@ -735,6 +952,10 @@ pub struct PackageNode {
 
     pub package: Option<Package>,
 
+    // How the package was matched with the derivation, when it was found.
+    #[serde(default)]
+    pub package_match: Option<PackageMatch>,
+
     pub sources: Vec<Derivation>,
 
     pub patches: BTreeSet<String>,
@@ -742,9 +963,38 @@ pub struct PackageNode {
     pub build_inputs: BTreeSet<String>,
 
     pub children: BTreeSet<String>,
+
+    // The type of each edge in children and build_inputs, keyed by derivation path.
+    // This is first guessed from the build inputs, and then replaced by the references
+    // of the outputs when they are available in the Nix store.
+    #[serde(default)]
+    pub dependency_types: BTreeMap<String, DependencyType>,
 }
 
 impl PackageNode {
+    pub fn get_dependency_type(&self, derivation_path: &str) -> DependencyType {
+        if let Some(dependency_type) = self.dependency_types.get(derivation_path) {
+            return dependency_type.clone();
+        }
+        if self.children.contains(derivation_path) {
+            return DependencyType::Runtime;
+        }
+        DependencyType::BuildTime
+    }
+
+    // Returns the derivation paths of all the dependencies of the package, or only of
+    // the runtime dependencies.
+    pub fn get_dependencies(&self, runtime_only: bool) -> BTreeSet<String> {
+        let mut dependencies: BTreeSet<String> = BTreeSet::default();
+        for dependency in self.children.iter().chain(self.build_inputs.iter()) {
+            if runtime_only && self.get_dependency_type(dependency) != DependencyType::Runtime {
+                continue;
+            }
+            dependencies.insert(dependency.to_string());
+        }
+        dependencies
+    }
+
     pub fn get_reachable_nodes_count(
         &self,
         package_nodes: &BTreeMap<String, PackageNode>,
@@ -779,12 +1029,7 @@ impl PackageNode {
         dump_options: &DumpOptions,
     ) -> Vec<String> {
         let mut longest_path = vec![];
-        let mut children = self.children.clone();
-        if !dump_options.runtime_only {
-            for build_input in &self.build_inputs {
-                children.insert(build_input.to_string());
-            }
-        }
+        let children = self.get_dependencies(dump_options.runtime_only);
         for child_derivation_path in &children {
             let path = match visited_children.get(child_derivation_path) {
                 Some(p) => p.to_vec(),
@@ -870,7 +1115,7 @@ impl PackageNode {
         if name == Some("raw".to_string()) {
             log::trace!("{}", self.to_json().unwrap());
         }
-        package_url.host = name.unwrap_or("".to_string());
+        package_url.name = name.unwrap_or("".to_string());
 
         package_url.version = self.get_version();
         if package_url.version.is_none() {
@@ -883,7 +1128,7 @@ impl PackageNode {
         // FIXME this cannot use the nix scope, which does not actually exist.
         // See https://github.com/package-url/purl-spec/blob/master/PURL-TYPES.rst
         // for the accepted scopes.
-        package_url.scheme = "generic".to_string();
+        package_url.package_type = "generic".to_string();
 
         let urls = self.main_derivation.get_urls();
         let url = match urls.get(0) {
@@ -894,64 +1139,59 @@ impl PackageNode {
             }
         };
 
-        // TODO detect the scheme using the url.
-        if url.starts_with("https://crates.io") {
-            package_url.scheme = "cargo".to_string();
-        }
-        if url.starts_with("https://www.cpan.org/") {
-            package_url.scheme = "cpan".to_string();
-        }
-        if url.starts_with("https://rubygems.org") {
-            package_url.scheme = "gem".to_string();
-        }
-        if url.starts_with("https://hackage.haskell.org/") {
-            package_url.scheme = "hackage".to_string();
-        }
-        if url.starts_with("https://repo.maven.apache.org/maven2") {
-            package_url.scheme = "maven".to_string();
-        }
-        if url.starts_with("https://registry.npmjs.org") {
-            package_url.scheme = "npm".to_string();
-        }
-        if url.starts_with("https://www.nuget.org") {
-            package_url.scheme = "nuget".to_string();
-        }
-        if url.starts_with("https://bitbucket.org") {
-            package_url.scheme = "bitbucket".to_string();
-        }
-        if url.starts_with("https://hub.docker.com") {
-            package_url.scheme = "docker".to_string();
-        }
-        if url.starts_with("https://pypi.org") || url.starts_with("https://pypi.python.org") {
-            package_url.scheme = "pypi".to_string();
+        let revision = self.main_derivation.env.get("rev").map(|r| r.as_str());
+        if let Some(mut ecosystem_package_url) = PackageURL::from_download_url(url, revision) {
+            if let Some(checksum) = self.get_checksum() {
+                ecosystem_package_url
+                    .qualifiers
+                    .insert(crate::purl::CHECKSUM_QUALIFIER.to_string(), checksum);
+            }
+            return ecosystem_package_url;
         }
-        // if url.starts_with("https://github.com") {
-        //     package_url.scheme = "gem".to_string();
-        // }
-        // if url.starts_with("https://crates.io") {}
-        // https://crates.io/api/v1/crates/project-name/1.0.2/download
-        // if url.starts_with("https://bitbucket.org") {}
-        // if url.starts_with("https://registry.npmjs.org") {}
-        // if url.starts_with("https://pypi.python.org") {}
-        // if url.starts_with("https://github.com") {}
-        // TODO How can we detect go and swift packages? The url will just be another git URL
-        // TODO gitlab ??
-        // TODO openwrt ??
 
+        // The URL did not give us the coordinates of the package in its ecosystem, and the
+        // name of the derivation is not necessarily the name of the package in the registry,
+        // so we fall back to a generic purl pointing to the download URL.
+        // TODO How can we detect go and swift packages? The url will just be another git URL
         // According to the PURL doc, for the generic scope:
         // > There is no default repository. A download_url and checksum may be provided in qualifiers
         // > or as separate attributes outside of a purl for proper identification and location.
         // https://github.com/package-url/purl-spec/blob/346589846130317464b677bc4eab30bf5040183a/PURL-TYPES.rst#generic
         package_url
-            .query_params
-            .insert("download_url".to_string(), url.to_string());
-        // Format should be sha256:de4d501267da...
-        // package_url
-        //     .query_params
-        //     .insert("checksum".to_string(), url.to_string());
+            .qualifiers
+            .insert(crate::purl::DOWNLOAD_URL_QUALIFIER.to_string(), url.to_string());
+        if let Some(checksum) = self.get_checksum() {
+            package_url
+                .qualifiers
+                .insert(crate::purl::CHECKSUM_QUALIFIER.to_string(), checksum);
+        }
+        package_url.normalize();
         return package_url;
     }
 
+    // Returns the CPE 2.3 identifier of the package. Packages without a version cannot
+    // be matched against vulnerabilities, so no CPE is generated for them.
+    pub fn get_cpe(&self, options: &DumpOptions) -> Option<String> {
+        let name = match self.get_name() {
+            Some(n) => n,
+            None => return None,
+        };
+        let version = match self.get_version().or(self.main_derivation.get_version()) {
+            Some(v) => v,
+            None => return None,
+        };
+        Some(crate::cpe::get_cpe(&name, &version, &options.cpe_mappings))
+    }
+
+    // The checksum is the hash of the downloaded file, so we cannot use the hash of
+    // the unpacked source (recursive mode).
+    fn get_checksum(&self) -> Option<String> {
+        match self.main_derivation.get_output_hash() {
+            Some(hash) if hash.mode == crate::hash::HashMode::Flat => Some(hash.to_checksum()),
+            _ => None,
+        }
+    }
+
     pub fn to_json(&self) -> Result<String, String> {
         return serde_json::to_string_pretty(self).map_err(|e| e.to_string());
     }
@@ -985,7 +1225,7 @@ impl PackageNode {
             return lines;
         }
 
-        if depth >= display_options.max_depth.unwrap_or(std::usize::MAX) {
+        if depth >= display_options.max_depth.unwrap_or(usize::MAX) {
             return lines;
         }
 
@@ -1071,6 +1311,24 @@ pub struct PackageGraphStats {
     pub package_meta_count: usize,
 
     pub purl_scope_count: BTreeMap<String, usize>,
+
+    /// Number of nodes marked as insecure in nixpkgs.
+    pub insecure_nodes_count: usize,
+
+    /// Number of nodes which are unfree, or have a license which is not free.
+    pub unfree_nodes_count: usize,
+
+    /// Number of nodes with a license which does not allow redistributing them.
+    pub non_redistributable_nodes_count: usize,
+
+    /// Number of vulnerabilities affecting at least one node.
+    pub vulnerabilities_count: usize,
+
+    /// Number of nodes affected by at least one vulnerability.
+    pub vulnerable_nodes_count: usize,
+
+    /// Number of vulnerabilities for each severity.
+    pub vulnerabilities_severity_count: BTreeMap<String, usize>,
 }
 
 #[derive(Debug)]
@@ -1102,9 +1360,123 @@ impl PackageGraph {
             package_graph_stats.longest_path = longest_path;
             package_graph_stats.purl_scope_count = self.get_purl_scope_stats();
         }
+
+        package_graph_stats.insecure_nodes_count = self
+            .nodes
+            .values()
+            .filter(|n| n.package.as_ref().map(|p| p.meta.is_insecure()).unwrap_or(false))
+            .count();
+        package_graph_stats.unfree_nodes_count = self
+            .nodes
+            .values()
+            .filter(|n| n.package.as_ref().and_then(|p| p.meta.get_unfree()) == Some(true))
+            .count();
+        package_graph_stats.non_redistributable_nodes_count = self
+            .nodes
+            .values()
+            .filter(|n| n.package.as_ref().and_then(|p| p.meta.get_redistributable()) == Some(false))
+            .count();
+        let vulnerabilities = crate::vulnerabilities::get_all_vulnerabilities(self, options);
+        let mut vulnerable_nodes: BTreeSet<&String> = BTreeSet::default();
+        for vulnerability in &vulnerabilities {
+            vulnerable_nodes.extend(vulnerability.affected.keys());
+            *package_graph_stats
+                .vulnerabilities_severity_count
+                .entry(vulnerability.get_severity())
+                .or_default() += 1;
+        }
+        package_graph_stats.vulnerable_nodes_count = vulnerable_nodes.len();
+        package_graph_stats.vulnerabilities_count = vulnerabilities.len();
         package_graph_stats
     }
 
+    // Uses the references of the outputs found in the Nix store to mark every dependency
+    // as a runtime or a build-time dependency, like `nix-store -qR` would. Dependencies of
+    // packages whose outputs were not built locally keep the type guessed from the
+    // build inputs.
+    pub fn set_runtime_references(
+        &mut self,
+        store_database: &crate::store_db::StoreDatabase,
+    ) -> Result<(), String> {
+        let mut output_paths: HashMap<String, String> = HashMap::default();
+        for (derivation_path, package_node) in self.nodes.iter() {
+            for output_path in package_node.main_derivation.get_output_paths() {
+                output_paths.insert(output_path, derivation_path.to_string());
+            }
+        }
+
+        for (derivation_path, package_node) in self.nodes.iter_mut() {
+            let mut is_realised = false;
+            let mut runtime_dependencies: BTreeSet<String> = BTreeSet::default();
+            for output_path in package_node.main_derivation.get_output_paths() {
+                if !store_database.is_valid_path(&output_path)? {
+                    continue;
+                }
+                is_realised = true;
+                for reference in store_database.get_references(&output_path)? {
+                    if let Some(reference_derivation_path) = output_paths.get(&reference) {
+                        if reference_derivation_path != derivation_path {
+                            runtime_dependencies.insert(reference_derivation_path.to_string());
+                        }
+                    }
+                }
+            }
+            if !is_realised {
+                log::debug!("Outputs of {} are not in the Nix store", derivation_path);
+                continue;
+            }
+
+            for dependency in package_node.get_dependencies(false) {
+                package_node
+                    .dependency_types
+                    .insert(dependency, DependencyType::BuildTime);
+            }
+            // The outputs can reference packages which are not direct inputs of the derivation.
+            for dependency in runtime_dependencies {
+                if !package_node.build_inputs.contains(&dependency) {
+                    package_node.children.insert(dependency.to_string());
+                }
+                package_node
+                    .dependency_types
+                    .insert(dependency, DependencyType::Runtime);
+            }
+        }
+        Ok(())
+    }
+
+    // Removes all the nodes which are not in the runtime closure of the root nodes.
+    // The patches of the remaining nodes are kept, since they are part of their pedigree.
+    pub fn retain_runtime_closure(&mut self) {
+        let mut runtime_closure: HashSet<String> = HashSet::default();
+        let mut node_queue = self.root_nodes.clone();
+        while let Some(derivation_path) = node_queue.pop_first() {
+            if runtime_closure.contains(&derivation_path) {
+                continue;
+            }
+            let package_node = match self.nodes.get(&derivation_path) {
+                Some(n) => n,
+                None => continue,
+            };
+            for patch in &package_node.patches {
+                runtime_closure.insert(patch.to_string());
+            }
+            for dependency in package_node.get_dependencies(true) {
+                node_queue.insert(dependency);
+            }
+            runtime_closure.insert(derivation_path);
+        }
+
+        self.nodes
+            .retain(|derivation_path, _| runtime_closure.contains(derivation_path));
+        for package_node in self.nodes.values_mut() {
+            package_node.children.retain(|d| runtime_closure.contains(d));
+            package_node.build_inputs.retain(|d| runtime_closure.contains(d));
+            package_node
+                .dependency_types
+                .retain(|d, _| runtime_closure.contains(d));
+        }
+    }
+
     pub fn get_purl_scope_stats(&self) -> BTreeMap<String, usize> {
         let mut visited_children: HashSet<String> = HashSet::default();
 
@@ -1121,11 +1493,11 @@ impl PackageGraph {
             let current_node = self.nodes.get(&current_node_path).unwrap();
             let purl = current_node.get_purl();
 
-            if response.contains_key(&purl.scheme) {
-                let count = response.get_mut(&purl.scheme).unwrap();
+            if response.contains_key(&purl.package_type) {
+                let count = response.get_mut(&purl.package_type).unwrap();
                 *count += 1;
             } else {
-                response.insert(purl.scheme.clone(), 1);
+                response.insert(purl.package_type.clone(), 1);
             }
 
             // FIXME we should also go through the patches?
@@ -1231,26 +1603,26 @@ pub fn get_package_graph(
     packages: &crate::nix::Packages,
 ) -> PackageGraph {
     let mut response = PackageGraph::default();
+    let package_index = PackageIndex::new(packages);
 
     for (derivation_path, derivation) in derivations.iter() {
-        let derivation_name = match derivation.get_name() {
-            Some(n) => n,
-            None => {
-                log::warn!("Found derivation without a name at {}", derivation_path);
-                continue;
-            }
-        };
-        let package = match packages.get(&derivation_name) {
-            Some(p) => Some(p.clone()),
-            None => None,
+        if derivation.get_name().is_none() {
+            log::warn!("Found derivation without a name at {}", derivation_path);
+            continue;
+        }
+        let (package, package_match) = match package_index.get_package(derivation_path, derivation) {
+            Some((p, m)) => (Some(p.clone()), Some(m)),
+            None => (None, None),
         };
         let mut current_node = PackageNode {
             package,
+            package_match,
             main_derivation: derivation.clone(),
             children: BTreeSet::default(),
             sources: vec![],
             patches: BTreeSet::default(),
             build_inputs: BTreeSet::default(),
+            dependency_types: BTreeMap::default(),
         };
         let current_node_patches = derivation.get_patches();
 
@@ -1279,7 +1651,11 @@ pub fn get_package_graph(
                     "NOT_AN_ACTUAL_NAME".to_string()
                 }
             };
-            if child_derivation_name != "source" && packages.get(&child_derivation_name).is_some() {
+            if child_derivation_name != "source"
+                && package_index
+                    .get_package(&child_derivation_path, child_derivation)
+                    .is_some()
+            {
                 current_node.children.insert(child_derivation_path.to_string());
                 // FIXME should we really continue here? Are there derivations that define both a
                 // package meta and urls to fetch?
@@ -1311,21 +1687,45 @@ pub fn get_package_graph(
     response
 }
 
+// Returns the packages which are the metadata of one of the derivations, keyed by their
+// attribute path. Building the package graph from these gives the same graph as from all
+// the packages.
+pub fn get_matched_packages(
+    derivations: &crate::nix::Derivations,
+    packages: &crate::nix::Packages,
+) -> Packages {
+    let package_index = PackageIndex::new(packages);
+    let mut response = Packages::default();
+    for (derivation_path, derivation) in derivations.iter() {
+        if let Some((attribute_path, _)) = package_index.get_attribute_path(derivation_path, derivation) {
+            response.insert(attribute_path.to_string(), packages[attribute_path].clone());
+        }
+    }
+    response
+}
+
 pub fn get_package_graph_next(
     derivations: &crate::nix::Derivations,
-    _packages: &crate::nix::Packages,
+    packages: &crate::nix::Packages,
 ) -> PackageGraph {
     let mut response = PackageGraph::default();
+    let package_index = PackageIndex::new(packages);
 
     let mut all_child_derivations: HashSet<String> = HashSet::default();
     for (derivation_path, derivation) in derivations.iter() {
+        let (package, package_match) = match package_index.get_package(derivation_path, derivation) {
+            Some((p, m)) => (Some(p.clone()), Some(m)),
+            None => (None, None),
+        };
         let mut current_node = PackageNode {
-            package: None,
+            package,
+            package_match,
             main_derivation: derivation.clone(),
             children: BTreeSet::default(),
             sources: vec![],
             patches: BTreeSet::default(),
             build_inputs: BTreeSet::default(),
+            dependency_types: BTreeMap::default(),
         };
 
         let current_node_patches = derivation.get_patches();
@@ -1345,6 +1745,9 @@ pub fn get_package_graph_next(
 
                 if current_node_build_inputs.contains(child_derivation_out_path) {
                     current_node.build_inputs.insert(input_derivation_path.clone());
+                    current_node
+                        .dependency_types
+                        .insert(input_derivation_path.clone(), DependencyType::BuildTime);
                     all_child_derivations.insert(input_derivation_path.clone());
                     is_runtime_dep = false;
                     break;
@@ -1353,6 +1756,9 @@ pub fn get_package_graph_next(
 
             if is_runtime_dep {
                 current_node.children.insert(input_derivation_path.clone());
+                current_node
+                    .dependency_types
+                    .insert(input_derivation_path.clone(), DependencyType::Runtime);
                 all_child_derivations.insert(input_derivation_path.clone());
             }
         }
@@ -1370,10 +1776,438 @@ pub fn get_package_graph_next(
     response
 }
 
+// Small package graph used in the unit tests of the different output formats.
+// hello depends on glibc, is built with gettext and has a single patch applied.
+#[cfg(test)]
+pub(crate) fn get_test_package_graph() -> PackageGraph {
+    get_package_graph_next(&get_test_derivations(), &Packages::default())
+}
+
+// Attaches the metadata of a package, in the JSON format of nix-env, to a node of a test
+// package graph.
+#[cfg(test)]
+pub(crate) fn set_test_package(package_graph: &mut PackageGraph, derivation_path: &str, package: &str) {
+    package_graph.nodes.get_mut(derivation_path).unwrap().package =
+        Some(serde_json::from_str(package).unwrap());
+}
+
+// Metadata of the test package graph, as reported by nix-env. hello is matched with its
+// derivation by the path of the derivation, and glibc by the path of its output.
+#[cfg(test)]
+pub(crate) fn get_test_packages() -> Packages {
+    let packages: &str = r###"
+      {
+        "hello": {
+          "name": "hello-2.12.1",
+          "pname": "hello",
+          "version": "2.12.1",
+          "system": "x86_64-linux",
+          "outputName": "out",
+          "drvPath": "/nix/store/aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa-hello-2.12.1.drv",
+          "meta": {
+            "mainProgram": "hello",
+            "license": {
+              "spdxId": "GPL-3.0-or-later",
+              "shortName": "gpl3Plus",
+              "fullName": "GNU General Public License v3.0 or later",
+              "free": true,
+              "redistributable": true
+            }
+          }
+        },
+        "glibc": {
+          "name": "glibc-2.38",
+          "pname": "glibc",
+          "version": "2.38",
+          "system": "x86_64-linux",
+          "outputName": "out",
+          "outputs": { "out": "/nix/store/dddddddddddddddddddddddddddddddd-glibc-2.38" },
+          "meta": {
+            "insecure": true,
+            "knownVulnerabilities": ["CVE-2023-4911: buffer overflow in the dynamic loader"],
+            "license": [
+              { "spdxId": "LGPL-2.1-or-later", "shortName": "lgpl21Plus", "free": true },
+              { "shortName": "unfree", "fullName": "Unfree", "free": false, "redistributable": false }
+            ]
+          }
+        }
+      }
+    "###;
+    serde_json::from_str(packages).unwrap()
+}
+
+#[cfg(test)]
+pub(crate) fn get_test_derivations() -> Derivations {
+    let derivations: &str = r###"
+      {
+        "/nix/store/aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa-hello-2.12.1.drv": {
+          "args": [],
+          "builder": "/nix/store/0rwyq0j954a7143p0wzd4rhycny8i967-bash-5.2-p15/bin/bash",
+          "env": {
+            "name": "hello-2.12.1",
+            "pname": "hello",
+            "version": "2.12.1",
+            "nativeBuildInputs": "/nix/store/bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb-gettext-0.21.1",
+            "patches": "/nix/store/cccccccccccccccccccccccccccccccc-fix.patch"
+          },
+          "inputDrvs": {
+            "/nix/store/bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb-gettext-0.21.1.drv": ["out"],
+            "/nix/store/cccccccccccccccccccccccccccccccc-fix.patch.drv": ["out"],
+            "/nix/store/dddddddddddddddddddddddddddddddd-glibc-2.38.drv": ["out"]
+          },
+          "inputSrcs": [],
+          "outputs": {
+            "out": { "path": "/nix/store/eeeeeeeeeeeeeeeeeeeeeeeeeeeeeeee-hello-2.12.1" }
+          },
+          "system": "x86_64-linux"
+        },
+        "/nix/store/bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb-gettext-0.21.1.drv": {
+          "args": [],
+          "builder": "/nix/store/0rwyq0j954a7143p0wzd4rhycny8i967-bash-5.2-p15/bin/bash",
+          "env": { "name": "gettext-0.21.1", "pname": "gettext", "version": "0.21.1" },
+          "inputDrvs": {},
+          "inputSrcs": [],
+          "outputs": {
+            "out": { "path": "/nix/store/bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb-gettext-0.21.1" }
+          },
+          "system": "x86_64-linux"
+        },
+        "/nix/store/cccccccccccccccccccccccccccccccc-fix.patch.drv": {
+          "args": [],
+          "builder": "builtin:fetchurl",
+          "env": {
+            "name": "fix.patch",
+            "url": "https://example.org/fix.patch",
+            "outputHash": "sha256-jZkUKv2SV28wsM18tCqNxoCZmLxdYH2Idh9RLibH2yA=",
+            "outputHashAlgo": "",
+            "outputHashMode": "flat"
+          },
+          "inputDrvs": {},
+          "inputSrcs": [],
+          "outputs": {
+            "out": { "path": "/nix/store/cccccccccccccccccccccccccccccccc-fix.patch" }
+          },
+          "system": "x86_64-linux"
+        },
+        "/nix/store/dddddddddddddddddddddddddddddddd-glibc-2.38.drv": {
+          "args": [],
+          "builder": "/nix/store/0rwyq0j954a7143p0wzd4rhycny8i967-bash-5.2-p15/bin/bash",
+          "env": { "name": "glibc-2.38", "pname": "glibc", "version": "2.38" },
+          "inputDrvs": {},
+          "inputSrcs": [],
+          "outputs": {
+            "out": { "path": "/nix/store/dddddddddddddddddddddddddddddddd-glibc-2.38" }
+          },
+          "system": "x86_64-linux"
+        }
+      }
+    "###;
+    serde_json::from_str(derivations).unwrap()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    pub fn test_runtime_references() {
+        let hello_path = "/nix/store/aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa-hello-2.12.1.drv";
+        let gettext_path = "/nix/store/bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb-gettext-0.21.1.drv";
+        let glibc_path = "/nix/store/dddddddddddddddddddddddddddddddd-glibc-2.38.drv";
+
+        let mut package_graph = get_test_package_graph();
+        let hello = package_graph.nodes.get(hello_path).unwrap();
+        assert_eq!(hello.get_dependency_type(glibc_path), DependencyType::Runtime);
+        assert_eq!(hello.get_dependency_type(gettext_path), DependencyType::BuildTime);
+
+        // In the test store, the hello output references both glibc and gettext.
+        let temp_dir = tempfile::tempdir().unwrap();
+        let store_root = temp_dir.path().join("runtime-references");
+        crate::store_db::create_test_store(&store_root);
+        let store_database = crate::store_db::StoreDatabase::open(store_root.to_str().unwrap()).unwrap();
+        package_graph.set_runtime_references(&store_database).unwrap();
+        drop(store_database);
+
+        let hello = package_graph.nodes.get(hello_path).unwrap();
+        assert_eq!(hello.get_dependency_type(glibc_path), DependencyType::Runtime);
+        assert_eq!(hello.get_dependency_type(gettext_path), DependencyType::Runtime);
+        assert_eq!(hello.get_dependencies(true).len(), 2);
+
+        package_graph.retain_runtime_closure();
+        assert_eq!(package_graph.nodes.len(), 4);
+    }
+
+    #[test]
+    pub fn test_purl_checksum() {
+        let package_graph = get_test_package_graph();
+        let patch = package_graph
+            .nodes
+            .get("/nix/store/cccccccccccccccccccccccccccccccc-fix.patch.drv")
+            .unwrap();
+        let purl = patch.get_purl();
+        assert_eq!(
+            purl.qualifiers.get("checksum").unwrap(),
+            "sha256:8d99142afd92576f30b0cd7cb42a8dc6809998bc5d607d88761f512e26c7db20"
+        );
+        let hello = package_graph
+            .nodes
+            .get("/nix/store/aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa-hello-2.12.1.drv")
+            .unwrap();
+        assert!(hello.main_derivation.get_output_hash().is_none());
+        assert_eq!(hello.get_purl().to_string(), "pkg:generic/hello@2.12.1");
+    }
+
+    #[test]
+    pub fn test_purl_round_trip() {
+        let patch_path = "/nix/store/cccccccccccccccccccccccccccccccc-fix.patch.drv";
+        let get_purl = |url: &str| -> PackageURL {
+            let mut derivations = get_test_derivations();
+            let patch = derivations.get_mut(patch_path).unwrap();
+            patch.env.insert("url".to_string(), url.to_string());
+            let package_graph = get_package_graph_next(&derivations, &Packages::default());
+            package_graph.nodes.get(patch_path).unwrap().get_purl()
+        };
+
+        let urls = [
+            "https://crates.io/api/v1/crates/serde/1.0.193/download",
+            "https://crates.io/crates/serde",
+            "https://repo.maven.apache.org/maven2/org/apache/commons/commons-lang3/3.14.0/commons-lang3-3.14.0.jar",
+            "https://repo.maven.apache.org/maven2/commons-lang3.jar",
+            "https://registry.npmjs.org/@babel/core/-/core-7.23.6.tgz",
+            "https://registry.npmjs.org/left-pad",
+            "https://files.pythonhosted.org/packages/source/r/requests/requests-2.31.0.tar.gz",
+            "https://pypi.org/simple/requests",
+            "https://github.com/NixOS/nix/archive/2.19.2.tar.gz",
+            "https://hub.docker.com/_/nginx",
+            "https://ftp.gnu.org/gnu/hello/hello-2.12.1.tar.gz",
+        ];
+        for url in urls {
+            let purl = get_purl(url);
+            assert_eq!(purl.validate(), Ok(()), "{}", url);
+            assert_eq!(purl.to_string().parse::<PackageURL>(), Ok(purl), "{}", url);
+        }
+
+        // Without the coordinates of the package in the registry, the name of the derivation
+        // is not used as the name of a package of the registry.
+        for url in [
+            "https://crates.io/crates/serde",
+            "https://repo.maven.apache.org/maven2/commons-lang3.jar",
+            "https://registry.npmjs.org/left-pad",
+            "https://pypi.org/simple/requests",
+        ] {
+            let purl = get_purl(url);
+            assert_eq!(purl.package_type, "generic", "{}", url);
+            assert_eq!(purl.qualifiers.get("download_url").unwrap(), url);
+        }
+    }
+
+    #[test]
+    pub fn test_vulnerabilities_stats() {
+        let package_graph = get_test_package_graph();
+        let mut vulnerability = crate::vulnerabilities::Vulnerability::new("CVE-2023-4911");
+        vulnerability.affected.insert(
+            "/nix/store/dddddddddddddddddddddddddddddddd-glibc-2.38.drv".to_string(),
+            Some("2.38".to_string()),
+        );
+        let mut options = DumpOptions::default();
+        options.vulnerabilities = vec![vulnerability];
+        let stats = package_graph.get_stats(&options);
+        assert_eq!(stats.vulnerabilities_count, 1);
+        assert_eq!(stats.vulnerable_nodes_count, 1);
+        assert_eq!(stats.vulnerabilities_severity_count.get("unknown"), Some(&1));
+    }
+
+    #[test]
+    pub fn test_license_flags_stats() {
+        let mut package_graph = get_test_package_graph();
+        let packages = [
+            (
+                "/nix/store/aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa-hello-2.12.1.drv",
+                r###"{ "name": "hello-2.12.1", "pname": "hello", "version": "2.12.1", "system": "x86_64-linux",
+                       "outputName": "out", "meta": { "unfree": false, "license": { "spdxId": "GPL-3.0-or-later", "free": true } } }"###,
+            ),
+            (
+                "/nix/store/dddddddddddddddddddddddddddddddd-glibc-2.38.drv",
+                r###"{ "name": "glibc-2.38", "pname": "glibc", "version": "2.38", "system": "x86_64-linux",
+                       "outputName": "out", "meta": { "license": { "shortName": "unfree", "free": false } } }"###,
+            ),
+        ];
+        for (derivation_path, package) in packages {
+            set_test_package(&mut package_graph, derivation_path, package);
+        }
+        let stats = package_graph.get_stats(&DumpOptions::default());
+        assert_eq!(stats.unfree_nodes_count, 1);
+        assert_eq!(stats.non_redistributable_nodes_count, 1);
+    }
+
+    #[test]
+    pub fn test_get_installable() {
+        assert_eq!(get_installable("github:org/repo#hello"), "github:org/repo#hello");
+        assert_eq!(get_installable("nixpkgs#hello"), "nixpkgs#hello");
+        assert_eq!(get_installable("./default.nix"), "./default.nix");
+        assert_eq!(
+            get_installable(".#nixosConfigurations.server"),
+            ".#nixosConfigurations.server.config.system.build.toplevel"
+        );
+        assert_eq!(
+            get_installable(".#nixosConfigurations.\"my.host\""),
+            ".#nixosConfigurations.\"my.host\".config.system.build.toplevel"
+        );
+        assert_eq!(
+            get_installable(".#nixosConfigurations.server.config.system.build.vm"),
+            ".#nixosConfigurations.server.config.system.build.vm"
+        );
+        assert_eq!(
+            get_nixos_configuration_name(".#nixosConfigurations.\"my.host\""),
+            Some("my.host".to_string())
+        );
+        assert_eq!(get_nixos_configuration_name("nixpkgs#hello"), None);
+    }
+
+    #[test]
+    pub fn test_get_matched_packages() {
+        // The attribute paths are not the names of the packages.
+        let mut packages = Packages::default();
+        for (attribute_path, package) in get_test_packages() {
+            packages.insert(format!("gnu.{}", attribute_path), package);
+        }
+        let mut curl = packages["gnu.hello"].clone();
+        curl.name = "curl-8.4.0".to_string();
+        curl.drv_path = Some("/nix/store/ffffffffffffffffffffffffffffffff-curl-8.4.0.drv".to_string());
+        packages.insert("curl".to_string(), curl);
+
+        let derivations = get_test_derivations();
+        let matched_packages = get_matched_packages(&derivations, &packages);
+        let mut attribute_paths: Vec<&String> = matched_packages.keys().collect();
+        attribute_paths.sort();
+        assert_eq!(attribute_paths, vec!["gnu.glibc", "gnu.hello"]);
+        assert_eq!(
+            get_package_graph_next(&derivations, &matched_packages),
+            get_package_graph_next(&derivations, &packages)
+        );
+    }
+
+    #[test]
+    pub fn test_package_index() {
+        // The override shares the name of hello and comes first, so it would be used if
+        // the packages were only matched by name.
+        let packages: Packages = serde_json::from_str(
+            r###"{
+              "hello": {
+                "name": "hello-2.12.1", "pname": "hello", "version": "2.12.1", "system": "x86_64-linux",
+                "outputName": "out", "drvPath": "/nix/store/aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa-hello-2.12.1.drv",
+                "outputs": { "out": "/nix/store/eeeeeeeeeeeeeeeeeeeeeeeeeeeeeeee-hello-2.12.1" },
+                "meta": { "description": "Program that produces a familiar, friendly greeting" }
+              },
+              "aHelloOverride": {
+                "name": "hello-2.12.1", "pname": "hello", "version": "2.12.1", "system": "x86_64-linux",
+                "outputName": "out", "drvPath": "/nix/store/ffffffffffffffffffffffffffffffff-hello-2.12.1.drv",
+                "outputs": { "out": "/nix/store/gggggggggggggggggggggggggggggggg-hello-2.12.1" },
+                "meta": { "description": "Overridden hello" }
+              },
+              "glibc": {
+                "name": "glibc-2.38", "pname": "glibc", "version": "2.38", "system": "x86_64-linux",
+                "outputName": "out", "outputs": { "out": "/nix/store/dddddddddddddddddddddddddddddddd-glibc-2.38" },
+                "meta": { "description": "The GNU C Library" }
+              },
+              "gettext": {
+                "name": "gettext-0.21.1", "pname": "gettext", "version": "0.21.1", "system": "x86_64-linux",
+                "outputName": "out", "outputs": { "out": null },
+                "meta": { "description": "Well integrated set of translation tools" }
+              }
+            }"###,
+        )
+        .unwrap();
+        let package_graph = get_package_graph_next(&get_test_derivations(), &packages);
+        let get_match = |derivation_path: &str| {
+            let package_node = package_graph.nodes.get(derivation_path).unwrap();
+            (
+                package_node
+                    .package
+                    .as_ref()
+                    .unwrap()
+                    .meta
+                    .description
+                    .clone()
+                    .unwrap(),
+                package_node.package_match.clone().unwrap(),
+            )
+        };
+        assert_eq!(
+            get_match("/nix/store/aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa-hello-2.12.1.drv"),
+            (
+                "Program that produces a familiar, friendly greeting".to_string(),
+                PackageMatch::DerivationPath
+            )
+        );
+        assert_eq!(
+            get_match("/nix/store/dddddddddddddddddddddddddddddddd-glibc-2.38.drv"),
+            ("The GNU C Library".to_string(), PackageMatch::OutputPath)
+        );
+        assert_eq!(
+            get_match("/nix/store/bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb-gettext-0.21.1.drv"),
+            (
+                "Well integrated set of translation tools".to_string(),
+                PackageMatch::Name
+            )
+        );
+        let patch = package_graph
+            .nodes
+            .get("/nix/store/cccccccccccccccccccccccccccccccc-fix.patch.drv")
+            .unwrap();
+        assert!(patch.package.is_none());
+        assert!(patch.package_match.is_none());
+    }
+
+    #[test]
+    pub fn test_retain_runtime_closure() {
+        let mut package_graph = get_test_package_graph();
+        package_graph.retain_runtime_closure();
+        let derivation_paths: Vec<&String> = package_graph.nodes.keys().collect();
+        assert_eq!(
+            derivation_paths,
+            vec![
+                "/nix/store/aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa-hello-2.12.1.drv",
+                "/nix/store/cccccccccccccccccccccccccccccccc-fix.patch.drv",
+                "/nix/store/dddddddddddddddddddddddddddddddd-glibc-2.38.drv",
+            ]
+        );
+        let hello = package_graph
+            .nodes
+            .get("/nix/store/aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa-hello-2.12.1.drv")
+            .unwrap();
+        assert_eq!(hello.build_inputs.len(), 0);
+    }
+
+    #[test]
+    pub fn test_read_derivations() {
+        let derivations: &str = r###"
+          {
+            "/nix/store/bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb-gettext-0.21.1.drv": {
+              "args": [],
+              "builder": "/nix/store/0rwyq0j954a7143p0wzd4rhycny8i967-bash-5.2-p15/bin/bash",
+              "env": { "name": "gettext-0.21.1", "pname": "gettext", "version": "0.21.1" },
+              "inputDrvs": {},
+              "inputSrcs": [],
+              "outputs": {
+                "out": { "path": "/nix/store/bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb-gettext-0.21.1" }
+              },
+              "system": "x86_64-linux"
+            }
+          }
+        "###;
+        let temp_dir = tempfile::tempdir().unwrap();
+        let derivations_path = temp_dir.path().join("read-derivations.json");
+        fs::write(&derivations_path, derivations).unwrap();
+        let derivations = Derivation::read_derivations(derivations_path.to_str().unwrap()).unwrap();
+
+        assert_eq!(derivations.len(), 1);
+        let derivation = derivations
+            .get("/nix/store/bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb-gettext-0.21.1.drv")
+            .unwrap();
+        assert_eq!(derivation.get_name(), Some("gettext".to_string()));
+    }
+
     #[test]
     pub fn parse_package_metadata() {
         let package_metadata: &str = r###"
@@ -0,0 +1,235 @@
+// Information about the NixOS system and the revision of nixpkgs which produced the closure.
+use std::path::Path;
+
+// Written by NixOS in the toplevel derivation of the system, like 24.05.20240315.1536926.
+pub const NIXOS_VERSION_FILE_NAME: &str = "nixos-version";
+
+// The channel of the root user, which is used by nixos-rebuild when the system is not built
+// from a flake. Its revision files contain the full revision of nixpkgs.
+pub const NIXOS_CHANNEL_PATH: &str = "/nix/var/nix/profiles/per-user/root/channels/nixos";
+pub const GIT_REVISION_FILE_NAME: &str = ".git-revision";
+
+// The name of the nixpkgs input of the flakes, by convention.
+pub const NIXPKGS_INPUT_NAME: &str = "nixpkgs";
+
+// Used as the name of the system when the host name is not known.
+pub const DEFAULT_SYSTEM_NAME: &str = "nixos";
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Default)]
+#[derive(PartialEq)]
+pub struct SystemMetadata {
+    // The name of the NixOS configuration the system was built from.
+    pub name: Option<String>,
+    pub nixos_version: Option<String>,
+    // The commit of nixpkgs, which can be abbreviated when only the NixOS version is known.
+    pub nixpkgs_revision: Option<String>,
+    // The branch or channel nixpkgs was taken from, like nixos-unstable.
+    pub nixpkgs_ref: Option<String>,
+    pub nixpkgs_last_modified: Option<u64>,
+}
+
+impl SystemMetadata {
+    // Reads the version of a NixOS system, and the revision of nixpkgs from the NixOS channel
+    // when it is the one the system was built from.
+    pub fn read_system(system_path: &str, channel_path: &str) -> SystemMetadata {
+        let mut system_metadata = SystemMetadata::default();
+        let nixos_version = match read_first_line(&Path::new(system_path).join(NIXOS_VERSION_FILE_NAME)) {
+            Some(v) => v,
+            None => {
+                log::debug!("Could not find the NixOS version of {}", system_path);
+                return system_metadata;
+            }
+        };
+        system_metadata.name = Some(DEFAULT_SYSTEM_NAME.to_string());
+        system_metadata.nixpkgs_revision = get_version_revision(&nixos_version);
+        system_metadata.nixos_version = Some(nixos_version);
+
+        let channel_revision = read_first_line(&Path::new(channel_path).join(GIT_REVISION_FILE_NAME));
+        if let (Some(short_revision), Some(channel_revision)) =
+            (&system_metadata.nixpkgs_revision, channel_revision)
+        {
+            // The channel could have been updated since the system was built.
+            if channel_revision.starts_with(short_revision.as_str()) {
+                system_metadata.nixpkgs_revision = Some(channel_revision);
+            }
+        }
+        system_metadata
+    }
+
+    // Reads the revision of the nixpkgs input of a flake.
+    pub fn from_flake_lock(flake_lock: &crate::flake_lock::FlakeLock) -> SystemMetadata {
+        let mut system_metadata = SystemMetadata::default();
+        let root_node = match flake_lock.nodes.get(&flake_lock.root) {
+            Some(n) => n,
+            None => return system_metadata,
+        };
+        let node_name = match root_node.inputs.get(NIXPKGS_INPUT_NAME) {
+            Some(r) => match flake_lock.resolve(r) {
+                Some(n) => n,
+                None => return system_metadata,
+            },
+            None => return system_metadata,
+        };
+        let node = match flake_lock.nodes.get(&node_name) {
+            Some(n) => n,
+            None => return system_metadata,
+        };
+        if let Some(locked) = &node.locked {
+            system_metadata.nixpkgs_revision = locked.rev.clone();
+            system_metadata.nixpkgs_last_modified = locked.last_modified;
+            system_metadata.nixpkgs_ref = locked.git_ref.clone();
+        }
+        // The branch is usually only in the original reference, the locked one has the commit.
+        if let Some(original) = &node.original {
+            if original.git_ref.is_some() {
+                system_metadata.nixpkgs_ref = original.git_ref.clone();
+            }
+        }
+        system_metadata
+    }
+
+    // Fills the fields which are not set with the ones of another source.
+    pub fn merge(&mut self, other: SystemMetadata) {
+        if self.name.is_none() {
+            self.name = other.name;
+        }
+        if self.nixos_version.is_none() {
+            self.nixos_version = other.nixos_version;
+        }
+        if self.nixpkgs_revision.is_none() {
+            self.nixpkgs_revision = other.nixpkgs_revision;
+        }
+        if self.nixpkgs_ref.is_none() {
+            self.nixpkgs_ref = other.nixpkgs_ref;
+        }
+        if self.nixpkgs_last_modified.is_none() {
+            self.nixpkgs_last_modified = other.nixpkgs_last_modified;
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == SystemMetadata::default()
+    }
+
+    // Only the builds of a NixOS system are described as an operating system.
+    pub fn is_nixos_system(&self) -> bool {
+        self.name.is_some() || self.nixos_version.is_some()
+    }
+}
+
+fn read_first_line(path: &Path) -> Option<String> {
+    let content = std::fs::read_to_string(path).ok()?;
+    let line = content.lines().next()?.trim();
+    if line.len() == 0 {
+        return None;
+    }
+    Some(line.to_string())
+}
+
+// The last part of the NixOS versions is the abbreviated commit of nixpkgs.
+pub fn get_version_revision(nixos_version: &str) -> Option<String> {
+    let (_, revision) = nixos_version.rsplit_once('.')?;
+    if revision.len() < 7 || !revision.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    Some(revision.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_get_version_revision() {
+        assert_eq!(
+            get_version_revision("24.05.20240315.1536926"),
+            Some("1536926".to_string())
+        );
+        assert_eq!(
+            get_version_revision("23.11.5542.0c13d20e36ce"),
+            Some("0c13d20e36ce".to_string())
+        );
+        assert_eq!(get_version_revision("24.05pre-git"), None);
+        assert_eq!(get_version_revision("24.05"), None);
+    }
+
+    #[test]
+    pub fn test_read_system() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let system_path = temp_dir.path().join("nixos-system");
+        let channel_path = temp_dir.path().join("nixos-channel");
+        std::fs::create_dir_all(&system_path).unwrap();
+        std::fs::create_dir_all(&channel_path).unwrap();
+        std::fs::write(
+            system_path.join(NIXOS_VERSION_FILE_NAME),
+            "24.05.20240315.1536926",
+        )
+        .unwrap();
+        std::fs::write(
+            channel_path.join(GIT_REVISION_FILE_NAME),
+            "1536926ef5621b09bba54035ae2bb6d806d72ac8\n",
+        )
+        .unwrap();
+
+        let system_metadata =
+            SystemMetadata::read_system(system_path.to_str().unwrap(), channel_path.to_str().unwrap());
+        assert_eq!(system_metadata.name, Some("nixos".to_string()));
+        assert_eq!(
+            system_metadata.nixos_version,
+            Some("24.05.20240315.1536926".to_string())
+        );
+        assert_eq!(
+            system_metadata.nixpkgs_revision,
+            Some("1536926ef5621b09bba54035ae2bb6d806d72ac8".to_string())
+        );
+
+        // The channel was updated after the system was built.
+        std::fs::write(
+            channel_path.join(GIT_REVISION_FILE_NAME),
+            "20f77aa09916374aa3141cbc605c955626762c9a",
+        )
+        .unwrap();
+        let system_metadata =
+            SystemMetadata::read_system(system_path.to_str().unwrap(), channel_path.to_str().unwrap());
+        assert_eq!(system_metadata.nixpkgs_revision, Some("1536926".to_string()));
+
+        std::fs::remove_dir_all(&system_path).unwrap();
+        std::fs::remove_dir_all(&channel_path).unwrap();
+        let system_metadata =
+            SystemMetadata::read_system(system_path.to_str().unwrap(), channel_path.to_str().unwrap());
+        assert!(system_metadata.is_empty());
+    }
+
+    #[test]
+    pub fn test_from_flake_lock() {
+        let flake_lock = crate::flake_lock::get_test_flake_lock();
+        let system_metadata = SystemMetadata::from_flake_lock(&flake_lock);
+        assert_eq!(
+            system_metadata.nixpkgs_revision,
+            Some("20f77aa09916374aa3141cbc605c955626762c9a".to_string())
+        );
+        assert_eq!(system_metadata.nixpkgs_ref, Some("nixos-unstable".to_string()));
+        assert_eq!(system_metadata.nixpkgs_last_modified, Some(1711001935));
+        assert!(!system_metadata.is_nixos_system());
+    }
+
+    #[test]
+    pub fn test_merge() {
+        let mut system_metadata = SystemMetadata {
+            name: Some("nixos".to_string()),
+            nixos_version: Some("24.05.20240315.1536926".to_string()),
+            nixpkgs_revision: Some("1536926".to_string()),
+            ..Default::default()
+        };
+        system_metadata.merge(SystemMetadata {
+            name: Some("laptop".to_string()),
+            nixpkgs_ref: Some("nixos-unstable".to_string()),
+            ..Default::default()
+        });
+        assert_eq!(system_metadata.name, Some("nixos".to_string()));
+        assert_eq!(system_metadata.nixpkgs_revision, Some("1536926".to_string()));
+        assert_eq!(system_metadata.nixpkgs_ref, Some("nixos-unstable".to_string()));
+    }
+}
@@ -0,0 +1,562 @@
+// Package URLs, as described in https://github.com/package-url/purl-spec
+// A purl has the following form, where only the type and the name are required:
+//   pkg:type/namespace/name@version?qualifiers#subpath
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+pub const PURL_SCHEME: &str = "pkg";
+
+pub const DOWNLOAD_URL_QUALIFIER: &str = "download_url";
+pub const CHECKSUM_QUALIFIER: &str = "checksum";
+
+lazy_static! {
+    static ref CARGO_URL_REGEX: Regex =
+        Regex::new(r"^https?://(?:static\.)?crates\.io/(?:api/v1/)?crates/([^/]+)/([^/]+)/download$").unwrap();
+}
+
+lazy_static! {
+    static ref NPM_URL_REGEX: Regex =
+        Regex::new(r"^https?://registry\.(?:npmjs\.org|yarnpkg\.com)/(?:(@[^/]+)/)?([^/]+)/-/([^/]+)\.tgz$")
+            .unwrap();
+}
+
+lazy_static! {
+    static ref MAVEN_URL_REGEX: Regex =
+        Regex::new(r"^https?://[^/]+/(?:[^/]+/)*maven2/(.+)/([^/]+)/([^/]+)/([^/]+)$").unwrap();
+}
+
+lazy_static! {
+    static ref PYPI_URL_REGEX: Regex = Regex::new(
+        r"^https?://(?:pypi\.io|pypi\.org|pypi\.python\.org|files\.pythonhosted\.org)/packages/source/./([^/]+)/([^/]+)$"
+    )
+    .unwrap();
+}
+
+lazy_static! {
+    static ref GITHUB_URL_REGEX: Regex = Regex::new(
+        r"^https?://github\.com/([^/]+)/([^/]+?)(?:\.git)?(?:/archive/(?:refs/tags/)?(.+?)\.(?:tar\.gz|tar\.bz2|tar\.xz|zip)|/releases/download/([^/]+)/[^/]+)?/?$"
+    )
+    .unwrap();
+}
+
+// Extensions of the source archives published on PyPI.
+const PYPI_ARCHIVE_EXTENSIONS: [&str; 4] = [".tar.gz", ".tar.bz2", ".tar.xz", ".zip"];
+
+#[derive(Debug)]
+#[derive(Default)]
+#[derive(Clone)]
+#[derive(PartialEq)]
+pub struct PackageURL {
+    // The package type, for example cargo, npm or generic.
+    pub package_type: String,
+    pub namespace: Option<String>,
+    pub name: String,
+    pub version: Option<String>,
+    pub qualifiers: BTreeMap<String, String>,
+    pub subpath: Option<String>,
+}
+
+impl PackageURL {
+    // Returns the canonical form of the purl.
+    pub fn to_string(&self) -> String {
+        let mut response = format!("{}:{}/", PURL_SCHEME, self.package_type.to_lowercase());
+
+        if let Some(namespace) = &self.namespace {
+            let namespace = encode_segments(namespace);
+            if namespace.len() != 0 {
+                response += &namespace;
+                response += "/";
+            }
+        }
+
+        response += &percent_encode(&self.name);
+
+        if let Some(version) = &self.version {
+            response += "@";
+            response += &percent_encode(version);
+        }
+
+        // The qualifiers are stored in a BTreeMap, so they are already sorted by key.
+        let qualifiers: Vec<String> = self
+            .qualifiers
+            .iter()
+            .filter(|(_, value)| value.len() != 0)
+            .map(|(key, value)| format!("{}={}", key.to_lowercase(), percent_encode(value)))
+            .collect();
+        if qualifiers.len() != 0 {
+            response += "?";
+            response += &qualifiers.join("&");
+        }
+
+        if let Some(subpath) = &self.subpath {
+            let subpath = encode_segments(subpath);
+            if subpath.len() != 0 {
+                response += "#";
+                response += &subpath;
+            }
+        }
+
+        response
+    }
+}
+
+impl PackageURL {
+    // Builds the purl of a package from the URL it was downloaded from, for the ecosystems
+    // whose registries have a well-known URL layout. The revision is used as the version
+    // of git sources when it is not part of the URL.
+    pub fn from_download_url(url: &str, revision: Option<&str>) -> Option<PackageURL> {
+        let mut package_url = PackageURL::default();
+
+        if let Some(captured_groups) = CARGO_URL_REGEX.captures(url) {
+            package_url.package_type = "cargo".to_string();
+            package_url.name = captured_groups[1].to_string();
+            package_url.version = Some(captured_groups[2].to_string());
+        } else if let Some(captured_groups) = NPM_URL_REGEX.captures(url) {
+            // The tarball is named after the package name without the scope.
+            let name = captured_groups[2].to_string();
+            let version = match captured_groups[3].strip_prefix(&format!("{}-", name)) {
+                Some(v) => v.to_string(),
+                None => return None,
+            };
+            package_url.package_type = "npm".to_string();
+            package_url.namespace = captured_groups.get(1).map(|n| n.as_str().to_string());
+            package_url.name = name;
+            package_url.version = Some(version);
+        } else if let Some(captured_groups) = MAVEN_URL_REGEX.captures(url) {
+            // The layout of maven repositories is group/artifact/version/artifact-version.ext
+            let artifact = captured_groups[2].to_string();
+            let version = captured_groups[3].to_string();
+            if !captured_groups[4].starts_with(&format!("{}-{}", artifact, version)) {
+                return None;
+            }
+            package_url.package_type = "maven".to_string();
+            package_url.namespace = Some(captured_groups[1].replace('/', "."));
+            package_url.name = artifact;
+            package_url.version = Some(version);
+        } else if let Some(captured_groups) = PYPI_URL_REGEX.captures(url) {
+            let file_name = &captured_groups[2];
+            let file_stem = match PYPI_ARCHIVE_EXTENSIONS
+                .iter()
+                .find_map(|extension| file_name.strip_suffix(extension))
+            {
+                Some(s) => s,
+                None => return None,
+            };
+            let version = match file_stem.rsplit_once('-') {
+                Some((_, v)) => v.to_string(),
+                None => return None,
+            };
+            package_url.package_type = "pypi".to_string();
+            package_url.name = captured_groups[1].to_string();
+            package_url.version = Some(version);
+        } else if let Some(captured_groups) = GITHUB_URL_REGEX.captures(url) {
+            package_url.package_type = "github".to_string();
+            package_url.namespace = Some(captured_groups[1].to_string());
+            package_url.name = captured_groups[2].to_string();
+            package_url.version = match captured_groups.get(3).or(captured_groups.get(4)) {
+                Some(v) => Some(v.as_str().to_string()),
+                None => revision.map(|r| r.to_string()),
+            };
+        } else {
+            return None;
+        }
+
+        package_url.normalize();
+        Some(package_url)
+    }
+
+    // Applies the normalisation rules of the package type, see
+    // https://github.com/package-url/purl-spec/blob/master/PURL-TYPES.rst
+    pub fn normalize(&mut self) {
+        self.package_type = self.package_type.to_lowercase();
+        match self.package_type.as_str() {
+            "bitbucket" | "github" => {
+                self.namespace = self.namespace.as_ref().map(|n| n.to_lowercase());
+                self.name = self.name.to_lowercase();
+            }
+            "pypi" => {
+                self.name = self.name.to_lowercase().replace('_', "-");
+            }
+            _ => {}
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.package_type.len() == 0 {
+            return Err("The type of a purl cannot be empty".to_string());
+        }
+        if self.package_type.starts_with(|c: char| c.is_ascii_digit())
+            || !self
+                .package_type
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || ".+-".contains(c))
+        {
+            return Err(format!("Invalid purl type {}", self.package_type));
+        }
+        if self.name.len() == 0 {
+            return Err("The name of a purl cannot be empty".to_string());
+        }
+        for key in self.qualifiers.keys() {
+            if key.len() == 0
+                || key.starts_with(|c: char| c.is_ascii_digit())
+                || !key
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || ".-_".contains(c))
+            {
+                return Err(format!("Invalid purl qualifier key {}", key));
+            }
+        }
+        match self.package_type.as_str() {
+            "generic" if self.namespace.is_some() => Err("A generic purl cannot have a namespace".to_string()),
+            "maven" if self.namespace.is_none() => Err("A maven purl requires a namespace".to_string()),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl FromStr for PackageURL {
+    type Err = String;
+
+    // Parses a purl using the algorithm described in the purl spec. The purl is normalised
+    // and validated, so that parsing the output of to_string gives back the same purl.
+    fn from_str(purl: &str) -> Result<PackageURL, String> {
+        let mut package_url = PackageURL::default();
+
+        let (remainder, subpath) = match purl.rsplit_once('#') {
+            Some((r, s)) => (r, Some(s)),
+            None => (purl, None),
+        };
+        if let Some(subpath) = subpath {
+            let segments = decode_segments(subpath)?;
+            if segments.len() != 0 {
+                package_url.subpath = Some(segments);
+            }
+        }
+
+        let (remainder, qualifiers) = match remainder.rsplit_once('?') {
+            Some((r, q)) => (r, Some(q)),
+            None => (remainder, None),
+        };
+        if let Some(qualifiers) = qualifiers {
+            for qualifier in qualifiers.split('&') {
+                let (key, value) = match qualifier.split_once('=') {
+                    Some(q) => q,
+                    None => return Err(format!("Invalid purl qualifier {}", qualifier)),
+                };
+                let value = percent_decode(value)?;
+                if value.len() == 0 {
+                    continue;
+                }
+                let key = key.to_lowercase();
+                if package_url.qualifiers.contains_key(&key) {
+                    return Err(format!("Duplicate purl qualifier {}", key));
+                }
+                package_url.qualifiers.insert(key, value);
+            }
+        }
+
+        let (scheme, remainder) = match remainder.split_once(':') {
+            Some(s) => s,
+            None => return Err(format!("Missing the {} scheme in purl {}", PURL_SCHEME, purl)),
+        };
+        if scheme.to_lowercase() != PURL_SCHEME {
+            return Err(format!("Invalid purl scheme {}", scheme));
+        }
+        let remainder = remainder.trim_start_matches('/');
+
+        let (package_type, remainder) = match remainder.split_once('/') {
+            Some(t) => t,
+            None => return Err(format!("Missing the type in purl {}", purl)),
+        };
+        package_url.package_type = package_type.to_lowercase();
+
+        let remainder = remainder.trim_end_matches('/');
+        let remainder = match remainder.rsplit_once('@') {
+            Some((r, v)) => {
+                package_url.version = Some(percent_decode(v)?);
+                r
+            }
+            None => remainder,
+        };
+
+        match remainder.rsplit_once('/') {
+            Some((namespace, name)) => {
+                let namespace = decode_segments(namespace)?;
+                if namespace.len() != 0 {
+                    package_url.namespace = Some(namespace);
+                }
+                package_url.name = percent_decode(name)?;
+            }
+            None => package_url.name = percent_decode(remainder)?,
+        }
+
+        package_url.normalize();
+        package_url.validate()?;
+        Ok(package_url)
+    }
+}
+
+// Encodes each segment of a path separately, dropping the empty segments as well
+// as the . and .. segments.
+fn encode_segments(path: &str) -> String {
+    path.split('/')
+        .filter(|segment| !segment.is_empty() && *segment != "." && *segment != "..")
+        .map(percent_encode)
+        .collect::<Vec<String>>()
+        .join("/")
+}
+
+fn decode_segments(path: &str) -> Result<String, String> {
+    let mut segments: Vec<String> = vec![];
+    for segment in path.split('/') {
+        if segment.is_empty() || segment == "." || segment == ".." {
+            continue;
+        }
+        segments.push(percent_decode(segment)?);
+    }
+    Ok(segments.join("/"))
+}
+
+pub fn percent_decode(value: &str) -> Result<String, String> {
+    let bytes = value.as_bytes();
+    let mut response: Vec<u8> = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'%' {
+            response.push(bytes[i]);
+            i += 1;
+            continue;
+        }
+        let encoded = match value.get(i + 1..i + 3) {
+            Some(e) => e,
+            None => return Err(format!("Invalid percent-encoding in {}", value)),
+        };
+        match u8::from_str_radix(encoded, 16) {
+            Ok(b) => response.push(b),
+            Err(_) => return Err(format!("Invalid percent-encoding in {}", value)),
+        }
+        i += 3;
+    }
+    String::from_utf8(response).map_err(|e| e.to_string())
+}
+
+// Percent-encodes everything except the unreserved characters of RFC 3986. The colon
+// is also left as is, as recommended by the purl spec.
+pub fn percent_encode(value: &str) -> String {
+    let mut response = "".to_string();
+    for byte in value.bytes() {
+        if byte.is_ascii_alphanumeric() || b".-_~:".contains(&byte) {
+            response.push(byte as char);
+        } else {
+            response += &format!("%{:02X}", byte);
+        }
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_to_string() {
+        let mut purl = PackageURL::default();
+        purl.package_type = "generic".to_string();
+        purl.name = "openssl".to_string();
+        purl.version = Some("1.1.10g".to_string());
+        purl.qualifiers.insert(
+            DOWNLOAD_URL_QUALIFIER.to_string(),
+            "https://openssl.org/source/openssl-1.1.0g.tar.gz".to_string(),
+        );
+        purl.qualifiers
+            .insert(CHECKSUM_QUALIFIER.to_string(), "sha256:de4d501267da".to_string());
+        assert_eq!(
+            purl.to_string(),
+            "pkg:generic/openssl@1.1.10g?checksum=sha256:de4d501267da&download_url=https:%2F%2Fopenssl.org%2Fsource%2Fopenssl-1.1.0g.tar.gz"
+        );
+
+        let mut purl = PackageURL::default();
+        purl.package_type = "NPM".to_string();
+        purl.namespace = Some("@angular".to_string());
+        purl.name = "animation".to_string();
+        purl.version = Some("12.3.1".to_string());
+        assert_eq!(purl.to_string(), "pkg:npm/%40angular/animation@12.3.1");
+
+        let mut purl = PackageURL::default();
+        purl.package_type = "github".to_string();
+        purl.namespace = Some("package-url".to_string());
+        purl.name = "purl-spec".to_string();
+        purl.version = Some("244fd47e07d1004".to_string());
+        purl.subpath = Some("/./everybody/loves/../dogs/".to_string());
+        assert_eq!(
+            purl.to_string(),
+            "pkg:github/package-url/purl-spec@244fd47e07d1004#everybody/loves/dogs"
+        );
+    }
+
+    #[test]
+    pub fn test_from_str() {
+        let purl = PackageURL::from_str("pkg:npm/%40angular/animation@12.3.1").unwrap();
+        assert_eq!(purl.package_type, "npm");
+        assert_eq!(purl.namespace, Some("@angular".to_string()));
+        assert_eq!(purl.name, "animation");
+        assert_eq!(purl.version, Some("12.3.1".to_string()));
+
+        let purl = PackageURL::from_str(
+            "pkg://maven/org.apache.xmlgraphics/batik-anim@1.9.1?classifier=sources&repositorY_url=repo.spring.io%2Frelease",
+        )
+        .unwrap();
+        assert_eq!(purl.namespace, Some("org.apache.xmlgraphics".to_string()));
+        assert_eq!(purl.qualifiers.get("classifier").unwrap(), "sources");
+        assert_eq!(
+            purl.qualifiers.get("repository_url").unwrap(),
+            "repo.spring.io/release"
+        );
+
+        let purl =
+            PackageURL::from_str("pkg:golang/google.golang.org/genproto#/googleapis/api/annotations/").unwrap();
+        assert_eq!(purl.namespace, Some("google.golang.org".to_string()));
+        assert_eq!(purl.version, None);
+        assert_eq!(purl.subpath, Some("googleapis/api/annotations".to_string()));
+    }
+
+    #[test]
+    pub fn test_from_str_normalization() {
+        let purl = PackageURL::from_str("pkg:PyPi/Django_Allauth@1.11.1").unwrap();
+        assert_eq!(purl.package_type, "pypi");
+        assert_eq!(purl.name, "django-allauth");
+
+        let purl = PackageURL::from_str("pkg:GitHub/Package-URL/Purl-Spec@244fd47e07d1004").unwrap();
+        assert_eq!(
+            purl.to_string(),
+            "pkg:github/package-url/purl-spec@244fd47e07d1004"
+        );
+    }
+
+    #[test]
+    pub fn test_from_str_errors() {
+        assert!(PackageURL::from_str("").is_err());
+        assert!(PackageURL::from_str("generic/hello@1.0").is_err());
+        assert!(PackageURL::from_str("http://generic/hello@1.0").is_err());
+        assert!(PackageURL::from_str("pkg:hello").is_err());
+        assert!(PackageURL::from_str("pkg:generic/").is_err());
+        assert!(PackageURL::from_str("pkg:generic/acme/hello@1.0").is_err());
+        assert!(PackageURL::from_str("pkg:maven/batik-anim@1.9.1").is_err());
+        assert!(PackageURL::from_str("pkg:3rd/hello").is_err());
+        assert!(PackageURL::from_str("pkg:generic/hello?checksum").is_err());
+        assert!(PackageURL::from_str("pkg:generic/hello?a=1&a=2").is_err());
+        assert!(PackageURL::from_str("pkg:generic/hello%2").is_err());
+    }
+
+    #[test]
+    pub fn test_round_trip() {
+        for purl in [
+            "pkg:generic/openssl@1.1.10g?checksum=sha256:de4d501267da&download_url=https:%2F%2Fopenssl.org%2Fsource%2Fopenssl-1.1.0g.tar.gz",
+            "pkg:npm/%40angular/animation@12.3.1",
+            "pkg:maven/org.apache.xmlgraphics/batik-anim@1.9.1?classifier=sources",
+            "pkg:github/package-url/purl-spec@244fd47e07d1004#everybody/loves/dogs",
+            "pkg:pypi/django-allauth@1.11.1",
+            "pkg:generic/hello%20world@1.0%2Bbuild.2",
+        ] {
+            let parsed = PackageURL::from_str(purl).unwrap();
+            assert_eq!(parsed.to_string(), purl);
+            assert_eq!(PackageURL::from_str(&parsed.to_string()).unwrap(), parsed);
+        }
+
+        let package_graph = crate::nix::get_test_package_graph();
+        for package_node in package_graph.nodes.values() {
+            let purl = package_node.get_purl();
+            assert_eq!(PackageURL::from_str(&purl.to_string()).unwrap(), purl);
+        }
+    }
+
+    #[test]
+    pub fn test_from_download_url() {
+        let purls: Vec<(&str, Option<&str>, &str)> = vec![
+            (
+                "https://crates.io/api/v1/crates/serde/1.0.1/download",
+                None,
+                "pkg:cargo/serde@1.0.1",
+            ),
+            (
+                "https://static.crates.io/crates/serde_json/1.0.117/download",
+                None,
+                "pkg:cargo/serde_json@1.0.117",
+            ),
+            (
+                "https://registry.npmjs.org/@angular/animation/-/animation-12.3.1.tgz",
+                None,
+                "pkg:npm/%40angular/animation@12.3.1",
+            ),
+            (
+                "https://registry.npmjs.org/lodash/-/lodash-4.17.21.tgz",
+                None,
+                "pkg:npm/lodash@4.17.21",
+            ),
+            (
+                "https://repo.maven.apache.org/maven2/org/apache/xmlgraphics/batik-anim/1.9.1/batik-anim-1.9.1.jar",
+                None,
+                "pkg:maven/org.apache.xmlgraphics/batik-anim@1.9.1",
+            ),
+            (
+                "https://pypi.io/packages/source/D/Django/Django-4.2.7.tar.gz",
+                None,
+                "pkg:pypi/django@4.2.7",
+            ),
+            (
+                "https://github.com/sass/libsass/archive/3.6.4.tar.gz",
+                None,
+                "pkg:github/sass/libsass@3.6.4",
+            ),
+            (
+                "https://github.com/NixOS/nix/archive/refs/tags/2.18.1.tar.gz",
+                None,
+                "pkg:github/nixos/nix@2.18.1",
+            ),
+            (
+                "https://github.com/BurntSushi/ripgrep/releases/download/14.0.3/ripgrep-14.0.3.tar.gz",
+                None,
+                "pkg:github/burntsushi/ripgrep@14.0.3",
+            ),
+            (
+                "https://github.com/louib/nix2sbom.git",
+                Some("7f7b4e3c"),
+                "pkg:github/louib/nix2sbom@7f7b4e3c",
+            ),
+        ];
+        for (url, revision, expected_purl) in purls {
+            let purl = PackageURL::from_download_url(url, revision).unwrap();
+            assert_eq!(purl.to_string(), expected_purl);
+        }
+
+        assert!(
+            PackageURL::from_download_url("https://ftp.gnu.org/gnu/hello/hello-2.12.1.tar.gz", None).is_none()
+        );
+        assert!(
+            PackageURL::from_download_url("https://registry.npmjs.org/lodash/-/other-4.17.21.tgz", None)
+                .is_none()
+        );
+    }
+
+    #[test]
+    pub fn test_percent_encode() {
+        assert_eq!(percent_encode("hello world"), "hello%20world");
+        assert_eq!(percent_encode("1.0+build.2"), "1.0%2Bbuild.2");
+        assert_eq!(percent_encode("a/b?c#d@e"), "a%2Fb%3Fc%23d%40e");
+        assert_eq!(percent_encode("sha256:abc"), "sha256:abc");
+        assert_eq!(percent_encode("é"), "%C3%A9");
+    }
+
+    #[test]
+    pub fn test_empty_qualifiers_are_omitted() {
+        let mut purl = PackageURL::default();
+        purl.package_type = "generic".to_string();
+        purl.name = "hello".to_string();
+        purl.qualifiers
+            .insert(DOWNLOAD_URL_QUALIFIER.to_string(), "".to_string());
+        assert_eq!(purl.to_string(), "pkg:generic/hello");
+    }
+}
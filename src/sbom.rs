@@ -3,6 +3,10 @@ pub const SPDX_NAME: &str = "SPDX";
 pub const PRETTY_PRINT_NAME: &str = "pretty-print";
 pub const OUT_PATHS_NAME: &str = "pretty-print";
 pub const STATS_NAME: &str = "stats";
+pub const GRAPH_NAME: &str = "graph";
+
+// See https://reproducible-builds.org/specs/source-date-epoch/
+pub const SOURCE_DATE_EPOCH_VAR_NAME: &str = "SOURCE_DATE_EPOCH";
 
 pub enum Format {
     SPDX,
@@ -10,6 +14,8 @@ pub enum Format {
     PrettyPrint,
     OutPaths,
     Stats,
+    // The package graph itself, which can be compared with the diff mode.
+    Graph,
 }
 
 impl Format {
@@ -29,6 +35,9 @@ impl Format {
         if format.ends_with("stats") {
             return Some(Format::Stats);
         }
+        if format.ends_with("graph") {
+            return Some(Format::Graph);
+        }
         None
     }
 
@@ -39,6 +48,7 @@ impl Format {
             crate::sbom::Format::PrettyPrint => PRETTY_PRINT_NAME.to_string(),
             crate::sbom::Format::OutPaths => OUT_PATHS_NAME.to_string(),
             crate::sbom::Format::Stats => STATS_NAME.to_string(),
+            crate::sbom::Format::Graph => GRAPH_NAME.to_string(),
         }
     }
 
@@ -47,6 +57,7 @@ impl Format {
             crate::sbom::Format::CycloneDX => crate::sbom::SerializationFormat::JSON,
             crate::sbom::Format::SPDX => crate::sbom::SerializationFormat::JSON,
             crate::sbom::Format::Stats => crate::sbom::SerializationFormat::JSON,
+            crate::sbom::Format::Graph => crate::sbom::SerializationFormat::JSON,
             // We don't really care which value is returned in those cases.
             crate::sbom::Format::PrettyPrint => crate::sbom::SerializationFormat::XML,
             crate::sbom::Format::OutPaths => crate::sbom::SerializationFormat::XML,
@@ -66,9 +77,12 @@ impl Format {
                     Err(s) => Err(Box::new(crate::errors::Error::UnknownError(s))),
                 };
             }
-            crate::sbom::Format::SPDX => Err(Box::new(crate::errors::Error::UnsupportedFormat(
-                "spdx".to_string(),
-            ))),
+            crate::sbom::Format::SPDX => {
+                return match crate::spdx::dump(&package_graph, &serialization_format, options) {
+                    Ok(d) => Ok(d),
+                    Err(s) => Err(Box::new(crate::errors::Error::UnknownError(s))),
+                };
+            }
             crate::sbom::Format::PrettyPrint => {
                 let display_options = crate::nix::DisplayOptions {
                     print_stdenv: false,
@@ -85,6 +99,9 @@ impl Format {
             crate::sbom::Format::Stats => {
                 return Ok(serde_json::to_string_pretty(&package_graph.get_stats(options))?);
             }
+            crate::sbom::Format::Graph => {
+                return Ok(serde_json::to_string_pretty(&package_graph)?);
+            }
         }
     }
 }
@@ -102,6 +119,8 @@ pub enum SerializationFormat {
     JSON,
     YAML,
     XML,
+    // The SPDX tag-value text format (`.spdx` files).
+    TagValue,
 }
 
 impl SerializationFormat {
@@ -115,6 +134,44 @@ impl SerializationFormat {
         if format.ends_with("xml") {
             return Some(SerializationFormat::XML);
         }
+        if format.ends_with("spdx") || format.ends_with("tag-value") || format.ends_with("tv") {
+            return Some(SerializationFormat::TagValue);
+        }
         None
     }
 }
+
+// Parses a timestamp given either as a number of seconds since the Unix epoch
+// (the format used by SOURCE_DATE_EPOCH) or as a RFC 3339 date.
+pub fn parse_timestamp(timestamp: &str) -> Option<chrono::DateTime<chrono::Utc>> {
+    let timestamp = timestamp.trim();
+    if let Ok(seconds) = timestamp.parse::<i64>() {
+        return chrono::DateTime::from_timestamp(seconds, 0);
+    }
+    match chrono::DateTime::parse_from_rfc3339(timestamp) {
+        Ok(t) => Some(t.with_timezone(&chrono::Utc)),
+        Err(_) => None,
+    }
+}
+
+// Returns the timestamp to use in the SBOM, falling back to the current time.
+pub fn get_timestamp(options: &crate::nix::DumpOptions) -> chrono::DateTime<chrono::Utc> {
+    match options.timestamp {
+        Some(t) => t,
+        None => chrono::Utc::now(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_parse_timestamp() {
+        let timestamp = parse_timestamp("1700000000").unwrap();
+        assert_eq!(timestamp.to_rfc3339(), "2023-11-14T22:13:20+00:00");
+        let timestamp = parse_timestamp("2023-11-14T23:13:20+01:00").unwrap();
+        assert_eq!(timestamp.timestamp(), 1700000000);
+        assert!(parse_timestamp("yesterday").is_none());
+    }
+}
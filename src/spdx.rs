@@ -0,0 +1,787 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde_spdx::spdx::v_2_3::{
+    Spdx, SpdxBuilder, SpdxCreationInfoBuilder, SpdxItemHasExtractedLicensingInfos,
+    SpdxItemHasExtractedLicensingInfosBuilder, SpdxItemPackages, SpdxItemPackagesBuilder,
+    SpdxItemPackagesItemExternalRefs, SpdxItemPackagesItemExternalRefsBuilder, SpdxItemRelationships,
+    SpdxItemRelationshipsBuilder,
+};
+
+const CURRENT_SPEC_VERSION: &str = "SPDX-2.3";
+// The SPDX spec requires the data license of the document to be CC0-1.0.
+const DATA_LICENSE: &str = "CC0-1.0";
+const DOCUMENT_SPDX_ID: &str = "SPDXRef-DOCUMENT";
+// Used as the prefix of the namespace of the documents when none is given and the crate does
+// not declare its repository.
+const DEFAULT_DOCUMENT_NAMESPACE_PREFIX: &str = "https://spdx.org/spdxdocs";
+pub const NO_ASSERTION: &str = "NOASSERTION";
+
+pub const DEPENDS_ON_RELATIONSHIP: &str = "DEPENDS_ON";
+pub const BUILD_DEPENDENCY_OF_RELATIONSHIP: &str = "BUILD_DEPENDENCY_OF";
+pub const PATCH_APPLIED_RELATIONSHIP: &str = "PATCH_APPLIED";
+pub const DESCRIBES_RELATIONSHIP: &str = "DESCRIBES";
+
+pub fn dump(
+    package_graph: &crate::nix::PackageGraph,
+    format: &crate::sbom::SerializationFormat,
+    options: &crate::nix::DumpOptions,
+) -> Result<String, String> {
+    let spdx = get_document(package_graph, options)?;
+
+    match format {
+        crate::sbom::SerializationFormat::JSON => {
+            serde_json::to_string_pretty(&spdx).map_err(|e| e.to_string())
+        }
+        crate::sbom::SerializationFormat::YAML => serde_yaml::to_string(&spdx).map_err(|e| e.to_string()),
+        crate::sbom::SerializationFormat::XML => Err("XML is not supported for SPDX".to_string()),
+        crate::sbom::SerializationFormat::TagValue => Ok(to_tag_value(&spdx)),
+    }
+}
+
+pub fn get_document(
+    package_graph: &crate::nix::PackageGraph,
+    options: &crate::nix::DumpOptions,
+) -> Result<Spdx, String> {
+    let timestamp = crate::sbom::get_timestamp(options);
+
+    let creation_info = SpdxCreationInfoBuilder::default()
+        .created(timestamp.format("%Y-%m-%dT%H:%M:%SZ").to_string())
+        .creators(vec![format!(
+            "Tool: {}-{}",
+            crate::consts::PROJECT_NAME,
+            env!("CARGO_PKG_VERSION")
+        )])
+        .build()
+        .map_err(|e| e.to_string())?;
+
+    let mut packages: Vec<SpdxItemPackages> = vec![];
+    let mut package_ids: BTreeSet<String> = BTreeSet::default();
+    // The licenses which are not on the SPDX license list, keyed by their LicenseRef- identifier.
+    let mut license_refs: BTreeMap<String, crate::license::NormalizedLicense> = BTreeMap::default();
+    for (derivation_path, package_node) in package_graph.nodes.iter() {
+        if let Some(package) = dump_package_node(derivation_path, package_node, options) {
+            package_ids.insert(package.spdxid.to_string());
+            packages.push(package);
+            for license in get_licenses(package_node) {
+                if license.is_license_ref {
+                    license_refs.insert(license.id.to_string(), license);
+                }
+            }
+        }
+    }
+
+    let flake_inputs = crate::cyclone_dx::get_flake_inputs(options);
+    for flake_input in &flake_inputs {
+        packages.push(dump_flake_input(flake_input));
+    }
+
+    let mut relationships: Vec<SpdxItemRelationships> = vec![];
+    for flake_input in &flake_inputs {
+        for input in &flake_input.inputs {
+            relationships.push(build_relationship(
+                &get_spdx_id(&flake_input.get_ref()),
+                DEPENDS_ON_RELATIONSHIP,
+                &get_spdx_id(&crate::flake_lock::get_flake_input_ref(input)),
+            ));
+        }
+    }
+    let flake_root_inputs = match &options.flake_lock {
+        Some(flake_lock) => flake_lock.get_root_inputs(),
+        None => BTreeSet::default(),
+    };
+    for root_node in &package_graph.root_nodes {
+        let root_node_id = get_spdx_id(root_node);
+        if !package_ids.contains(&root_node_id) {
+            continue;
+        }
+        for input in &flake_root_inputs {
+            relationships.push(build_relationship(
+                &root_node_id,
+                DEPENDS_ON_RELATIONSHIP,
+                &get_spdx_id(&crate::flake_lock::get_flake_input_ref(input)),
+            ));
+        }
+    }
+    for root_node in &package_graph.root_nodes {
+        let root_node_id = get_spdx_id(root_node);
+        if !package_ids.contains(&root_node_id) {
+            continue;
+        }
+        relationships.push(build_relationship(
+            DOCUMENT_SPDX_ID,
+            DESCRIBES_RELATIONSHIP,
+            &root_node_id,
+        ));
+    }
+
+    for (derivation_path, package_node) in package_graph.nodes.iter() {
+        let package_id = get_spdx_id(derivation_path);
+        if !package_ids.contains(&package_id) {
+            continue;
+        }
+        for dependency in package_node.get_dependencies(options.runtime_only) {
+            let dependency_id = get_spdx_id(&dependency);
+            if !package_ids.contains(&dependency_id) {
+                continue;
+            }
+            match package_node.get_dependency_type(&dependency) {
+                crate::nix::DependencyType::Runtime => relationships.push(build_relationship(
+                    &package_id,
+                    DEPENDS_ON_RELATIONSHIP,
+                    &dependency_id,
+                )),
+                // The direction of the BUILD_DEPENDENCY_OF relationship is reversed, the
+                // build input is the subject of the relationship.
+                crate::nix::DependencyType::BuildTime => relationships.push(build_relationship(
+                    &dependency_id,
+                    BUILD_DEPENDENCY_OF_RELATIONSHIP,
+                    &package_id,
+                )),
+            }
+        }
+        for patch in package_node.patches.iter() {
+            let patch_id = get_spdx_id(patch);
+            if package_ids.contains(&patch_id) {
+                relationships.push(build_relationship(
+                    &patch_id,
+                    PATCH_APPLIED_RELATIONSHIP,
+                    &package_id,
+                ));
+            }
+        }
+    }
+
+    relationships.sort_by_cached_key(get_relationship_key);
+    relationships.dedup_by_key(|r| get_relationship_key(r));
+
+    let document_name = get_document_name(package_graph);
+    let mut spdx_builder = SpdxBuilder::default();
+    if license_refs.len() != 0 {
+        spdx_builder.has_extracted_licensing_infos(
+            license_refs
+                .values()
+                .map(get_extracted_licensing_info)
+                .collect::<Vec<SpdxItemHasExtractedLicensingInfos>>(),
+        );
+    }
+    let mut spdx = spdx_builder
+        .spdxid(DOCUMENT_SPDX_ID)
+        .spdx_version(CURRENT_SPEC_VERSION)
+        .data_license(DATA_LICENSE)
+        .name(document_name.to_string())
+        .document_namespace("".to_string())
+        .creation_info(creation_info)
+        .packages(packages)
+        .relationships(relationships)
+        .build()
+        .map_err(|e| e.to_string())?;
+
+    // The namespace is derived from the content of the document, so that the same
+    // package graph always produces the same document.
+    let content = serde_json::to_vec(&spdx).map_err(|e| e.to_string())?;
+    spdx.document_namespace = format!(
+        "{}/{}-{}",
+        get_document_namespace_prefix(options),
+        document_name,
+        uuid::Uuid::new_v5(&uuid::Uuid::NAMESPACE_URL, &content)
+    );
+    Ok(spdx)
+}
+
+fn get_document_namespace_prefix(options: &crate::nix::DumpOptions) -> String {
+    if let Some(prefix) = &options.document_namespace_prefix {
+        return prefix.trim_end_matches('/').to_string();
+    }
+    let repository = env!("CARGO_PKG_REPOSITORY");
+    if repository.len() != 0 {
+        return format!("{}/spdxdocs", repository.trim_end_matches('/'));
+    }
+    DEFAULT_DOCUMENT_NAMESPACE_PREFIX.to_string()
+}
+
+fn get_relationship_key(relationship: &SpdxItemRelationships) -> (String, String, String) {
+    (
+        relationship.spdx_element_id.to_string(),
+        relationship.relationship_type.to_string(),
+        relationship.related_spdx_element.to_string(),
+    )
+}
+
+pub fn dump_package_node(
+    derivation_path: &str,
+    package_node: &crate::nix::PackageNode,
+    options: &crate::nix::DumpOptions,
+) -> Option<SpdxItemPackages> {
+    // FIXME this should be configurable.
+    if package_node.is_inline_script() {
+        return None;
+    }
+
+    let name = match package_node.get_name() {
+        Some(n) => n,
+        None => return None,
+    };
+    log::debug!("Dumping SPDX package for {}", &derivation_path);
+
+    let mut package_builder = SpdxItemPackagesBuilder::default();
+    package_builder.spdxid(get_spdx_id(derivation_path));
+    package_builder.name(name);
+    // We don't have access to the files of the package, only to the derivation.
+    package_builder.files_analyzed(false);
+    package_builder
+        .primary_package_purpose(crate::component_type::get_component_type(package_node).to_spdx_purpose());
+    package_builder.copyright_text(NO_ASSERTION);
+    package_builder.license_concluded(NO_ASSERTION);
+    package_builder.license_declared(get_license_declared(package_node, options));
+
+    let download_location = match package_node.main_derivation.get_url() {
+        Some(u) => u,
+        None => NO_ASSERTION.to_string(),
+    };
+    package_builder.download_location(download_location);
+
+    if let Some(v) = package_node.get_version() {
+        package_builder.version_info(v);
+    } else if let Some(v) = package_node.main_derivation.get_version() {
+        package_builder.version_info(v);
+    }
+
+    if let Some(p) = &package_node.package {
+        if let Some(description) = &p.meta.description {
+            package_builder.description(description.to_string());
+        }
+        if let Some(homepage) = p.meta.get_homepages().first() {
+            package_builder.homepage(homepage.to_string());
+        }
+    }
+
+    let mut external_refs = vec![SpdxItemPackagesItemExternalRefsBuilder::default()
+        .reference_category("PACKAGE-MANAGER")
+        .reference_type("purl")
+        .reference_locator(package_node.get_purl().to_string())
+        .build()
+        .unwrap()];
+    if let Some(cpe) = package_node.get_cpe(options) {
+        external_refs.push(
+            SpdxItemPackagesItemExternalRefsBuilder::default()
+                .reference_category("SECURITY")
+                .reference_type("cpe23Type")
+                .reference_locator(cpe)
+                .build()
+                .unwrap(),
+        );
+    }
+    package_builder.external_refs(external_refs);
+
+    Some(package_builder.build().unwrap())
+}
+
+// The inputs of the flake are the sources the packages were built from.
+fn dump_flake_input(flake_input: &crate::flake_lock::FlakeInput) -> SpdxItemPackages {
+    let locked = &flake_input.locked;
+    let mut package_builder = SpdxItemPackagesBuilder::default();
+    package_builder.spdxid(get_spdx_id(&flake_input.get_ref()));
+    package_builder.name(flake_input.get_name());
+    package_builder.files_analyzed(false);
+    package_builder.primary_package_purpose("SOURCE");
+    package_builder.copyright_text(NO_ASSERTION);
+    package_builder.license_concluded(NO_ASSERTION);
+    package_builder.license_declared(NO_ASSERTION);
+    if let Some(version) = locked.rev.as_ref().or(locked.git_ref.as_ref()) {
+        package_builder.version_info(version.to_string());
+    }
+
+    let url = locked.get_url();
+    // See https://spdx.github.io/spdx-spec/v2.3/package-information/#77-package-download-location-field
+    let vcs_tool = match locked.reference_type.as_str() {
+        "github" | "gitlab" | "sourcehut" | "git" => Some("git"),
+        "mercurial" => Some("hg"),
+        _ => None,
+    };
+    let download_location = match (&url, vcs_tool, &locked.rev) {
+        (Some(url), Some(tool), Some(rev)) => format!("{}+{}@{}", tool, url, rev),
+        (Some(url), Some(tool), None) => format!("{}+{}", tool, url),
+        (Some(url), None, _) if locked.reference_type != "path" => url.to_string(),
+        _ => NO_ASSERTION.to_string(),
+    };
+    package_builder.download_location(download_location);
+
+    let mut comments: Vec<String> = vec![];
+    if let Some(nar_hash) = &locked.nar_hash {
+        comments.push(format!("narHash: {}", nar_hash));
+    }
+    if let Some(last_modified) = locked.last_modified {
+        comments.push(format!("lastModified: {}", last_modified));
+    }
+    if comments.len() != 0 {
+        package_builder.comment(comments.join("\n"));
+    }
+
+    let purl = url
+        .as_ref()
+        .and_then(|u| crate::purl::PackageURL::from_download_url(u, locked.rev.as_deref()));
+    if let Some(purl) = purl {
+        package_builder.external_refs(vec![SpdxItemPackagesItemExternalRefsBuilder::default()
+            .reference_category("PACKAGE-MANAGER")
+            .reference_type("purl")
+            .reference_locator(purl.to_string())
+            .build()
+            .unwrap()]);
+    }
+
+    package_builder.build().unwrap()
+}
+
+// Serializes the document using the SPDX tag-value format.
+// Packages and relationships are sorted so that the output is stable between runs.
+// See https://spdx.github.io/spdx-spec/v2.3/conformance/#44-standard-data-format-requirements
+pub fn to_tag_value(spdx: &Spdx) -> String {
+    let mut lines: Vec<String> = vec![
+        get_tag_value_line("SPDXVersion", &spdx.spdx_version),
+        get_tag_value_line("DataLicense", &spdx.data_license),
+        get_tag_value_line("SPDXID", &spdx.spdxid),
+        get_tag_value_line("DocumentName", &spdx.name),
+        get_tag_value_line("DocumentNamespace", &spdx.document_namespace),
+    ];
+    if let Some(comment) = &spdx.comment {
+        lines.push(get_tag_value_line("DocumentComment", comment));
+    }
+    for creator in &spdx.creation_info.creators {
+        lines.push(get_tag_value_line("Creator", creator));
+    }
+    lines.push(get_tag_value_line("Created", &spdx.creation_info.created));
+    if let Some(license_list_version) = &spdx.creation_info.license_list_version {
+        lines.push(get_tag_value_line("LicenseListVersion", license_list_version));
+    }
+
+    let mut packages = spdx.packages.clone().unwrap_or_default();
+    packages.sort_by(|a, b| a.spdxid.cmp(&b.spdxid));
+    for package in &packages {
+        lines.push("".to_string());
+        lines.push(format!("##### Package: {}", package.name));
+        lines.push("".to_string());
+        lines.push(get_tag_value_line("PackageName", &package.name));
+        lines.push(get_tag_value_line("SPDXID", &package.spdxid));
+        if let Some(version) = &package.version_info {
+            lines.push(get_tag_value_line("PackageVersion", version));
+        }
+        if let Some(supplier) = &package.supplier {
+            lines.push(get_tag_value_line("PackageSupplier", supplier));
+        }
+        if let Some(originator) = &package.originator {
+            lines.push(get_tag_value_line("PackageOriginator", originator));
+        }
+        lines.push(get_tag_value_line(
+            "PackageDownloadLocation",
+            &package.download_location,
+        ));
+        if let Some(files_analyzed) = package.files_analyzed {
+            lines.push(get_tag_value_line("FilesAnalyzed", &files_analyzed.to_string()));
+        }
+        for checksum in package.checksums.as_deref().unwrap_or_default() {
+            lines.push(get_tag_value_line(
+                "PackageChecksum",
+                &format!("{}: {}", checksum.algorithm, checksum.checksum_value),
+            ));
+        }
+        if let Some(homepage) = &package.homepage {
+            lines.push(get_tag_value_line("PackageHomePage", homepage));
+        }
+        if let Some(license_concluded) = &package.license_concluded {
+            lines.push(get_tag_value_line("PackageLicenseConcluded", license_concluded));
+        }
+        if let Some(license_declared) = &package.license_declared {
+            lines.push(get_tag_value_line("PackageLicenseDeclared", license_declared));
+        }
+        if let Some(copyright_text) = &package.copyright_text {
+            lines.push(get_tag_value_line("PackageCopyrightText", copyright_text));
+        }
+        if let Some(summary) = &package.summary {
+            lines.push(get_tag_value_line("PackageSummary", summary));
+        }
+        if let Some(description) = &package.description {
+            lines.push(get_tag_value_line("PackageDescription", description));
+        }
+        if let Some(comment) = &package.comment {
+            lines.push(get_tag_value_line("PackageComment", comment));
+        }
+        let mut external_refs: Vec<&SpdxItemPackagesItemExternalRefs> = package
+            .external_refs
+            .as_deref()
+            .unwrap_or_default()
+            .iter()
+            .collect();
+        external_refs.sort_by(|a, b| {
+            (&a.reference_category, &a.reference_type, &a.reference_locator).cmp(&(
+                &b.reference_category,
+                &b.reference_type,
+                &b.reference_locator,
+            ))
+        });
+        for external_ref in external_refs {
+            lines.push(get_tag_value_line(
+                "ExternalRef",
+                &format!(
+                    "{} {} {}",
+                    external_ref.reference_category,
+                    external_ref.reference_type,
+                    external_ref.reference_locator
+                ),
+            ));
+        }
+        if let Some(primary_package_purpose) = &package.primary_package_purpose {
+            lines.push(get_tag_value_line(
+                "PrimaryPackagePurpose",
+                primary_package_purpose,
+            ));
+        }
+    }
+
+    for extracted_licensing_info in spdx.has_extracted_licensing_infos.as_deref().unwrap_or_default() {
+        lines.push("".to_string());
+        lines.push(get_tag_value_line(
+            "LicenseID",
+            &extracted_licensing_info.license_id,
+        ));
+        lines.push(get_tag_value_line(
+            "ExtractedText",
+            &extracted_licensing_info.extracted_text,
+        ));
+        if let Some(name) = &extracted_licensing_info.name {
+            lines.push(get_tag_value_line("LicenseName", name));
+        }
+    }
+
+    let mut relationships: Vec<String> = spdx
+        .relationships
+        .as_deref()
+        .unwrap_or_default()
+        .iter()
+        .map(|r| {
+            get_tag_value_line(
+                "Relationship",
+                &format!(
+                    "{} {} {}",
+                    r.spdx_element_id, r.relationship_type, r.related_spdx_element
+                ),
+            )
+        })
+        .collect();
+    relationships.sort();
+    relationships.dedup();
+    if relationships.len() != 0 {
+        lines.push("".to_string());
+        lines.append(&mut relationships);
+    }
+
+    lines.join("\n") + "\n"
+}
+
+// Multi-line values have to be wrapped in <text> tags in the tag-value format.
+fn get_tag_value_line(tag: &str, value: &str) -> String {
+    if value.contains('\n') {
+        return format!("{}: <text>{}</text>", tag, value);
+    }
+    format!("{}: {}", tag, value)
+}
+
+fn build_relationship(
+    element_id: &str,
+    relationship_type: &str,
+    related_element_id: &str,
+) -> SpdxItemRelationships {
+    SpdxItemRelationshipsBuilder::default()
+        .spdx_element_id(element_id)
+        .relationship_type(relationship_type)
+        .related_spdx_element(related_element_id)
+        .build()
+        .unwrap()
+}
+
+// SPDX identifiers can only contain letters, numbers, `.` and `-`.
+// See https://spdx.github.io/spdx-spec/v2.3/package-information/#72-package-spdx-identifier-field
+pub fn get_spdx_id(derivation_path: &str) -> String {
+    let derivation_file_name = derivation_path.rsplit('/').next().unwrap_or(derivation_path);
+    let sanitized_name: String = derivation_file_name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect();
+    format!("SPDXRef-{}", sanitized_name)
+}
+
+fn get_document_name(package_graph: &crate::nix::PackageGraph) -> String {
+    if package_graph.root_nodes.len() == 1 {
+        let root_node_path = package_graph.root_nodes.first().unwrap();
+        if let Some(root_node) = package_graph.nodes.get(root_node_path) {
+            if let Some(name) = root_node.get_name() {
+                return name;
+            }
+        }
+    }
+    crate::consts::PROJECT_NAME.to_string()
+}
+
+fn get_licenses(package_node: &crate::nix::PackageNode) -> Vec<crate::license::NormalizedLicense> {
+    match &package_node.package {
+        Some(p) => crate::license::normalize_licenses(&p.meta.get_licenses()),
+        None => vec![],
+    }
+}
+
+fn get_license_declared(package_node: &crate::nix::PackageNode, options: &crate::nix::DumpOptions) -> String {
+    let licenses = get_licenses(package_node);
+    crate::license::get_license_expression(&licenses, &options.license_join_policy)
+        .unwrap_or(NO_ASSERTION.to_string())
+}
+
+// Every LicenseRef- used in the document has to be defined with its text. We don't have
+// the text of the licenses, only their names.
+fn get_extracted_licensing_info(
+    license: &crate::license::NormalizedLicense,
+) -> SpdxItemHasExtractedLicensingInfos {
+    let name = license.name.clone().unwrap_or(license.id.to_string());
+    SpdxItemHasExtractedLicensingInfosBuilder::default()
+        .license_id(license.id.to_string())
+        .name(name.to_string())
+        .extracted_text(format!(
+            "The text of the {} license is not available in nixpkgs.",
+            name
+        ))
+        .build()
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_get_spdx_id() {
+        assert_eq!(
+            get_spdx_id("/nix/store/975cwk57d5xy6cyakapsifyg19n3g516-file-5.44.drv"),
+            "SPDXRef-975cwk57d5xy6cyakapsifyg19n3g516-file-5.44.drv"
+        );
+        assert_eq!(
+            get_spdx_id("/nix/store/7aqhla8b73157glwx8klvnvdq014ykri-python3.10-pycairo_1.23.0+dev.drv"),
+            "SPDXRef-7aqhla8b73157glwx8klvnvdq014ykri-python3.10-pycairo-1.23.0-dev.drv"
+        );
+    }
+
+    #[test]
+    pub fn test_relationships() {
+        let package_graph = crate::nix::get_test_package_graph();
+
+        let document = get_document(&package_graph, &crate::nix::DumpOptions::default()).unwrap();
+        assert_eq!(document.spdx_version, "SPDX-2.3");
+        assert_eq!(document.name, "hello");
+        assert_eq!(document.packages.unwrap().len(), 4);
+
+        let relationships: Vec<(String, String, String)> = document
+            .relationships
+            .unwrap()
+            .into_iter()
+            .map(|r| (r.spdx_element_id, r.relationship_type, r.related_spdx_element))
+            .collect();
+        let hello_id = "SPDXRef-aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa-hello-2.12.1.drv".to_string();
+        assert!(relationships.contains(&(
+            "SPDXRef-DOCUMENT".to_string(),
+            "DESCRIBES".to_string(),
+            hello_id.to_string()
+        )));
+        assert!(relationships.contains(&(
+            hello_id.to_string(),
+            "DEPENDS_ON".to_string(),
+            "SPDXRef-dddddddddddddddddddddddddddddddd-glibc-2.38.drv".to_string()
+        )));
+        assert!(relationships.contains(&(
+            "SPDXRef-bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb-gettext-0.21.1.drv".to_string(),
+            "BUILD_DEPENDENCY_OF".to_string(),
+            hello_id.to_string()
+        )));
+        assert!(relationships.contains(&(
+            "SPDXRef-cccccccccccccccccccccccccccccccc-fix.patch.drv".to_string(),
+            "PATCH_APPLIED".to_string(),
+            hello_id.to_string()
+        )));
+    }
+
+    #[test]
+    pub fn test_dump_is_reproducible() {
+        let package_graph = crate::nix::get_test_package_graph();
+        let mut options = crate::nix::DumpOptions::default();
+        options.timestamp = crate::sbom::parse_timestamp("1700000000");
+        let first_document = get_document(&package_graph, &options).unwrap();
+        let second_document = get_document(&package_graph, &options).unwrap();
+        assert_eq!(first_document, second_document);
+        assert_eq!(first_document.creation_info.created, "2023-11-14T22:13:20Z");
+
+        options.document_namespace_prefix = Some("https://example.org/sboms/".to_string());
+        let document = get_document(&package_graph, &options).unwrap();
+        assert!(document
+            .document_namespace
+            .starts_with("https://example.org/sboms/hello-"));
+    }
+
+    #[test]
+    pub fn test_cpe_external_ref() {
+        let package_graph = crate::nix::get_test_package_graph();
+        let document = get_document(&package_graph, &crate::nix::DumpOptions::default()).unwrap();
+        let glibc = document
+            .packages
+            .unwrap()
+            .into_iter()
+            .find(|p| p.name == "glibc")
+            .unwrap();
+        let cpes: Vec<String> = glibc
+            .external_refs
+            .unwrap()
+            .into_iter()
+            .filter(|r| r.reference_type == "cpe23Type")
+            .map(|r| r.reference_locator)
+            .collect();
+        assert_eq!(cpes, vec!["cpe:2.3:a:gnu:glibc:2.38:*:*:*:*:*:*:*".to_string()]);
+    }
+
+    #[test]
+    pub fn test_license_refs() {
+        let mut package_graph = crate::nix::get_test_package_graph();
+        crate::nix::set_test_package(
+            &mut package_graph,
+            "/nix/store/dddddddddddddddddddddddddddddddd-glibc-2.38.drv",
+            r###"{ "name": "glibc-2.38", "pname": "glibc", "version": "2.38", "system": "x86_64-linux",
+                   "outputName": "out", "meta": { "license": [
+                     { "spdxId": "LGPL-2.1-or-later", "shortName": "lgpl21Plus" },
+                     { "shortName": "unfree", "fullName": "Unfree" }
+                   ] } }"###,
+        );
+        let document = get_document(&package_graph, &crate::nix::DumpOptions::default()).unwrap();
+        let glibc = document
+            .packages
+            .clone()
+            .unwrap()
+            .into_iter()
+            .find(|p| p.name == "glibc")
+            .unwrap();
+        assert_eq!(
+            glibc.license_declared,
+            Some("LGPL-2.1-or-later AND LicenseRef-nixpkgs-unfree".to_string())
+        );
+        let extracted_licensing_infos = document.has_extracted_licensing_infos.clone().unwrap();
+        assert_eq!(extracted_licensing_infos.len(), 1);
+        assert_eq!(
+            extracted_licensing_infos[0].license_id,
+            "LicenseRef-nixpkgs-unfree"
+        );
+        assert_eq!(extracted_licensing_infos[0].name, Some("Unfree".to_string()));
+        assert!(to_tag_value(&document).contains("\nLicenseID: LicenseRef-nixpkgs-unfree\n"));
+    }
+
+    #[test]
+    pub fn test_flake_inputs() {
+        let package_graph = crate::nix::get_test_package_graph();
+        let options = crate::nix::DumpOptions {
+            flake_lock: Some(crate::flake_lock::get_test_flake_lock()),
+            ..Default::default()
+        };
+        let document = get_document(&package_graph, &options).unwrap();
+        let nixpkgs = document
+            .packages
+            .unwrap()
+            .into_iter()
+            .find(|p| p.spdxid == "SPDXRef-flake-input-nixpkgs")
+            .unwrap();
+        assert_eq!(
+            nixpkgs.download_location,
+            "git+https://github.com/NixOS/nixpkgs@20f77aa09916374aa3141cbc605c955626762c9a"
+        );
+        assert_eq!(nixpkgs.primary_package_purpose, Some("SOURCE".to_string()));
+        let relationships: Vec<(String, String)> = document
+            .relationships
+            .unwrap()
+            .into_iter()
+            .filter(|r| r.related_spdx_element == "SPDXRef-flake-input-nixpkgs")
+            .map(|r| (r.spdx_element_id, r.relationship_type))
+            .collect();
+        assert_eq!(
+            relationships,
+            vec![
+                (
+                    "SPDXRef-aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa-hello-2.12.1.drv".to_string(),
+                    DEPENDS_ON_RELATIONSHIP.to_string()
+                ),
+                (
+                    "SPDXRef-flake-input-home-manager".to_string(),
+                    DEPENDS_ON_RELATIONSHIP.to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    pub fn test_tag_value_line() {
+        assert_eq!(get_tag_value_line("PackageName", "hello"), "PackageName: hello");
+        assert_eq!(
+            get_tag_value_line("PackageDescription", "A program\nthat says hello"),
+            "PackageDescription: <text>A program\nthat says hello</text>"
+        );
+    }
+
+    #[test]
+    pub fn test_tag_value_package_purpose() {
+        let package_graph = crate::nix::get_test_package_graph();
+        let document = get_document(&package_graph, &crate::nix::DumpOptions::default()).unwrap();
+        let tag_value = to_tag_value(&document);
+        let hello = &tag_value[tag_value.find("PackageName: hello").unwrap()..];
+        let hello = &hello[..hello.find("##### Package:").unwrap_or(hello.len())];
+        assert!(hello.contains("\nPrimaryPackagePurpose: APPLICATION\n"));
+        let patch = &tag_value[tag_value.find("PackageName: fix.patch").unwrap()..];
+        let patch = &patch[..patch.find("##### Package:").unwrap_or(patch.len())];
+        assert!(patch.contains("\nPrimaryPackagePurpose: FILE\n"));
+    }
+
+    #[test]
+    pub fn test_tag_value_is_sorted() {
+        let package = |id: &str, name: &str| {
+            SpdxItemPackagesBuilder::default()
+                .spdxid(id)
+                .name(name)
+                .download_location(NO_ASSERTION)
+                .build()
+                .unwrap()
+        };
+        let document = SpdxBuilder::default()
+            .spdxid(DOCUMENT_SPDX_ID)
+            .spdx_version(CURRENT_SPEC_VERSION)
+            .data_license(DATA_LICENSE)
+            .name("hello")
+            .document_namespace("https://example.org/hello")
+            .creation_info(
+                SpdxCreationInfoBuilder::default()
+                    .created("2024-01-01T00:00:00Z")
+                    .creators(vec!["Tool: nix2sbom".to_string()])
+                    .build()
+                    .unwrap(),
+            )
+            .packages(vec![package("SPDXRef-b", "zlib"), package("SPDXRef-a", "hello")])
+            .relationships(vec![
+                build_relationship("SPDXRef-a", DEPENDS_ON_RELATIONSHIP, "SPDXRef-b"),
+                build_relationship(DOCUMENT_SPDX_ID, DESCRIBES_RELATIONSHIP, "SPDXRef-a"),
+            ])
+            .build()
+            .unwrap();
+
+        let tag_value = to_tag_value(&document);
+        assert!(tag_value.starts_with("SPDXVersion: SPDX-2.3\nDataLicense: CC0-1.0\n"));
+        assert!(tag_value.find("PackageName: hello").unwrap() < tag_value.find("PackageName: zlib").unwrap());
+        assert!(tag_value.ends_with(
+            "Relationship: SPDXRef-DOCUMENT DESCRIBES SPDXRef-a\nRelationship: SPDXRef-a DEPENDS_ON SPDXRef-b\n"
+        ));
+        assert_eq!(tag_value, to_tag_value(&document));
+    }
+}
@@ -0,0 +1,216 @@
+// Read-only access to the database of the Nix store, which records the references
+// and the deriver of every valid path in the store. This gives the same answers as
+// `nix-store -q --references` and `nix-store -q --deriver` without calling Nix.
+use rusqlite::{Connection, OpenFlags, OptionalExtension};
+
+const DATABASE_PATH: &str = "nix/var/nix/db/db.sqlite";
+const DEFAULT_STORE_ROOT: &str = "/";
+
+pub struct StoreDatabase {
+    connection: Connection,
+}
+
+impl StoreDatabase {
+    // Opens the database of the store located under the given root, which is `/`
+    // for the local Nix store.
+    pub fn open(store_root: &str) -> Result<StoreDatabase, String> {
+        let database_path = std::path::Path::new(store_root).join(DATABASE_PATH);
+        if !database_path.is_file() {
+            return Err(format!(
+                "Could not find the Nix database at {}",
+                database_path.display()
+            ));
+        }
+
+        // The database of the local store is used by the Nix daemon, so we need to go through
+        // the usual locking. The database of a mounted image is usually read-only and not used
+        // by anyone else, so we can open it as immutable, which does not require write access
+        // to create the lock and the WAL files.
+        let database_path_str = match database_path.to_str() {
+            Some(p) => p,
+            None => {
+                return Err(format!(
+                    "The path of the Nix database {} is not valid UTF-8",
+                    database_path.display()
+                ))
+            }
+        };
+        let mut uri = format!("{}?mode=ro", get_uri(database_path_str));
+        if store_root != DEFAULT_STORE_ROOT {
+            uri += "&immutable=1";
+        }
+        let connection = Connection::open_with_flags(
+            &uri,
+            OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_URI | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        )
+        .map_err(|e| {
+            format!(
+                "Could not open the Nix database at {}: {}",
+                database_path.display(),
+                e
+            )
+        })?;
+
+        Ok(StoreDatabase { connection })
+    }
+
+    // Returns the store paths referenced by a store path, sorted.
+    pub fn get_references(&self, store_path: &str) -> Result<Vec<String>, String> {
+        let mut statement = self
+            .connection
+            .prepare_cached(
+                "SELECT reference.path FROM Refs \
+                 JOIN ValidPaths referrer ON referrer.id = Refs.referrer \
+                 JOIN ValidPaths reference ON reference.id = Refs.reference \
+                 WHERE referrer.path = ?1 ORDER BY reference.path",
+            )
+            .map_err(|e| e.to_string())?;
+        let rows = statement
+            .query_map([store_path], |row| row.get::<usize, String>(0))
+            .map_err(|e| e.to_string())?;
+
+        let mut references: Vec<String> = vec![];
+        for row in rows {
+            references.push(row.map_err(|e| e.to_string())?);
+        }
+        Ok(references)
+    }
+
+    // Returns the path of the derivation that produced a store path, if it is known.
+    pub fn get_deriver(&self, store_path: &str) -> Result<Option<String>, String> {
+        let mut statement = self
+            .connection
+            .prepare_cached("SELECT deriver FROM ValidPaths WHERE path = ?1")
+            .map_err(|e| e.to_string())?;
+        let deriver: Option<Option<String>> = statement
+            .query_row([store_path], |row| row.get(0))
+            .optional()
+            .map_err(|e| e.to_string())?;
+
+        match deriver {
+            Some(Some(d)) if d.len() != 0 => Ok(Some(d)),
+            _ => Ok(None),
+        }
+    }
+
+    pub fn is_valid_path(&self, store_path: &str) -> Result<bool, String> {
+        let mut statement = self
+            .connection
+            .prepare_cached("SELECT 1 FROM ValidPaths WHERE path = ?1")
+            .map_err(|e| e.to_string())?;
+        statement.exists([store_path]).map_err(|e| e.to_string())
+    }
+}
+
+// Creates a store database with the same schema as the one used by Nix, where
+// hello references gettext, glibc and itself.
+#[cfg(test)]
+pub(crate) fn create_test_store(store_root: &std::path::Path) {
+    let database_path = store_root.join(DATABASE_PATH);
+    std::fs::create_dir_all(database_path.parent().unwrap()).unwrap();
+    let connection = Connection::open(&database_path).unwrap();
+    connection
+        .execute_batch(
+            r###"
+            CREATE TABLE ValidPaths (
+                id integer primary key autoincrement not null,
+                path text unique not null,
+                hash text not null,
+                registrationTime integer not null,
+                deriver text,
+                narSize integer,
+                ultimate integer,
+                sigs text,
+                ca text
+            );
+            CREATE TABLE Refs (
+                referrer integer not null,
+                reference integer not null,
+                primary key (referrer, reference)
+            );
+            INSERT INTO ValidPaths (id, path, hash, registrationTime, deriver) VALUES
+                (1, '/nix/store/eeeeeeeeeeeeeeeeeeeeeeeeeeeeeeee-hello-2.12.1', 'sha256:0', 0,
+                    '/nix/store/aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa-hello-2.12.1.drv'),
+                (2, '/nix/store/dddddddddddddddddddddddddddddddd-glibc-2.38', 'sha256:0', 0, NULL),
+                (4, '/nix/store/bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb-gettext-0.21.1', 'sha256:0', 0, NULL),
+                (3, '/nix/store/aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa-hello-2.12.1.drv', 'sha256:0', 0, '');
+            INSERT INTO Refs (referrer, reference) VALUES (1, 1), (1, 2), (1, 4);
+            "###,
+        )
+        .unwrap();
+}
+
+// SQLite URIs are percent-encoded, so a `?`, `#` or `%` in the path would otherwise be
+// taken as the start of the query, the fragment or an escape.
+fn get_uri(path: &str) -> String {
+    let segments: Vec<String> = path.split('/').map(crate::purl::percent_encode).collect();
+    format!("file:{}", segments.join("/"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_store_database() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let store_root = temp_dir.path().join("store-database");
+        create_test_store(&store_root);
+        let store_database = StoreDatabase::open(store_root.to_str().unwrap()).unwrap();
+
+        let references = store_database
+            .get_references("/nix/store/eeeeeeeeeeeeeeeeeeeeeeeeeeeeeeee-hello-2.12.1")
+            .unwrap();
+        assert_eq!(
+            references,
+            vec![
+                "/nix/store/bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb-gettext-0.21.1".to_string(),
+                "/nix/store/dddddddddddddddddddddddddddddddd-glibc-2.38".to_string(),
+                "/nix/store/eeeeeeeeeeeeeeeeeeeeeeeeeeeeeeee-hello-2.12.1".to_string(),
+            ]
+        );
+        assert_eq!(
+            store_database
+                .get_deriver("/nix/store/eeeeeeeeeeeeeeeeeeeeeeeeeeeeeeee-hello-2.12.1")
+                .unwrap(),
+            Some("/nix/store/aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa-hello-2.12.1.drv".to_string())
+        );
+        assert_eq!(
+            store_database
+                .get_deriver("/nix/store/dddddddddddddddddddddddddddddddd-glibc-2.38")
+                .unwrap(),
+            None
+        );
+        assert!(store_database
+            .is_valid_path("/nix/store/dddddddddddddddddddddddddddddddd-glibc-2.38")
+            .unwrap());
+        assert!(!store_database.is_valid_path("/nix/store/unknown").unwrap());
+        assert_eq!(
+            store_database.get_references("/nix/store/unknown").unwrap().len(),
+            0
+        );
+
+        drop(store_database);
+    }
+
+    #[test]
+    pub fn test_store_root_with_uri_characters() {
+        assert_eq!(
+            get_uri("/mnt/a?b#c%d/db.sqlite"),
+            "file:/mnt/a%3Fb%23c%25d/db.sqlite"
+        );
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let store_root = temp_dir.path().join("store?mode=rw#100%");
+        create_test_store(&store_root);
+        let store_database = StoreDatabase::open(store_root.to_str().unwrap()).unwrap();
+        assert!(store_database
+            .is_valid_path("/nix/store/dddddddddddddddddddddddddddddddd-glibc-2.38")
+            .unwrap());
+    }
+
+    #[test]
+    pub fn test_missing_store_database() {
+        assert!(StoreDatabase::open("/nonexistent-nix2sbom-store").is_err());
+    }
+}
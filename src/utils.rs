@@ -266,6 +266,83 @@ pub fn get_project_name_from_archive_url(archive_url: &str) -> Option<String> {
     return None;
 }
 
+// Components of a version which sort before a release, so that 1.0rc1 < 1.0.
+// Nix only treats pre specially, but the vulnerability databases use the others as well.
+const PRE_RELEASE_COMPONENTS: &[&str] = &["pre", "rc", "alpha", "beta", "dev"];
+
+// Compares two versions like `builtins.compareVersions` in Nix: the versions are split
+// into numeric and alphabetic components on the `.` and `-` separators, and numeric
+// components are compared as numbers.
+pub fn compare_versions(version: &str, other_version: &str) -> std::cmp::Ordering {
+    let mut components = get_version_components(version).into_iter();
+    let mut other_components = get_version_components(other_version).into_iter();
+    loop {
+        let (component, other_component) = match (components.next(), other_components.next()) {
+            (None, None) => return std::cmp::Ordering::Equal,
+            (c, o) => (c.unwrap_or_default(), o.unwrap_or_default()),
+        };
+        if is_version_component_lower(&component, &other_component) {
+            return std::cmp::Ordering::Less;
+        }
+        if is_version_component_lower(&other_component, &component) {
+            return std::cmp::Ordering::Greater;
+        }
+    }
+}
+
+fn get_version_components(version: &str) -> Vec<String> {
+    let mut components: Vec<String> = vec![];
+    let mut current_component = "".to_string();
+    for c in version.chars() {
+        let is_separator = c == '.' || c == '-';
+        let changes_kind = match current_component.chars().last() {
+            Some(l) => l.is_ascii_digit() != c.is_ascii_digit(),
+            None => false,
+        };
+        if (is_separator || changes_kind) && current_component.len() != 0 {
+            components.push(current_component);
+            current_component = "".to_string();
+        }
+        if !is_separator {
+            current_component.push(c);
+        }
+    }
+    if current_component.len() != 0 {
+        components.push(current_component);
+    }
+    components
+}
+
+// Port of componentsLT from Nix.
+fn is_version_component_lower(component: &str, other_component: &str) -> bool {
+    let is_numeric = component.len() != 0 && component.chars().all(|c| c.is_ascii_digit());
+    let other_is_numeric = other_component.len() != 0 && other_component.chars().all(|c| c.is_ascii_digit());
+    if is_numeric && other_is_numeric {
+        let component = component.trim_start_matches('0');
+        let other_component = other_component.trim_start_matches('0');
+        return (component.len(), component) < (other_component.len(), other_component);
+    }
+    let is_pre_release = PRE_RELEASE_COMPONENTS.contains(&component.to_lowercase().as_str());
+    let other_is_pre_release = PRE_RELEASE_COMPONENTS.contains(&other_component.to_lowercase().as_str());
+    if component.len() == 0 && other_is_numeric {
+        return true;
+    }
+    if is_pre_release && !other_is_pre_release {
+        return true;
+    }
+    if other_is_pre_release {
+        return false;
+    }
+    // 2.3a < 2.3.1
+    if other_is_numeric {
+        return true;
+    }
+    if is_numeric {
+        return false;
+    }
+    component < other_component
+}
+
 #[cfg(test)]
 mod tests {
     #[test]
@@ -426,4 +503,27 @@ mod tests {
         assert!(project_name.is_some());
         assert_eq!(project_name.unwrap(), "clap_lex");
     }
+
+    #[test]
+    pub fn test_compare_versions() {
+        use std::cmp::Ordering;
+        assert_eq!(crate::utils::compare_versions("1.0", "1.0"), Ordering::Equal);
+        assert_eq!(crate::utils::compare_versions("1.0", "1.0.0"), Ordering::Less);
+        assert_eq!(crate::utils::compare_versions("1.9", "1.10"), Ordering::Less);
+        assert_eq!(crate::utils::compare_versions("2.3a", "2.3.1"), Ordering::Less);
+        assert_eq!(crate::utils::compare_versions("2.3pre1", "2.3"), Ordering::Less);
+        assert_eq!(
+            crate::utils::compare_versions("1.0.0-rc1", "1.0.0"),
+            Ordering::Less
+        );
+        assert_eq!(
+            crate::utils::compare_versions("3.0.12", "3.0.2"),
+            Ordering::Greater
+        );
+        assert_eq!(
+            crate::utils::compare_versions("1.1.1w", "1.1.1t"),
+            Ordering::Greater
+        );
+        assert_eq!(crate::utils::compare_versions("010", "9"), Ordering::Greater);
+    }
 }
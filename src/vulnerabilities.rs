@@ -0,0 +1,1087 @@
+// Offline matching of the packages against a local copy of a vulnerability database.
+// Two formats are supported:
+// * a directory of OSV advisories, like the ones in https://osv-vulnerabilities.storage.googleapis.com/
+//   See https://ossf.github.io/osv-schema/
+// * a NVD JSON 2.0 feed, like the ones in https://nvd.nist.gov/vuln/data-feeds
+// The packages are matched using their package URL for OSV, and their CPE for NVD.
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::str::FromStr;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::Deserialize;
+
+pub const OSV_SOURCE_NAME: &str = "OSV";
+pub const NVD_SOURCE_NAME: &str = "NVD";
+pub const NIXPKGS_SOURCE_NAME: &str = "nixpkgs";
+const NIXPKGS_SOURCE_URL: &str = "https://github.com/NixOS/nixpkgs";
+
+const JSON_EXTENSION: &str = "json";
+
+// The severities used by CycloneDX, from the most to the least severe.
+pub const SEVERITIES: &[&str] = &["critical", "high", "medium", "low", "info", "none", "unknown"];
+
+lazy_static! {
+    static ref CVE_ID_REGEX: Regex = Regex::new(r"CVE-[0-9]{4}-[0-9]{4,}").unwrap();
+    static ref URL_REGEX: Regex = Regex::new(r"https?://[^\s,;)]+").unwrap();
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(PartialEq)]
+pub struct Rating {
+    pub score: Option<f64>,
+    // One of the severities supported by CycloneDX.
+    pub severity: Option<String>,
+    // One of the scoring methods supported by CycloneDX, like CVSSv31.
+    pub method: Option<String>,
+    pub vector: Option<String>,
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(PartialEq)]
+pub struct Vulnerability {
+    pub id: String,
+    pub source_name: String,
+    pub source_url: String,
+    // Identifiers of the same vulnerability in other databases.
+    pub aliases: Vec<String>,
+    pub description: Option<String>,
+    pub detail: Option<String>,
+    pub ratings: Vec<Rating>,
+    // URLs of the advisories for the vulnerability.
+    pub advisories: Vec<String>,
+    // The derivation paths of the affected packages, with the version that was matched.
+    pub affected: BTreeMap<String, Option<String>>,
+}
+
+impl Vulnerability {
+    pub fn new(id: &str) -> Vulnerability {
+        let (source_name, source_url) = get_source(id);
+        Vulnerability {
+            id: id.to_string(),
+            source_name,
+            source_url,
+            aliases: vec![],
+            description: None,
+            detail: None,
+            ratings: vec![],
+            advisories: vec![],
+            affected: BTreeMap::default(),
+        }
+    }
+
+    // The most severe of the severities of the ratings.
+    pub fn get_severity(&self) -> String {
+        for severity in SEVERITIES {
+            if self
+                .ratings
+                .iter()
+                .any(|r| r.severity.as_deref() == Some(*severity))
+            {
+                return severity.to_string();
+            }
+        }
+        "unknown".to_string()
+    }
+}
+
+// Returns the name and the URL of the database in which a vulnerability is published.
+pub fn get_source(id: &str) -> (String, String) {
+    if id.starts_with("CVE-") {
+        return (
+            NVD_SOURCE_NAME.to_string(),
+            format!("https://nvd.nist.gov/vuln/detail/{}", id),
+        );
+    }
+    (
+        OSV_SOURCE_NAME.to_string(),
+        format!("https://osv.dev/vulnerability/{}", id),
+    )
+}
+
+// Returns the vulnerabilities from the knownVulnerabilities of the packages in nixpkgs.
+// An entry can mention several CVEs, or none at all, like the ones announcing the end
+// of life of a package. Packages marked as insecure without any entry also get a
+// vulnerability, so that they are not missed.
+pub fn get_known_vulnerabilities(package_graph: &crate::nix::PackageGraph) -> Vec<Vulnerability> {
+    let mut vulnerabilities: Vec<Vulnerability> = vec![];
+    for (derivation_path, package_node) in package_graph.nodes.iter() {
+        let package = match &package_node.package {
+            Some(p) => p,
+            None => continue,
+        };
+        if !package.meta.is_insecure() {
+            continue;
+        }
+        let name = package_node.get_name().unwrap_or(package.name.to_string());
+        let version = package_node.get_version();
+
+        let mut known_vulnerabilities = package.meta.get_known_vulnerabilities();
+        if known_vulnerabilities.len() == 0 {
+            known_vulnerabilities.push(format!("{} is marked as insecure in nixpkgs", name));
+        }
+        for (index, known_vulnerability) in known_vulnerabilities.iter().enumerate() {
+            let mut ids: Vec<String> = CVE_ID_REGEX
+                .find_iter(known_vulnerability)
+                .map(|m| m.as_str().to_string())
+                .collect();
+            ids.dedup();
+            if ids.len() == 0 {
+                ids.push(format!("NIXPKGS-{}-{}", name, index + 1));
+            }
+            for id in ids {
+                let mut vulnerability = Vulnerability::new(&id);
+                if !id.starts_with("CVE-") {
+                    vulnerability.source_name = NIXPKGS_SOURCE_NAME.to_string();
+                    vulnerability.source_url = NIXPKGS_SOURCE_URL.to_string();
+                }
+                vulnerability.description = Some(known_vulnerability.to_string());
+                vulnerability.advisories = URL_REGEX
+                    .find_iter(known_vulnerability)
+                    .map(|m| m.as_str().trim_end_matches('.').to_string())
+                    .collect();
+                vulnerability
+                    .affected
+                    .insert(derivation_path.to_string(), version.clone());
+                vulnerabilities.push(vulnerability);
+            }
+        }
+    }
+    merge_vulnerabilities(&[], &vulnerabilities)
+}
+
+// Merges two lists of vulnerabilities. The affected packages of the vulnerabilities with
+// the same id are combined, and the first occurrence is used for the other fields.
+pub fn merge_vulnerabilities(vulnerabilities: &[Vulnerability], other: &[Vulnerability]) -> Vec<Vulnerability> {
+    let mut merged_vulnerabilities: BTreeMap<String, Vulnerability> = BTreeMap::default();
+    for vulnerability in vulnerabilities.iter().chain(other.iter()) {
+        match merged_vulnerabilities.get_mut(&vulnerability.id) {
+            Some(v) => {
+                for (derivation_path, version) in &vulnerability.affected {
+                    v.affected
+                        .entry(derivation_path.to_string())
+                        .or_insert(version.clone());
+                }
+            }
+            None => {
+                merged_vulnerabilities.insert(vulnerability.id.to_string(), vulnerability.clone());
+            }
+        };
+    }
+    merged_vulnerabilities.into_values().collect()
+}
+
+// Returns the vulnerabilities found in the vulnerability databases, and the ones known by nixpkgs.
+pub fn get_all_vulnerabilities(
+    package_graph: &crate::nix::PackageGraph,
+    options: &crate::nix::DumpOptions,
+) -> Vec<Vulnerability> {
+    merge_vulnerabilities(
+        &options.vulnerabilities,
+        &get_known_vulnerabilities(package_graph),
+    )
+}
+
+// Converts the severities used by the databases to the ones used by CycloneDX.
+fn normalize_severity(severity: &str) -> Option<String> {
+    let severity = severity.to_lowercase();
+    if severity == "moderate" {
+        return Some("medium".to_string());
+    }
+    if SEVERITIES.contains(&severity.as_str()) {
+        return Some(severity);
+    }
+    None
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(PartialEq)]
+struct VersionBound {
+    version: String,
+    inclusive: bool,
+}
+
+// A range of affected versions. A missing bound means that the range is not bounded
+// on that side.
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(PartialEq)]
+#[derive(Default)]
+struct VersionRange {
+    start: Option<VersionBound>,
+    end: Option<VersionBound>,
+}
+
+impl VersionRange {
+    fn contains(&self, version: &str) -> bool {
+        if let Some(start) = &self.start {
+            match crate::utils::compare_versions(version, &start.version) {
+                std::cmp::Ordering::Less => return false,
+                std::cmp::Ordering::Equal if !start.inclusive => return false,
+                _ => {}
+            }
+        }
+        if let Some(end) = &self.end {
+            match crate::utils::compare_versions(version, &end.version) {
+                std::cmp::Ordering::Greater => return false,
+                std::cmp::Ordering::Equal if !end.inclusive => return false,
+                _ => {}
+            }
+        }
+        true
+    }
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(PartialEq)]
+struct AffectedVersions {
+    ranges: Vec<VersionRange>,
+    versions: Vec<String>,
+}
+
+impl AffectedVersions {
+    fn contains(&self, version: &str) -> bool {
+        if self
+            .versions
+            .iter()
+            .any(|v| crate::utils::compare_versions(version, v) == std::cmp::Ordering::Equal)
+        {
+            return true;
+        }
+        self.ranges.iter().any(|r| r.contains(version))
+    }
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(PartialEq)]
+#[derive(Hash)]
+#[derive(Eq)]
+enum PackageKey {
+    // The type, namespace and name of a package URL.
+    PackageURL(String, Option<String>, String),
+    // The vendor and product of a CPE.
+    Cpe(String, String),
+}
+
+#[derive(Debug)]
+#[derive(Default)]
+pub struct VulnerabilityDatabase {
+    vulnerabilities: Vec<Vulnerability>,
+    // The affected versions of each package, with the index of the vulnerability.
+    affected_packages: HashMap<PackageKey, Vec<(usize, AffectedVersions)>>,
+}
+
+impl VulnerabilityDatabase {
+    pub fn len(&self) -> usize {
+        self.vulnerabilities.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vulnerabilities.len() == 0
+    }
+
+    // Reads the vulnerabilities from a directory of OSV advisories, a single OSV advisory,
+    // or a NVD JSON feed, and adds them to the database.
+    pub fn read(&mut self, database_path: &str) -> Result<(), String> {
+        log::info!("Reading the vulnerabilities from {}", database_path);
+        let path = Path::new(database_path);
+        if path.is_dir() {
+            return self.read_osv_directory(path);
+        }
+
+        let content = std::fs::read(path).map_err(|e| format!("Could not read {}: {}", database_path, e))?;
+        let document: serde_json::Value = serde_json::from_slice(&content)
+            .map_err(|e| format!("Could not parse {}: {}", database_path, e))?;
+        if document.get("CVE_Items").is_some() {
+            return Err(format!(
+                "{} is a NVD 1.1 feed, which was retired by the NVD. Use a 2.0 feed instead.",
+                database_path
+            ));
+        }
+        if document.get("vulnerabilities").is_some() {
+            let feed: NvdFeed = serde_json::from_value(document)
+                .map_err(|e| format!("Could not parse the NVD feed {}: {}", database_path, e))?;
+            for item in feed.vulnerabilities {
+                self.add_nvd_vulnerability(item.cve);
+            }
+            return Ok(());
+        }
+        let entry: OsvEntry = serde_json::from_value(document)
+            .map_err(|e| format!("Could not parse the OSV advisory {}: {}", database_path, e))?;
+        self.add_osv_vulnerability(entry);
+        Ok(())
+    }
+
+    fn read_osv_directory(&mut self, directory_path: &Path) -> Result<(), String> {
+        let mut entries: Vec<std::path::PathBuf> = vec![];
+        for entry in std::fs::read_dir(directory_path).map_err(|e| e.to_string())? {
+            entries.push(entry.map_err(|e| e.to_string())?.path());
+        }
+        // Sorting the files makes the order of the vulnerabilities deterministic.
+        entries.sort();
+
+        for entry_path in entries {
+            if entry_path.is_dir() {
+                self.read_osv_directory(&entry_path)?;
+                continue;
+            }
+            if entry_path.extension().and_then(|e| e.to_str()) != Some(JSON_EXTENSION) {
+                continue;
+            }
+            let content = std::fs::read(&entry_path).map_err(|e| e.to_string())?;
+            let entry: OsvEntry = match serde_json::from_slice(&content) {
+                Ok(e) => e,
+                Err(e) => {
+                    log::warn!("Could not parse the OSV advisory {}: {}", entry_path.display(), e);
+                    continue;
+                }
+            };
+            self.add_osv_vulnerability(entry);
+        }
+        Ok(())
+    }
+
+    fn add_osv_vulnerability(&mut self, entry: OsvEntry) {
+        if entry.withdrawn.is_some() {
+            return;
+        }
+        let mut vulnerability = Vulnerability::new(&entry.id);
+        vulnerability.source_name = OSV_SOURCE_NAME.to_string();
+        vulnerability.source_url = format!("https://osv.dev/vulnerability/{}", entry.id);
+        vulnerability.aliases = entry.aliases;
+        vulnerability.description = entry.summary;
+        vulnerability.detail = entry.details;
+
+        let severity = entry
+            .database_specific
+            .and_then(|d| d.severity)
+            .and_then(|s| normalize_severity(&s));
+        for osv_severity in entry.severity {
+            let method = match osv_severity.severity_type.as_str() {
+                "CVSS_V2" => "CVSSv2",
+                "CVSS_V3" if osv_severity.score.starts_with("CVSS:3.1/") => "CVSSv31",
+                "CVSS_V3" => "CVSSv3",
+                "CVSS_V4" => "CVSSv4",
+                _ => "other",
+            };
+            vulnerability.ratings.push(Rating {
+                score: None,
+                severity: severity.clone(),
+                method: Some(method.to_string()),
+                vector: Some(osv_severity.score),
+            });
+        }
+        if vulnerability.ratings.len() == 0 && severity.is_some() {
+            vulnerability.ratings.push(Rating {
+                score: None,
+                severity,
+                method: None,
+                vector: None,
+            });
+        }
+        for reference in entry.references {
+            if reference.reference_type == "ADVISORY" {
+                vulnerability.advisories.push(reference.url);
+            }
+        }
+
+        let vulnerability_index = self.vulnerabilities.len();
+        self.vulnerabilities.push(vulnerability);
+
+        for affected in entry.affected {
+            let package_key = match affected.package.as_ref().and_then(get_osv_package_key) {
+                Some(k) => k,
+                None => continue,
+            };
+            let mut affected_versions = AffectedVersions {
+                ranges: vec![],
+                versions: affected.versions,
+            };
+            for range in affected.ranges {
+                // We cannot compare the git revisions with the versions of the packages.
+                if range.range_type == "GIT" {
+                    continue;
+                }
+                affected_versions
+                    .ranges
+                    .append(&mut get_osv_version_ranges(&range.events));
+            }
+            self.affected_packages
+                .entry(package_key)
+                .or_default()
+                .push((vulnerability_index, affected_versions));
+        }
+    }
+
+    fn add_nvd_vulnerability(&mut self, cve: NvdCve) {
+        if cve.vuln_status.as_deref() == Some("Rejected") {
+            return;
+        }
+        let mut vulnerability = Vulnerability::new(&cve.id);
+        vulnerability.source_name = NVD_SOURCE_NAME.to_string();
+        vulnerability.source_url = format!("https://nvd.nist.gov/vuln/detail/{}", cve.id);
+        vulnerability.description = cve
+            .descriptions
+            .into_iter()
+            .find(|d| d.lang == "en")
+            .map(|d| d.value);
+
+        let metrics = [
+            ("CVSSv31", cve.metrics.cvss_metric_v31),
+            ("CVSSv3", cve.metrics.cvss_metric_v30),
+            ("CVSSv2", cve.metrics.cvss_metric_v2),
+        ];
+        for (method, cvss_metrics) in metrics {
+            for cvss_metric in cvss_metrics {
+                // The severity is part of the CVSS data starting with CVSS 3.
+                let severity = cvss_metric
+                    .cvss_data
+                    .base_severity
+                    .or(cvss_metric.base_severity)
+                    .and_then(|s| normalize_severity(&s));
+                vulnerability.ratings.push(Rating {
+                    score: Some(cvss_metric.cvss_data.base_score),
+                    severity,
+                    method: Some(method.to_string()),
+                    vector: Some(cvss_metric.cvss_data.vector_string),
+                });
+            }
+        }
+        for reference in cve.references {
+            if reference.tags.iter().any(|t| t.ends_with("Advisory")) {
+                vulnerability.advisories.push(reference.url);
+            }
+        }
+
+        let vulnerability_index = self.vulnerabilities.len();
+        self.vulnerabilities.push(vulnerability);
+
+        // The configurations can combine the CPEs with AND, for example to say that an
+        // application is only vulnerable on a given OS. We consider every vulnerable CPE
+        // on its own, which can report more vulnerabilities than there really are.
+        for configuration in cve.configurations {
+            for node in configuration.nodes {
+                for cpe_match in node.cpe_match {
+                    if !cpe_match.vulnerable {
+                        continue;
+                    }
+                    let cpe_fields = split_cpe(&cpe_match.criteria);
+                    if cpe_fields.len() < 6 {
+                        continue;
+                    }
+                    let package_key = PackageKey::Cpe(cpe_fields[3].to_string(), cpe_fields[4].to_string());
+                    let affected_versions = match cpe_fields[5].as_str() {
+                        "*" => AffectedVersions {
+                            ranges: vec![get_nvd_version_range(&cpe_match)],
+                            versions: vec![],
+                        },
+                        // Not applicable.
+                        "-" => continue,
+                        version => AffectedVersions {
+                            ranges: vec![],
+                            versions: vec![version.replace('\\', "")],
+                        },
+                    };
+                    self.affected_packages
+                        .entry(package_key)
+                        .or_default()
+                        .push((vulnerability_index, affected_versions));
+                }
+            }
+        }
+    }
+
+    // Returns the vulnerabilities affecting the packages of the graph, sorted by id.
+    pub fn get_vulnerabilities(
+        &self,
+        package_graph: &crate::nix::PackageGraph,
+        options: &crate::nix::DumpOptions,
+    ) -> Vec<Vulnerability> {
+        let mut affected: BTreeMap<usize, BTreeMap<String, Option<String>>> = BTreeMap::default();
+        for (derivation_path, package_node) in package_graph.nodes.iter() {
+            if package_node.is_inline_script() || package_node.get_name().is_none() {
+                continue;
+            }
+            let version = match package_node
+                .get_version()
+                .or(package_node.main_derivation.get_version())
+            {
+                Some(v) => v,
+                None => continue,
+            };
+
+            let mut package_keys: Vec<PackageKey> = vec![];
+            let purl = package_node.get_purl();
+            package_keys.push(PackageKey::PackageURL(
+                purl.package_type,
+                purl.namespace,
+                purl.name,
+            ));
+            if let Some(cpe) = package_node.get_cpe(options) {
+                let cpe_fields = split_cpe(&cpe);
+                package_keys.push(PackageKey::Cpe(
+                    cpe_fields[3].to_string(),
+                    cpe_fields[4].to_string(),
+                ));
+            }
+
+            for package_key in package_keys {
+                let affected_packages = match self.affected_packages.get(&package_key) {
+                    Some(a) => a,
+                    None => continue,
+                };
+                for (vulnerability_index, affected_versions) in affected_packages {
+                    if affected_versions.contains(&version) {
+                        affected
+                            .entry(*vulnerability_index)
+                            .or_default()
+                            .insert(derivation_path.to_string(), Some(version.to_string()));
+                    }
+                }
+            }
+        }
+
+        let mut vulnerabilities: Vec<Vulnerability> = vec![];
+        for (vulnerability_index, affected_packages) in affected {
+            let mut vulnerability = self.vulnerabilities[vulnerability_index].clone();
+            vulnerability.affected = affected_packages;
+            vulnerabilities.push(vulnerability);
+        }
+        vulnerabilities.sort_by(|v1, v2| v1.id.cmp(&v2.id));
+        vulnerabilities
+    }
+}
+
+fn get_osv_package_key(package: &OsvPackage) -> Option<PackageKey> {
+    if let Some(purl) = &package.purl {
+        if let Ok(purl) = crate::purl::PackageURL::from_str(purl) {
+            return Some(PackageKey::PackageURL(
+                purl.package_type,
+                purl.namespace,
+                purl.name,
+            ));
+        }
+    }
+
+    let package_type = match package.ecosystem.as_str() {
+        "crates.io" => "cargo",
+        "npm" => "npm",
+        "PyPI" => "pypi",
+        "Maven" => "maven",
+        "RubyGems" => "gem",
+        "Go" => "golang",
+        "NuGet" => "nuget",
+        "Hex" => "hex",
+        "Pub" => "pub",
+        "Hackage" => "hackage",
+        "Packagist" => "composer",
+        _ => return None,
+    };
+    let mut purl = crate::purl::PackageURL::default();
+    purl.package_type = package_type.to_string();
+    let separator = if package_type == "maven" { ':' } else { '/' };
+    match package.name.rsplit_once(separator) {
+        Some((namespace, name)) => {
+            purl.namespace = Some(namespace.to_string());
+            purl.name = name.to_string();
+        }
+        None => purl.name = package.name.to_string(),
+    };
+    purl.normalize();
+    Some(PackageKey::PackageURL(
+        purl.package_type,
+        purl.namespace,
+        purl.name,
+    ))
+}
+
+// Converts the events of an OSV range to version ranges. Each introduced event starts
+// a range, which is closed by the next fixed or last_affected event.
+fn get_osv_version_ranges(events: &[OsvEvent]) -> Vec<VersionRange> {
+    let mut ranges: Vec<VersionRange> = vec![];
+    let mut current_range: Option<VersionRange> = None;
+    for event in events {
+        if let Some(introduced) = &event.introduced {
+            let mut range = VersionRange::default();
+            // 0 is used for ranges which start at the first version.
+            if introduced != "0" {
+                range.start = Some(VersionBound {
+                    version: introduced.to_string(),
+                    inclusive: true,
+                });
+            }
+            current_range = Some(range);
+            continue;
+        }
+        let end = if let Some(fixed) = &event.fixed {
+            VersionBound {
+                version: fixed.to_string(),
+                inclusive: false,
+            }
+        } else if let Some(last_affected) = &event.last_affected {
+            VersionBound {
+                version: last_affected.to_string(),
+                inclusive: true,
+            }
+        } else {
+            continue;
+        };
+        if let Some(mut range) = current_range.take() {
+            range.end = Some(end);
+            ranges.push(range);
+        }
+    }
+    if let Some(range) = current_range {
+        ranges.push(range);
+    }
+    ranges
+}
+
+fn get_nvd_version_range(cpe_match: &NvdCpeMatch) -> VersionRange {
+    let mut range = VersionRange::default();
+    if let Some(version) = &cpe_match.version_start_including {
+        range.start = Some(VersionBound {
+            version: version.to_string(),
+            inclusive: true,
+        });
+    } else if let Some(version) = &cpe_match.version_start_excluding {
+        range.start = Some(VersionBound {
+            version: version.to_string(),
+            inclusive: false,
+        });
+    }
+    if let Some(version) = &cpe_match.version_end_including {
+        range.end = Some(VersionBound {
+            version: version.to_string(),
+            inclusive: true,
+        });
+    } else if let Some(version) = &cpe_match.version_end_excluding {
+        range.end = Some(VersionBound {
+            version: version.to_string(),
+            inclusive: false,
+        });
+    }
+    range
+}
+
+// Splits a CPE on the `:` separators, ignoring the escaped ones.
+fn split_cpe(cpe: &str) -> Vec<String> {
+    let mut fields: Vec<String> = vec![];
+    let mut current_field = "".to_string();
+    let mut is_escaped = false;
+    for c in cpe.chars() {
+        if c == ':' && !is_escaped {
+            fields.push(current_field);
+            current_field = "".to_string();
+            continue;
+        }
+        is_escaped = c == '\\' && !is_escaped;
+        current_field.push(c);
+    }
+    fields.push(current_field);
+    fields
+}
+
+#[derive(Deserialize)]
+struct OsvEntry {
+    id: String,
+    withdrawn: Option<String>,
+    #[serde(default)]
+    aliases: Vec<String>,
+    summary: Option<String>,
+    details: Option<String>,
+    #[serde(default)]
+    severity: Vec<OsvSeverity>,
+    #[serde(default)]
+    affected: Vec<OsvAffected>,
+    #[serde(default)]
+    references: Vec<OsvReference>,
+    database_specific: Option<OsvDatabaseSpecific>,
+}
+
+#[derive(Deserialize)]
+struct OsvSeverity {
+    #[serde(rename = "type")]
+    severity_type: String,
+    score: String,
+}
+
+#[derive(Deserialize)]
+struct OsvDatabaseSpecific {
+    severity: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct OsvAffected {
+    package: Option<OsvPackage>,
+    #[serde(default)]
+    ranges: Vec<OsvRange>,
+    #[serde(default)]
+    versions: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct OsvPackage {
+    ecosystem: String,
+    name: String,
+    purl: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct OsvRange {
+    #[serde(rename = "type")]
+    range_type: String,
+    #[serde(default)]
+    events: Vec<OsvEvent>,
+}
+
+#[derive(Deserialize)]
+struct OsvEvent {
+    introduced: Option<String>,
+    fixed: Option<String>,
+    last_affected: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct OsvReference {
+    #[serde(rename = "type")]
+    reference_type: String,
+    url: String,
+}
+
+#[derive(Deserialize)]
+struct NvdFeed {
+    vulnerabilities: Vec<NvdItem>,
+}
+
+#[derive(Deserialize)]
+struct NvdItem {
+    cve: NvdCve,
+}
+
+#[derive(Deserialize)]
+struct NvdCve {
+    id: String,
+    #[serde(rename = "vulnStatus")]
+    vuln_status: Option<String>,
+    #[serde(default)]
+    descriptions: Vec<NvdDescription>,
+    #[serde(default)]
+    metrics: NvdMetrics,
+    #[serde(default)]
+    configurations: Vec<NvdConfiguration>,
+    #[serde(default)]
+    references: Vec<NvdReference>,
+}
+
+#[derive(Deserialize)]
+struct NvdDescription {
+    lang: String,
+    value: String,
+}
+
+#[derive(Deserialize)]
+#[derive(Default)]
+struct NvdMetrics {
+    #[serde(rename = "cvssMetricV31", default)]
+    cvss_metric_v31: Vec<NvdCvssMetric>,
+    #[serde(rename = "cvssMetricV30", default)]
+    cvss_metric_v30: Vec<NvdCvssMetric>,
+    #[serde(rename = "cvssMetricV2", default)]
+    cvss_metric_v2: Vec<NvdCvssMetric>,
+}
+
+#[derive(Deserialize)]
+struct NvdCvssMetric {
+    #[serde(rename = "cvssData")]
+    cvss_data: NvdCvssData,
+    #[serde(rename = "baseSeverity")]
+    base_severity: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct NvdCvssData {
+    #[serde(rename = "baseScore")]
+    base_score: f64,
+    #[serde(rename = "baseSeverity")]
+    base_severity: Option<String>,
+    #[serde(rename = "vectorString")]
+    vector_string: String,
+}
+
+#[derive(Deserialize)]
+struct NvdConfiguration {
+    #[serde(default)]
+    nodes: Vec<NvdNode>,
+}
+
+#[derive(Deserialize)]
+struct NvdNode {
+    #[serde(rename = "cpeMatch", default)]
+    cpe_match: Vec<NvdCpeMatch>,
+}
+
+#[derive(Deserialize)]
+struct NvdCpeMatch {
+    vulnerable: bool,
+    criteria: String,
+    #[serde(rename = "versionStartIncluding")]
+    version_start_including: Option<String>,
+    #[serde(rename = "versionStartExcluding")]
+    version_start_excluding: Option<String>,
+    #[serde(rename = "versionEndIncluding")]
+    version_end_including: Option<String>,
+    #[serde(rename = "versionEndExcluding")]
+    version_end_excluding: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct NvdReference {
+    url: String,
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A NVD feed with a vulnerability affecting glibc up to 2.38, and one affecting only 2.37.
+    const TEST_NVD_FEED: &str = r###"
+{
+  "resultsPerPage": 2,
+  "format": "NVD_CVE",
+  "version": "2.0",
+  "vulnerabilities": [
+    {
+      "cve": {
+        "id": "CVE-2023-4911",
+        "vulnStatus": "Analyzed",
+        "descriptions": [{ "lang": "en", "value": "A buffer overflow was discovered in the dynamic loader." }],
+        "metrics": {
+          "cvssMetricV31": [{
+            "cvssData": {
+              "version": "3.1",
+              "vectorString": "CVSS:3.1/AV:L/AC:L/PR:L/UI:N/S:U/C:H/I:H/A:H",
+              "baseScore": 7.8,
+              "baseSeverity": "HIGH"
+            }
+          }]
+        },
+        "configurations": [{
+          "nodes": [{
+            "operator": "OR",
+            "cpeMatch": [{
+              "vulnerable": true,
+              "criteria": "cpe:2.3:a:gnu:glibc:*:*:*:*:*:*:*:*",
+              "versionStartIncluding": "2.34",
+              "versionEndIncluding": "2.38"
+            }]
+          }]
+        }],
+        "references": [{ "url": "https://www.openwall.com/lists/oss-security/2023/10/03/2", "tags": ["Mailing List", "Third Party Advisory"] }]
+      }
+    },
+    {
+      "cve": {
+        "id": "CVE-2023-0001",
+        "vulnStatus": "Analyzed",
+        "descriptions": [{ "lang": "en", "value": "Only affects 2.37." }],
+        "configurations": [{
+          "nodes": [{
+            "operator": "OR",
+            "cpeMatch": [{ "vulnerable": true, "criteria": "cpe:2.3:a:gnu:glibc:2.37:*:*:*:*:*:*:*" }]
+          }]
+        }]
+      }
+    }
+  ]
+}
+"###;
+
+    const TEST_OSV_ADVISORY: &str = r###"
+    {
+      "id": "RUSTSEC-2023-0001",
+      "aliases": ["CVE-2023-22466"],
+      "summary": "Reading uninitialized memory",
+      "affected": [{
+        "package": { "ecosystem": "crates.io", "name": "tokio", "purl": "pkg:cargo/tokio" },
+        "ranges": [{
+          "type": "SEMVER",
+          "events": [{ "introduced": "0" }, { "fixed": "1.18.4" }, { "introduced": "1.19.0" }, { "fixed": "1.20.3" }]
+        }]
+      }],
+      "database_specific": { "severity": "MODERATE" },
+      "references": [{ "type": "ADVISORY", "url": "https://rustsec.org/advisories/RUSTSEC-2023-0001.html" }]
+    }
+    "###;
+
+    #[test]
+    pub fn test_osv_version_ranges() {
+        let entry: OsvEntry = serde_json::from_str(TEST_OSV_ADVISORY).unwrap();
+        let ranges = get_osv_version_ranges(&entry.affected[0].ranges[0].events);
+        assert_eq!(ranges.len(), 2);
+        let affected_versions = AffectedVersions {
+            ranges,
+            versions: vec![],
+        };
+        assert!(affected_versions.contains("1.0.0"));
+        assert!(!affected_versions.contains("1.18.4"));
+        assert!(affected_versions.contains("1.19.5"));
+        assert!(!affected_versions.contains("1.20.3"));
+        assert!(!affected_versions.contains("1.21.0"));
+    }
+
+    #[test]
+    pub fn test_osv_package_key() {
+        let package = OsvPackage {
+            ecosystem: "Maven".to_string(),
+            name: "org.apache.logging.log4j:log4j-core".to_string(),
+            purl: None,
+        };
+        assert_eq!(
+            get_osv_package_key(&package),
+            Some(PackageKey::PackageURL(
+                "maven".to_string(),
+                Some("org.apache.logging.log4j".to_string()),
+                "log4j-core".to_string()
+            ))
+        );
+        let package = OsvPackage {
+            ecosystem: "PyPI".to_string(),
+            name: "Django_Rest".to_string(),
+            purl: None,
+        };
+        assert_eq!(
+            get_osv_package_key(&package),
+            Some(PackageKey::PackageURL(
+                "pypi".to_string(),
+                None,
+                "django-rest".to_string()
+            ))
+        );
+        let package = OsvPackage {
+            ecosystem: "Debian".to_string(),
+            name: "glibc".to_string(),
+            purl: None,
+        };
+        assert_eq!(get_osv_package_key(&package), None);
+    }
+
+    #[test]
+    pub fn test_known_vulnerabilities() {
+        let mut package_graph = crate::nix::get_test_package_graph();
+        let glibc_path = "/nix/store/dddddddddddddddddddddddddddddddd-glibc-2.38.drv";
+        crate::nix::set_test_package(
+            &mut package_graph,
+            glibc_path,
+            r###"{
+              "name": "glibc-2.38",
+              "pname": "glibc",
+              "version": "2.38",
+              "system": "x86_64-linux",
+              "outputName": "out",
+              "meta": {
+                "insecure": true,
+                "knownVulnerabilities": [
+                  "CVE-2023-4911 and CVE-2023-4806, see https://www.openwall.com/lists/oss-security/2023/10/03/2.",
+                  "glibc 2.38 is end of life"
+                ]
+              }
+            }"###,
+        );
+
+        let vulnerabilities = get_known_vulnerabilities(&package_graph);
+        let ids: Vec<&str> = vulnerabilities.iter().map(|v| v.id.as_str()).collect();
+        assert_eq!(ids, vec!["CVE-2023-4806", "CVE-2023-4911", "NIXPKGS-glibc-2"]);
+        assert_eq!(vulnerabilities[1].source_name, NVD_SOURCE_NAME);
+        assert_eq!(
+            vulnerabilities[1].advisories,
+            vec!["https://www.openwall.com/lists/oss-security/2023/10/03/2".to_string()]
+        );
+        assert_eq!(vulnerabilities[2].source_name, NIXPKGS_SOURCE_NAME);
+        assert_eq!(
+            vulnerabilities[2].affected.get(glibc_path),
+            Some(&Some("2.38".to_string()))
+        );
+
+        // The vulnerabilities found in a database are merged with the known ones.
+        let mut options = crate::nix::DumpOptions::default();
+        let mut vulnerability = Vulnerability::new("CVE-2023-4911");
+        vulnerability
+            .affected
+            .insert("/nix/store/other.drv".to_string(), None);
+        options.vulnerabilities = vec![vulnerability];
+        let vulnerabilities = get_all_vulnerabilities(&package_graph, &options);
+        assert_eq!(vulnerabilities.len(), 3);
+        assert_eq!(vulnerabilities[1].affected.len(), 2);
+    }
+
+    #[test]
+    pub fn test_split_cpe() {
+        assert_eq!(
+            split_cpe("cpe:2.3:a:gnu:glibc:2.38:*:*:*:*:*:*:*")[3..6],
+            ["gnu", "glibc", "2.38"]
+        );
+        assert_eq!(
+            split_cpe("cpe:2.3:a:vendor:product:1.0\\:2:*:*:*:*:*:*:*")[5],
+            "1.0\\:2"
+        );
+    }
+
+    #[test]
+    pub fn test_match_nvd_feed() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let feed_path = temp_dir.path().join("nvd-feed.json");
+        std::fs::write(&feed_path, TEST_NVD_FEED).unwrap();
+        let mut database = VulnerabilityDatabase::default();
+        database.read(feed_path.to_str().unwrap()).unwrap();
+        assert_eq!(database.len(), 2);
+
+        let package_graph = crate::nix::get_test_package_graph();
+        let vulnerabilities = database.get_vulnerabilities(&package_graph, &crate::nix::DumpOptions::default());
+        assert_eq!(vulnerabilities.len(), 1);
+        let vulnerability = &vulnerabilities[0];
+        assert_eq!(vulnerability.id, "CVE-2023-4911");
+        assert_eq!(vulnerability.get_severity(), "high");
+        assert_eq!(vulnerability.ratings[0].score, Some(7.8));
+        assert_eq!(
+            vulnerability.advisories,
+            vec!["https://www.openwall.com/lists/oss-security/2023/10/03/2".to_string()]
+        );
+        assert_eq!(
+            vulnerability.affected.keys().collect::<Vec<&String>>(),
+            vec!["/nix/store/dddddddddddddddddddddddddddddddd-glibc-2.38.drv"]
+        );
+    }
+
+    #[test]
+    pub fn test_read_osv_directory() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let directory_path = temp_dir.path().join("osv-directory");
+        std::fs::create_dir_all(directory_path.join("crates.io")).unwrap();
+        std::fs::write(
+            directory_path.join("crates.io").join("RUSTSEC-2023-0001.json"),
+            TEST_OSV_ADVISORY,
+        )
+        .unwrap();
+        std::fs::write(directory_path.join("README.md"), "Not an advisory").unwrap();
+
+        let mut database = VulnerabilityDatabase::default();
+        database.read(directory_path.to_str().unwrap()).unwrap();
+
+        assert_eq!(database.len(), 1);
+        let vulnerability = &database.vulnerabilities[0];
+        assert_eq!(vulnerability.source_name, OSV_SOURCE_NAME);
+        assert_eq!(vulnerability.aliases, vec!["CVE-2023-22466".to_string()]);
+        assert_eq!(vulnerability.get_severity(), "medium");
+        let key = PackageKey::PackageURL("cargo".to_string(), None, "tokio".to_string());
+        assert_eq!(database.affected_packages.get(&key).unwrap().len(), 1);
+    }
+}
@@ -3,8 +3,6 @@ use std::fs::File;
 use std::io::{BufReader, Read};
 use std::path::PathBuf;
 
-use nix2sbom;
-
 #[rstest]
 fn for_each_file(#[files("tests/fixtures/*")] path: PathBuf) {
     if path.display().to_string().contains("DO_NOT_DELETE.txt") {
@@ -34,14 +32,28 @@ fn for_each_file(#[files("tests/fixtures/*")] path: PathBuf) {
     buf_reader.read_to_string(&mut contents).unwrap();
     let package_graph: nix2sbom::nix::PackageGraph = serde_json::from_str(&contents).unwrap();
 
-    // let file = File::open(sbom_file_path).unwrap();
-    // let mut buf_reader = BufReader::new(file);
-    // let mut sbom = String::new();
-    // buf_reader.read_to_string(&mut sbom).unwrap();
-
-    let expected_package_graph = nix2sbom::nix::get_package_graph(&derivations, &packages);
+    let expected_package_graph = nix2sbom::nix::get_package_graph_next(&derivations, &packages);
 
     assert_eq!(expected_package_graph, package_graph);
 
+    // Fixtures created before the SBOM output was reproducible do not have a stored SBOM.
+    if let Ok(file) = File::open(sbom_file_path) {
+        let mut buf_reader = BufReader::new(file);
+        let mut sbom = String::new();
+        buf_reader.read_to_string(&mut sbom).unwrap();
+
+        // Same options as the ones used in create-integration-test.
+        let mut dump_options = nix2sbom::nix::DumpOptions::default();
+        dump_options.timestamp = nix2sbom::sbom::parse_timestamp("0");
+        let expected_sbom = nix2sbom::sbom::Format::CycloneDX
+            .dump(
+                &nix2sbom::sbom::SerializationFormat::JSON,
+                &package_graph,
+                &dump_options,
+            )
+            .unwrap();
+        assert_eq!(expected_sbom, sbom);
+    }
+
     // TODO overwrite the stored sbom if an env var was set
 }